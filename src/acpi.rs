@@ -0,0 +1,346 @@
+// ACPI table discovery. Finds the RSDP in the BIOS areas, follows it to the
+// RSDT or XSDT and reads the MADT, which lists each processor's local APIC
+// and the I/O APICs, and the FADT's reset register. Tables are read through
+// the bootloader's mapping of all physical memory.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::VirtAddr;
+
+use crate::power;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// Revision 0 RSDPs end after the RSDT address; later ones add the XSDT.
+const RSDP_V1_LEN: usize = 20;
+const RSDP_V2_LEN: usize = 36;
+// The RSDP sits on a 16-byte boundary in the first KiB of the EBDA, whose
+// real-mode segment is in the BIOS data area, or in the BIOS ROM area.
+const EBDA_SEGMENT_PTR: u64 = 0x40E;
+const EBDA_SCAN_LEN: u64 = 1024;
+const BIOS_AREA: Range<u64> = 0xE0000..0x100000;
+
+const SDT_HEADER_LEN: usize = 36;
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+// The local APIC address and flags come between the header and the entries.
+const MADT_ENTRIES_OFFSET: usize = 44;
+
+// MADT entry types.
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
+const FADT_SIGNATURE: &[u8; 4] = b"FACP";
+const FADT_FLAGS_OFFSET: usize = 112;
+const FADT_RESET_REG_SUPPORTED: u32 = 1 << 10;
+// A generic address structure, then the value to write to it. ACPI 1.0
+// FADTs end before these.
+const FADT_RESET_REG_OFFSET: usize = 116;
+const FADT_RESET_VALUE_OFFSET: usize = 128;
+const GAS_SYSTEM_IO: u8 = 1;
+
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+// Disabled but can be brought online later.
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    NoRsdp,
+    BadTable([u8; 4]),
+    NoMadt,
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AcpiError::NoRsdp => f.write_str("no RSDP found"),
+            AcpiError::BadTable(signature) => {
+                write!(f, "invalid {} table", core::str::from_utf8(signature).unwrap_or("ACPI"))
+            }
+            AcpiError::NoMadt => f.write_str("no MADT"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApic {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    // False for processors that are present but not yet online.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    // The first global system interrupt this I/O APIC handles.
+    pub gsi_base: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u32,
+    pub local_apics: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+}
+
+static MADT: Mutex<Option<Madt>> = Mutex::new(None);
+
+// Where physical memory is mapped.
+struct PhysMemory {
+    offset: VirtAddr,
+}
+
+impl PhysMemory {
+    // Physical memory is all mapped, and tables are only read, so any range
+    // of it can be borrowed.
+    fn bytes(&self, phys: u64, len: usize) -> &'static [u8] {
+        let ptr: *const u8 = (self.offset + phys).as_ptr();
+        unsafe { core::slice::from_raw_parts(ptr, len) }
+    }
+
+    // Returns the whole table at `phys` if its signature is `wanted`, checking
+    // its length and checksum.
+    fn table(&self, phys: u64, wanted: &[u8; 4]) -> Result<Option<&'static [u8]>, AcpiError> {
+        let header = self.bytes(phys, SDT_HEADER_LEN);
+        if &header[0..4] != wanted {
+            return Ok(None);
+        }
+        let len = read_u32(header, 4) as usize;
+        if len < SDT_HEADER_LEN {
+            return Err(AcpiError::BadTable(*wanted));
+        }
+        let table = self.bytes(phys, len);
+        if !checksum_ok(table) {
+            return Err(AcpiError::BadTable(*wanted));
+        }
+        Ok(Some(table))
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(bytes, offset)) | u32::from(read_u16(bytes, offset + 2)) << 16
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(bytes, offset)) | u64::from(read_u32(bytes, offset + 4)) << 32
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn scan_for_rsdp(memory: &PhysMemory, area: Range<u64>) -> Option<&'static [u8]> {
+    for phys in area.step_by(16) {
+        let candidate = memory.bytes(phys, RSDP_V1_LEN);
+        if &candidate[0..8] != RSDP_SIGNATURE || !checksum_ok(candidate) {
+            continue;
+        }
+        if candidate[15] < 2 {
+            return Some(candidate);
+        }
+        // Later revisions checksum the extended fields separately.
+        let extended = memory.bytes(phys, RSDP_V2_LEN);
+        if checksum_ok(extended) {
+            return Some(extended);
+        }
+    }
+    None
+}
+
+fn find_rsdp(memory: &PhysMemory) -> Option<&'static [u8]> {
+    let ebda_segment = read_u16(memory.bytes(EBDA_SEGMENT_PTR, 2), 0);
+    let ebda = u64::from(ebda_segment) << 4;
+    if ebda != 0 {
+        if let Some(rsdp) = scan_for_rsdp(memory, ebda..ebda + EBDA_SCAN_LEN) {
+            return Some(rsdp);
+        }
+    }
+    scan_for_rsdp(memory, BIOS_AREA)
+}
+
+// The physical address of the table with signature `wanted`, from the XSDT
+// if there is one and otherwise the RSDT.
+fn find_table(memory: &PhysMemory, rsdp: &[u8], wanted: &[u8; 4]) -> Result<Option<u64>, AcpiError> {
+    let xsdt = if rsdp.len() >= RSDP_V2_LEN { read_u64(rsdp, 24) } else { 0 };
+    let (root, signature, entry_len) = if xsdt != 0 {
+        (memory.table(xsdt, b"XSDT")?, b"XSDT", 8)
+    } else {
+        let rsdt = u64::from(read_u32(rsdp, 16));
+        (memory.table(rsdt, b"RSDT")?, b"RSDT", 4)
+    };
+    let root = root.ok_or(AcpiError::BadTable(*signature))?;
+    for entry in root[SDT_HEADER_LEN..].chunks_exact(entry_len) {
+        let phys = if entry_len == 8 { read_u64(entry, 0) } else { u64::from(read_u32(entry, 0)) };
+        if memory.bytes(phys, 4) == wanted {
+            return Ok(Some(phys));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_madt(table: &[u8]) -> Madt {
+    let mut madt = Madt {
+        local_apic_address: read_u32(table, SDT_HEADER_LEN),
+        local_apics: Vec::new(),
+        io_apics: Vec::new(),
+    };
+    let mut offset = MADT_ENTRIES_OFFSET;
+    while offset + 2 <= table.len() {
+        let (kind, len) = (table[offset], usize::from(table[offset + 1]));
+        // A zero length would loop forever; a long one would run off the end.
+        if len < 2 || offset + len > table.len() {
+            break;
+        }
+        let entry = &table[offset..offset + len];
+        match kind {
+            MADT_LOCAL_APIC if len >= 8 => {
+                let flags = read_u32(entry, 4);
+                if flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0 {
+                    madt.local_apics.push(LocalApic {
+                        processor_id: u32::from(entry[2]),
+                        apic_id: u32::from(entry[3]),
+                        enabled: flags & LOCAL_APIC_ENABLED != 0,
+                    });
+                }
+            }
+            MADT_LOCAL_X2APIC if len >= 16 => {
+                let flags = read_u32(entry, 8);
+                if flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0 {
+                    madt.local_apics.push(LocalApic {
+                        processor_id: read_u32(entry, 12),
+                        apic_id: read_u32(entry, 4),
+                        enabled: flags & LOCAL_APIC_ENABLED != 0,
+                    });
+                }
+            }
+            MADT_IO_APIC if len >= 12 => madt.io_apics.push(IoApic {
+                id: entry[2],
+                address: read_u32(entry, 4),
+                gsi_base: read_u32(entry, 8),
+            }),
+            _ => {}
+        }
+        offset += len;
+    }
+    madt
+}
+
+// The I/O port and value of the FADT reset register, if the FADT has one
+// and it is in I/O space.
+fn parse_fadt_reset(table: &[u8]) -> Option<(u16, u8)> {
+    if table.len() <= FADT_RESET_VALUE_OFFSET {
+        return None;
+    }
+    if read_u32(table, FADT_FLAGS_OFFSET) & FADT_RESET_REG_SUPPORTED == 0 {
+        return None;
+    }
+    let register = &table[FADT_RESET_REG_OFFSET..FADT_RESET_VALUE_OFFSET];
+    if register[0] != GAS_SYSTEM_IO {
+        return None;
+    }
+    let port = read_u64(register, 4);
+    if port == 0 || port > u64::from(u16::MAX) {
+        return None;
+    }
+    Some((port as u16, table[FADT_RESET_VALUE_OFFSET]))
+}
+
+// Hands the FADT reset register, if there is one, to power::reboot().
+fn init_reset(memory: &PhysMemory, rsdp: &[u8]) -> Result<(), AcpiError> {
+    if let Some(phys) = find_table(memory, rsdp, FADT_SIGNATURE)? {
+        let table = memory.table(phys, FADT_SIGNATURE)?;
+        if let Some((port, value)) = table.and_then(parse_fadt_reset) {
+            power::set_acpi_reset(port, value);
+        }
+    }
+    Ok(())
+}
+
+// Finds and parses the MADT and the FADT reset register.
+// `physical_memory_offset` is where the bootloader mapped physical memory.
+pub fn init(physical_memory_offset: VirtAddr) -> Result<(), AcpiError> {
+    let memory = PhysMemory {
+        offset: physical_memory_offset,
+    };
+    let rsdp = find_rsdp(&memory).ok_or(AcpiError::NoRsdp)?;
+    // A bad FADT only costs the reset fallback.
+    let _ = init_reset(&memory, rsdp);
+    let phys = find_table(&memory, rsdp, MADT_SIGNATURE)?.ok_or(AcpiError::NoMadt)?;
+    let table = memory.table(phys, MADT_SIGNATURE)?.ok_or(AcpiError::NoMadt)?;
+    let madt = parse_madt(table);
+    interrupts::without_interrupts(|| *MADT.lock() = Some(madt));
+    Ok(())
+}
+
+// A copy of the parsed MADT, or None before init() or if it failed.
+pub fn madt() -> Option<Madt> {
+    interrupts::without_interrupts(|| MADT.lock().clone())
+}
+
+// The number of enabled processors. Without a MADT only the boot processor
+// is known about.
+pub fn cpu_count() -> usize {
+    madt().map_or(1, |madt| madt.local_apics.iter().filter(|apic| apic.enabled).count().max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // The tests run under QEMU with -smp 2.
+    #[test_case]
+    fn the_madt_lists_both_processors() {
+        let madt = madt().unwrap();
+        assert!(madt.local_apics.len() >= 2);
+        assert!(!madt.io_apics.is_empty());
+        assert!(cpu_count() >= 2);
+    }
+
+    #[test_case]
+    fn parse_madt_keeps_usable_entries() {
+        let mut table = vec![0; MADT_ENTRIES_OFFSET];
+        table[SDT_HEADER_LEN..SDT_HEADER_LEN + 4].copy_from_slice(&0xFEE0_0000u32.to_le_bytes());
+        // Enabled, online capable and absent local APICs, then an I/O APIC.
+        table.extend_from_slice(&[MADT_LOCAL_APIC, 8, 0, 0, 1, 0, 0, 0]);
+        table.extend_from_slice(&[MADT_LOCAL_APIC, 8, 1, 2, 2, 0, 0, 0]);
+        table.extend_from_slice(&[MADT_LOCAL_APIC, 8, 2, 4, 0, 0, 0, 0]);
+        table.extend_from_slice(&[MADT_IO_APIC, 12, 7, 0, 0, 0, 0xC0, 0xFE, 0, 0, 0, 0]);
+        // A zero-length entry ends the walk.
+        table.extend_from_slice(&[MADT_LOCAL_APIC, 0, 3, 6, 1, 0, 0, 0]);
+        let madt = parse_madt(&table);
+        assert_eq!(madt.local_apic_address, 0xFEE0_0000);
+        let apics: Vec<_> = madt.local_apics.iter().map(|apic| (apic.apic_id, apic.enabled)).collect();
+        assert_eq!(apics, [(0, true), (2, false)]);
+        assert_eq!(madt.io_apics.len(), 1);
+        assert_eq!((madt.io_apics[0].id, madt.io_apics[0].address), (7, 0xFEC0_0000));
+    }
+
+    fn fadt_with_reset(flags: u32, space: u8, port: u64, value: u8) -> Vec<u8> {
+        let mut table = vec![0; FADT_RESET_VALUE_OFFSET + 1];
+        table[FADT_FLAGS_OFFSET..FADT_FLAGS_OFFSET + 4].copy_from_slice(&flags.to_le_bytes());
+        table[FADT_RESET_REG_OFFSET] = space;
+        table[FADT_RESET_REG_OFFSET + 4..FADT_RESET_REG_OFFSET + 12].copy_from_slice(&port.to_le_bytes());
+        table[FADT_RESET_VALUE_OFFSET] = value;
+        table
+    }
+
+    #[test_case]
+    fn parse_fadt_reset_takes_io_space_registers() {
+        let table = fadt_with_reset(FADT_RESET_REG_SUPPORTED, GAS_SYSTEM_IO, 0xCF9, 0x06);
+        assert_eq!(parse_fadt_reset(&table), Some((0xCF9, 0x06)));
+        // Unsupported, in memory space, out of port range, or an ACPI 1.0 FADT.
+        assert_eq!(parse_fadt_reset(&fadt_with_reset(0, GAS_SYSTEM_IO, 0xCF9, 0x06)), None);
+        assert_eq!(parse_fadt_reset(&fadt_with_reset(FADT_RESET_REG_SUPPORTED, 0, 0xCF9, 0x06)), None);
+        assert_eq!(parse_fadt_reset(&fadt_with_reset(FADT_RESET_REG_SUPPORTED, GAS_SYSTEM_IO, 0x1_0000, 0x06)), None);
+        assert_eq!(parse_fadt_reset(&table[..FADT_RESET_REG_OFFSET]), None);
+    }
+}
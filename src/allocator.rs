@@ -0,0 +1,114 @@
+use core::alloc::{GlobalAlloc, Layout};
+use linked_list_allocator::LockedHeap;
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Page, Size4KiB},
+    VirtAddr,
+};
+
+use crate::memory::{self, Access};
+
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024; // 4 MiB, room for a framebuffer back buffer
+
+#[global_allocator]
+static ALLOCATOR: Heap = Heap(LockedHeap::empty());
+
+// Holds the heap lock with interrupts disabled, so a task is never preempted
+// while holding it and the code that runs instead can allocate too.
+struct Heap(LockedHeap);
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
+
+pub fn init_heap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page_range = {
+        let heap_start = VirtAddr::new(HEAP_START as u64);
+        let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
+        let heap_start_page = Page::containing_address(heap_start);
+        let heap_end_page = Page::containing_address(heap_end);
+        Page::range_inclusive(heap_start_page, heap_end_page)
+    };
+
+    memory::map_region(page_range, Access::ReadWrite, mapper, frame_allocator)?;
+
+    unsafe {
+        ALLOCATOR.0.lock().init(HEAP_START, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+// Returns the used and free heap bytes, read together under the heap lock.
+pub fn usage() -> (usize, usize) {
+    interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.0.lock();
+        (heap.used(), heap.free())
+    })
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!("allocation error: {:?}", layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn boxes_hold_their_values() {
+        let a = Box::new(41);
+        let b = Box::new(13);
+        assert_eq!(*a + *b, 54);
+    }
+
+    #[test_case]
+    fn a_large_vec_grows_past_many_pages() {
+        let n = 100_000;
+        let mut vec = Vec::new();
+        for i in 0..n {
+            vec.push(i);
+        }
+        assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+    }
+
+    // Twice the heap's size in total, so freed blocks must be reused.
+    #[test_case]
+    fn freed_memory_is_reused() {
+        let long_lived = Box::new(1);
+        for i in 0..HEAP_SIZE / 8 {
+            let x = Box::new(i);
+            assert_eq!(*x, i);
+        }
+        assert_eq!(*long_lived, 1);
+    }
+
+    // The heap lock is taken with interrupts off, but the caller's setting
+    // must come back either way.
+    #[test_case]
+    fn allocating_keeps_the_interrupt_flag() {
+        let enabled = Box::new(1);
+        assert!(interrupts::are_enabled());
+        interrupts::without_interrupts(|| {
+            let disabled = Box::new(2);
+            assert!(!interrupts::are_enabled());
+            drop(disabled);
+            assert!(!interrupts::are_enabled());
+        });
+        drop(enabled);
+        assert!(interrupts::are_enabled());
+    }
+}
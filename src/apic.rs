@@ -0,0 +1,169 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::cpu;
+use crate::memory::Access;
+
+pub const TIMER_VECTOR: u8 = 0x30;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+// Where the local APIC register page is mapped.
+const LAPIC_VIRT_ADDR: u64 = 0x_5555_5555_0000;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDR_MASK: u64 = 0xF_FFFF_F000;
+
+// Register offsets from the local APIC base.
+const REG_ID: u64 = 0x020;
+const REG_EOI: u64 = 0x0B0;
+const REG_SPURIOUS: u64 = 0x0F0;
+const REG_ICR_LOW: u64 = 0x300;
+const REG_ICR_HIGH: u64 = 0x310;
+const REG_LVT_TIMER: u64 = 0x320;
+const REG_TIMER_INITIAL_COUNT: u64 = 0x380;
+const REG_TIMER_CURRENT_COUNT: u64 = 0x390;
+const REG_TIMER_DIVIDE: u64 = 0x3E0;
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_DIVIDE_BY_16: u32 = 0x3;
+
+const ICR_DELIVERY_INIT: u32 = 0x5 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0x6 << 8;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+// Set while the previous IPI is still being sent.
+const ICR_SEND_PENDING: u32 = 1 << 12;
+
+const PIT_BASE_FREQUENCY_HZ: u32 = 1_193_182;
+const CALIBRATION_MS: u32 = 10;
+
+// Virtual base of the local APIC registers, 0 until mapped.
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+
+pub fn is_supported() -> bool {
+    cpu::CPU_INFO.features.apic
+}
+
+fn read(register: u64) -> u32 {
+    let base = LAPIC_BASE.load(Ordering::Relaxed);
+    unsafe { core::ptr::read_volatile((base + register) as *const u32) }
+}
+
+fn write(register: u64, value: u32) {
+    let base = LAPIC_BASE.load(Ordering::Relaxed);
+    unsafe { core::ptr::write_volatile((base + register) as *mut u32, value) }
+}
+
+pub fn is_enabled() -> bool {
+    LAPIC_BASE.load(Ordering::Relaxed) != 0
+}
+
+pub fn id() -> u32 {
+    read(REG_ID) >> 24
+}
+
+pub fn end_of_interrupt() {
+    write(REG_EOI, 0);
+}
+
+// Maps and software-enables the local APIC. Returns false if the CPU has
+// none or its registers could not be mapped.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> bool {
+    if !is_supported() {
+        return false;
+    }
+    if is_enabled() {
+        return true;
+    }
+
+    let mut apic_base_msr = Msr::new(IA32_APIC_BASE);
+    let apic_base = unsafe { apic_base_msr.read() };
+    let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(apic_base & APIC_BASE_ADDR_MASK));
+    let page = Page::containing_address(VirtAddr::new(LAPIC_VIRT_ADDR));
+    let flags = Access::Mmio.flags();
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(_) => return false,
+    }
+    unsafe {
+        apic_base_msr.write(apic_base | APIC_BASE_ENABLE);
+    }
+    LAPIC_BASE.store(LAPIC_VIRT_ADDR, Ordering::Relaxed);
+
+    write(REG_SPURIOUS, SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+    true
+}
+
+// Enables an application processor's own local APIC. Every local APIC sits
+// at the same address, so the boot processor's mapping from init() is reused.
+pub fn init_ap() {
+    let mut apic_base_msr = Msr::new(IA32_APIC_BASE);
+    unsafe {
+        let apic_base = apic_base_msr.read();
+        apic_base_msr.write(apic_base | APIC_BASE_ENABLE);
+    }
+    write(REG_SPURIOUS, SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+fn send_ipi(apic_id: u8, command: u32) {
+    write(REG_ICR_HIGH, u32::from(apic_id) << 24);
+    write(REG_ICR_LOW, command);
+    while read(REG_ICR_LOW) & ICR_SEND_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+// Resets the processor with local APIC `apic_id` into its wait-for-SIPI state.
+pub fn send_init(apic_id: u8) {
+    send_ipi(apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT);
+}
+
+// Starts a processor waiting for SIPI in real mode at `vector` * 4 KiB.
+pub fn send_startup(apic_id: u8, vector: u8) {
+    send_ipi(apic_id, ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | u32::from(vector));
+}
+
+// Blocks for `ms` milliseconds by polling PIT channel 2 in one-shot mode.
+// Only usable before the speaker is in use, since it drives the same gate.
+pub fn pit_wait_ms(ms: u32) {
+    let count = (PIT_BASE_FREQUENCY_HZ * ms / 1000) as u16;
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+    unsafe {
+        // Enable the channel 2 gate with the speaker output off.
+        let value = gate.read();
+        gate.write((value & !0x02) | 0x01);
+        command.write(0xB0); // channel 2, lobyte/hibyte, mode 0
+        channel2.write((count & 0xFF) as u8);
+        channel2.write((count >> 8) as u8);
+        // Bit 5 goes high when the count reaches zero.
+        while gate.read() & 0x20 == 0 {}
+        gate.write(value);
+    }
+}
+
+// Starts the APIC timer in periodic mode at `frequency_hz`, calibrated
+// against the PIT. Returns the rate actually programmed, which the integer
+// divide can leave slightly off the one asked for.
+pub fn start_timer(frequency_hz: u32) -> u32 {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_TIMER_INITIAL_COUNT, u32::MAX);
+    pit_wait_ms(CALIBRATION_MS);
+    let elapsed = u32::MAX - read(REG_TIMER_CURRENT_COUNT);
+
+    let ticks_per_second = elapsed / CALIBRATION_MS * 1000;
+    write(REG_LVT_TIMER, LVT_TIMER_PERIODIC | TIMER_VECTOR as u32);
+    let initial_count = (ticks_per_second / frequency_hz).max(1);
+    write(REG_TIMER_INITIAL_COUNT, initial_count);
+    ticks_per_second / initial_count
+}
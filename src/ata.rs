@@ -0,0 +1,287 @@
+// ATA PIO driver for the master drive on the primary channel, using 28-bit
+// LBA and polling rather than IRQ 14. Under QEMU attach a disk with e.g.
+// `-drive file=disk.img,format=raw,index=0,media=disk`.
+
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+pub const SECTOR_SIZE: usize = 512;
+
+const PRIMARY_IO_BASE: u16 = 0x1F0;
+const PRIMARY_CONTROL: u16 = 0x3F6;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+const COMMAND_READ_SECTORS: u8 = 0x20;
+const COMMAND_WRITE_SECTORS: u8 = 0x30;
+const COMMAND_CACHE_FLUSH: u8 = 0xE7;
+const COMMAND_IDENTIFY: u8 = 0xEC;
+
+// Bit 1 of the device control register masks the drive's interrupt.
+const CONTROL_NIEN: u8 = 1 << 1;
+// Drive/head register: bits 7 and 5 are always set, bit 6 selects LBA.
+const DRIVE_MASTER_LBA: u8 = 0xE0;
+const DRIVE_MASTER: u8 = 0xA0;
+
+const TIMEOUT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtaError {
+    NoDevice,
+    Timeout,
+    DeviceFault,
+    // The command was aborted; the value is the error register.
+    Error(u8),
+    OutOfRange,
+}
+
+impl fmt::Display for AtaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtaError::NoDevice => f.write_str("no drive"),
+            AtaError::Timeout => f.write_str("timed out"),
+            AtaError::DeviceFault => f.write_str("device fault"),
+            AtaError::Error(code) => write!(f, "error {:#04x}", code),
+            AtaError::OutOfRange => f.write_str("sector out of range"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DriveInfo {
+    pub sectors: u32,
+    model: [u8; 40],
+}
+
+impl DriveInfo {
+    pub fn model(&self) -> &str {
+        core::str::from_utf8(&self.model).unwrap_or("").trim()
+    }
+}
+
+pub struct AtaBus {
+    data: Port<u16>,
+    error: PortReadOnly<u8>,
+    sector_count: Port<u8>,
+    lba_low: Port<u8>,
+    lba_mid: Port<u8>,
+    lba_high: Port<u8>,
+    drive: Port<u8>,
+    // Reads return the status register, writes issue a command.
+    command: Port<u8>,
+    // Reads return the alternate status, which doesn't clear a pending
+    // interrupt; writes go to the device control register.
+    alt_status: PortReadOnly<u8>,
+    control: PortWriteOnly<u8>,
+    info: Option<DriveInfo>,
+}
+
+impl AtaBus {
+    pub const fn new(io_base: u16, control: u16) -> Self {
+        AtaBus {
+            data: Port::new(io_base),
+            error: PortReadOnly::new(io_base + 1),
+            sector_count: Port::new(io_base + 2),
+            lba_low: Port::new(io_base + 3),
+            lba_mid: Port::new(io_base + 4),
+            lba_high: Port::new(io_base + 5),
+            drive: Port::new(io_base + 6),
+            command: Port::new(io_base + 7),
+            alt_status: PortReadOnly::new(control),
+            control: PortWriteOnly::new(control),
+            info: None,
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        unsafe { self.command.read() }
+    }
+
+    // The drive needs ~400 ns after a command or drive select before its
+    // status is valid; each alternate status read takes ~100 ns.
+    fn delay_400ns(&mut self) {
+        for _ in 0..4 {
+            unsafe {
+                self.alt_status.read();
+            }
+        }
+    }
+
+    fn wait_not_busy(&mut self) -> Result<u8, AtaError> {
+        for _ in 0..TIMEOUT {
+            let status = self.status();
+            if status & STATUS_BSY == 0 {
+                return Ok(status);
+            }
+        }
+        Err(AtaError::Timeout)
+    }
+
+    // Waits until the drive is ready to transfer a sector's worth of data.
+    fn wait_data_ready(&mut self) -> Result<(), AtaError> {
+        for _ in 0..TIMEOUT {
+            let status = self.wait_not_busy()?;
+            if status & STATUS_ERR != 0 {
+                return Err(AtaError::Error(unsafe { self.error.read() }));
+            }
+            if status & STATUS_DF != 0 {
+                return Err(AtaError::DeviceFault);
+            }
+            if status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(AtaError::Timeout)
+    }
+
+    fn select_lba(&mut self, lba: u32) {
+        unsafe {
+            self.drive.write(DRIVE_MASTER_LBA | ((lba >> 24) & 0x0F) as u8);
+            self.sector_count.write(1);
+            self.lba_low.write(lba as u8);
+            self.lba_mid.write((lba >> 8) as u8);
+            self.lba_high.write((lba >> 16) as u8);
+        }
+    }
+
+    pub fn identify(&mut self) -> Result<DriveInfo, AtaError> {
+        unsafe {
+            self.control.write(CONTROL_NIEN);
+            self.drive.write(DRIVE_MASTER);
+        }
+        self.delay_400ns();
+        unsafe {
+            self.sector_count.write(0);
+            self.lba_low.write(0);
+            self.lba_mid.write(0);
+            self.lba_high.write(0);
+            self.command.write(COMMAND_IDENTIFY);
+        }
+        // A floating bus reads 0xFF and an absent drive reads 0.
+        let status = self.status();
+        if status == 0 || status == 0xFF {
+            return Err(AtaError::NoDevice);
+        }
+        self.wait_not_busy()?;
+        // ATAPI and SATA devices identify themselves through these.
+        if unsafe { self.lba_mid.read() != 0 || self.lba_high.read() != 0 } {
+            return Err(AtaError::NoDevice);
+        }
+        self.wait_data_ready()?;
+
+        let mut words = [0u16; SECTOR_SIZE / 2];
+        for word in words.iter_mut() {
+            *word = unsafe { self.data.read() };
+        }
+        let sectors = u32::from(words[60]) | (u32::from(words[61]) << 16);
+        // The model string is stored as big-endian words.
+        let mut model = [0u8; 40];
+        for (i, word) in words[27..47].iter().enumerate() {
+            model[i * 2..i * 2 + 2].copy_from_slice(&word.to_be_bytes());
+        }
+        let info = DriveInfo { sectors, model };
+        self.info = Some(info);
+        Ok(info)
+    }
+
+    fn check_lba(&self, lba: u32) -> Result<(), AtaError> {
+        match self.info {
+            None => Err(AtaError::NoDevice),
+            Some(info) if lba >= info.sectors => Err(AtaError::OutOfRange),
+            Some(_) => Ok(()),
+        }
+    }
+
+    pub fn read_sector(&mut self, lba: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), AtaError> {
+        self.check_lba(lba)?;
+        self.select_lba(lba);
+        unsafe {
+            self.command.write(COMMAND_READ_SECTORS);
+        }
+        self.delay_400ns();
+        self.wait_data_ready()?;
+        for chunk in buf.chunks_exact_mut(2) {
+            let word = unsafe { self.data.read() };
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    pub fn write_sector(&mut self, lba: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), AtaError> {
+        self.check_lba(lba)?;
+        self.select_lba(lba);
+        unsafe {
+            self.command.write(COMMAND_WRITE_SECTORS);
+        }
+        self.delay_400ns();
+        self.wait_data_ready()?;
+        for chunk in buf.chunks_exact(2) {
+            unsafe {
+                self.data.write(u16::from_le_bytes([chunk[0], chunk[1]]));
+            }
+        }
+        // Make sure the data has left the drive's write cache.
+        unsafe {
+            self.command.write(COMMAND_CACHE_FLUSH);
+        }
+        self.delay_400ns();
+        let status = self.wait_not_busy()?;
+        if status & STATUS_ERR != 0 {
+            return Err(AtaError::Error(unsafe { self.error.read() }));
+        }
+        Ok(())
+    }
+
+    pub fn info(&self) -> Option<DriveInfo> {
+        self.info
+    }
+}
+
+pub static PRIMARY: Mutex<AtaBus> = Mutex::new(AtaBus::new(PRIMARY_IO_BASE, PRIMARY_CONTROL));
+
+pub fn init() -> Result<DriveInfo, AtaError> {
+    PRIMARY.lock().identify()
+}
+
+pub fn read_sector(lba: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), AtaError> {
+    PRIMARY.lock().read_sector(lba, buf)
+}
+
+pub fn write_sector(lba: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), AtaError> {
+    PRIMARY.lock().write_sector(lba, buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Under `cargo test` the drive is the boot image, so the sector used is
+    // the last one and it is put back afterwards.
+    #[test_case]
+    fn sectors_read_back_as_written() {
+        let info = match PRIMARY.lock().info() {
+            Some(info) => info,
+            None => {
+                crate::serial_print!("(no drive, skipped) ");
+                return;
+            }
+        };
+        let lba = info.sectors - 1;
+        let mut original = [0; SECTOR_SIZE];
+        read_sector(lba, &mut original).unwrap();
+        let mut pattern = [0; SECTOR_SIZE];
+        for (i, byte) in pattern.iter_mut().enumerate() {
+            *byte = (i * 7 + 3) as u8;
+        }
+        write_sector(lba, &pattern).unwrap();
+        let mut read_back = [0; SECTOR_SIZE];
+        read_sector(lba, &mut read_back).unwrap();
+        write_sector(lba, &original).unwrap();
+        assert!(read_back == pattern);
+        assert!(matches!(read_sector(info.sectors, &mut read_back), Err(AtaError::OutOfRange)));
+    }
+}
@@ -0,0 +1,353 @@
+// Fixed-size block storage for the filesystem, backed either by a RAM disk
+// or by the ATA drive. Blocks are numbered from 0 to block_count() - 1.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+use crate::ata::{self, AtaError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    OutOfRange,
+    // The buffer's length isn't the device's block size.
+    BadBufferSize,
+    Ata(AtaError),
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::OutOfRange => f.write_str("block out of range"),
+            BlockError::BadBufferSize => f.write_str("buffer is not one block long"),
+            BlockError::Ata(err) => write!(f, "ata: {}", err),
+        }
+    }
+}
+
+// Reads and writes take `&self` so a shared filesystem can read without
+// exclusive access; devices that need it lock internally.
+pub trait BlockDevice: Send {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u32;
+    fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), BlockError>;
+    fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), BlockError>;
+}
+
+pub const RAMDISK_BLOCK_SIZE: usize = 512;
+
+pub struct RamDisk {
+    data: Mutex<Vec<u8>>,
+    blocks: u32,
+}
+
+impl RamDisk {
+    pub fn new(blocks: u32) -> Self {
+        RamDisk {
+            data: Mutex::new(vec![0; blocks as usize * RAMDISK_BLOCK_SIZE]),
+            blocks,
+        }
+    }
+
+    fn range(&self, index: u32, len: usize) -> Result<core::ops::Range<usize>, BlockError> {
+        if index >= self.blocks {
+            return Err(BlockError::OutOfRange);
+        }
+        if len != RAMDISK_BLOCK_SIZE {
+            return Err(BlockError::BadBufferSize);
+        }
+        let start = index as usize * RAMDISK_BLOCK_SIZE;
+        Ok(start..start + RAMDISK_BLOCK_SIZE)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        RAMDISK_BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u32 {
+        self.blocks
+    }
+
+    fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+        let range = self.range(index, buf.len())?;
+        buf.copy_from_slice(&self.data.lock()[range]);
+        Ok(())
+    }
+
+    fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), BlockError> {
+        let range = self.range(index, buf.len())?;
+        self.data.lock()[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+// The primary master ATA drive, one sector per block. ata::init() must have
+// identified the drive first.
+pub struct AtaDisk;
+
+impl AtaDisk {
+    // None if no drive was found.
+    pub fn new() -> Option<Self> {
+        ata::PRIMARY.lock().info().map(|_| AtaDisk)
+    }
+}
+
+impl From<AtaError> for BlockError {
+    fn from(err: AtaError) -> Self {
+        match err {
+            AtaError::OutOfRange => BlockError::OutOfRange,
+            err => BlockError::Ata(err),
+        }
+    }
+}
+
+impl BlockDevice for AtaDisk {
+    fn block_size(&self) -> usize {
+        ata::SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u32 {
+        ata::PRIMARY.lock().info().map_or(0, |info| info.sectors)
+    }
+
+    fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+        if buf.len() != ata::SECTOR_SIZE {
+            return Err(BlockError::BadBufferSize);
+        }
+        let mut sector = [0; ata::SECTOR_SIZE];
+        ata::read_sector(index, &mut sector)?;
+        buf.copy_from_slice(&sector);
+        Ok(())
+    }
+
+    fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), BlockError> {
+        if buf.len() != ata::SECTOR_SIZE {
+            return Err(BlockError::BadBufferSize);
+        }
+        let mut sector = [0; ata::SECTOR_SIZE];
+        sector.copy_from_slice(buf);
+        ata::write_sector(index, &sector)?;
+        Ok(())
+    }
+}
+
+const CACHE_BLOCKS: usize = 16;
+
+struct CachedBlock {
+    index: u32,
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+struct CacheState {
+    blocks: Vec<CachedBlock>,
+    // Bumped on every access to order blocks for eviction.
+    clock: u64,
+}
+
+// A write-back cache in front of another device. Writes stay in memory until
+// flush(), or until the block is the least recently used one and room is
+// needed, in which case it is written back before being dropped.
+pub struct BlockCache {
+    device: Box<dyn BlockDevice>,
+    state: Mutex<CacheState>,
+}
+
+impl BlockCache {
+    pub fn new(device: Box<dyn BlockDevice>) -> Self {
+        BlockCache {
+            device,
+            state: Mutex::new(CacheState {
+                blocks: Vec::with_capacity(CACHE_BLOCKS),
+                clock: 0,
+            }),
+        }
+    }
+
+    fn check(&self, index: u32, len: usize) -> Result<(), BlockError> {
+        if index >= self.device.block_count() {
+            return Err(BlockError::OutOfRange);
+        }
+        if len != self.device.block_size() {
+            return Err(BlockError::BadBufferSize);
+        }
+        Ok(())
+    }
+
+    // Makes room for one more block. Fails, evicting nothing, if the block
+    // chosen can't be written back.
+    fn make_room(&self, state: &mut CacheState) -> Result<(), BlockError> {
+        if state.blocks.len() < CACHE_BLOCKS {
+            return Ok(());
+        }
+        let lru = (0..state.blocks.len())
+            .min_by_key(|&i| state.blocks[i].last_used)
+            .expect("cache is full, so not empty");
+        let victim = &state.blocks[lru];
+        if victim.dirty {
+            self.device.write_block(victim.index, &victim.data)?;
+        }
+        state.blocks.swap_remove(lru);
+        Ok(())
+    }
+
+    // Writes every dirty block to the device, returning how many there were.
+    pub fn flush(&self) -> Result<usize, BlockError> {
+        let mut state = self.state.lock();
+        let mut written = 0;
+        for block in state.blocks.iter_mut().filter(|block| block.dirty) {
+            self.device.write_block(block.index, &block.data)?;
+            block.dirty = false;
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u32 {
+        self.device.block_count()
+    }
+
+    fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check(index, buf.len())?;
+        let mut state = self.state.lock();
+        state.clock += 1;
+        let now = state.clock;
+        if let Some(block) = state.blocks.iter_mut().find(|block| block.index == index) {
+            block.last_used = now;
+            buf.copy_from_slice(&block.data);
+            return Ok(());
+        }
+        self.device.read_block(index, buf)?;
+        self.make_room(&mut state)?;
+        state.blocks.push(CachedBlock {
+            index,
+            data: buf.to_vec(),
+            dirty: false,
+            last_used: now,
+        });
+        Ok(())
+    }
+
+    fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), BlockError> {
+        self.check(index, buf.len())?;
+        let mut state = self.state.lock();
+        state.clock += 1;
+        let now = state.clock;
+        if let Some(block) = state.blocks.iter_mut().find(|block| block.index == index) {
+            block.last_used = now;
+            block.data.copy_from_slice(buf);
+            block.dirty = true;
+            return Ok(());
+        }
+        self.make_room(&mut state)?;
+        state.blocks.push(CachedBlock {
+            index,
+            data: buf.to_vec(),
+            dirty: true,
+            last_used: now,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::filesystem::FileSystem;
+    use alloc::sync::Arc;
+
+    // Lets a test look at what actually reached a RAM disk behind a cache or
+    // filesystem that owns its device.
+    pub struct SharedDisk(pub Arc<RamDisk>);
+
+    impl BlockDevice for SharedDisk {
+        fn block_size(&self) -> usize {
+            self.0.block_size()
+        }
+
+        fn block_count(&self) -> u32 {
+            self.0.block_count()
+        }
+
+        fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+            self.0.read_block(index, buf)
+        }
+
+        fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), BlockError> {
+            self.0.write_block(index, buf)
+        }
+    }
+
+    // Every block of `disk`, in order.
+    pub fn contents(disk: &RamDisk) -> Vec<u8> {
+        let mut data = vec![0; disk.block_count() as usize * RAMDISK_BLOCK_SIZE];
+        for (index, block) in data.chunks_mut(RAMDISK_BLOCK_SIZE).enumerate() {
+            disk.read_block(index as u32, block).unwrap();
+        }
+        data
+    }
+
+    #[test_case]
+    fn cached_writes_reach_the_device_on_flush() {
+        let disk = Arc::new(RamDisk::new(4));
+        let cache = BlockCache::new(Box::new(SharedDisk(disk.clone())));
+        cache.write_block(1, &[0xAB; RAMDISK_BLOCK_SIZE]).unwrap();
+        let mut buf = [0; RAMDISK_BLOCK_SIZE];
+        disk.read_block(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+        cache.read_block(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0xAB));
+        assert_eq!(cache.flush(), Ok(1));
+        disk.read_block(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0xAB));
+        assert_eq!(cache.flush(), Ok(0));
+    }
+
+    #[test_case]
+    fn evicting_a_dirty_block_writes_it_back() {
+        let disk = Arc::new(RamDisk::new(CACHE_BLOCKS as u32 + 1));
+        let cache = BlockCache::new(Box::new(SharedDisk(disk.clone())));
+        for index in 0..=CACHE_BLOCKS as u32 {
+            cache.write_block(index, &[index as u8 + 1; RAMDISK_BLOCK_SIZE]).unwrap();
+        }
+        // Block 0 was least recently used, so it made room for the last one.
+        let mut buf = [0; RAMDISK_BLOCK_SIZE];
+        disk.read_block(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 1));
+    }
+
+    #[test_case]
+    fn ram_disk_blocks_round_trip() {
+        let disk = RamDisk::new(8);
+        assert_eq!((disk.block_size(), disk.block_count()), (RAMDISK_BLOCK_SIZE, 8));
+        for index in 0..8 {
+            disk.write_block(index, &[index as u8 * 3; RAMDISK_BLOCK_SIZE]).unwrap();
+        }
+        let mut buf = [0; RAMDISK_BLOCK_SIZE];
+        for index in (0..8).rev() {
+            disk.read_block(index, &mut buf).unwrap();
+            assert!(buf.iter().all(|&b| b == index as u8 * 3));
+        }
+        assert_eq!(disk.read_block(8, &mut buf), Err(BlockError::OutOfRange));
+        assert_eq!(disk.write_block(0, &[0; 16]), Err(BlockError::BadBufferSize));
+    }
+
+    #[test_case]
+    fn the_filesystem_works_over_a_ram_disk() {
+        let mut fs = FileSystem::format(Box::new(RamDisk::new(64))).unwrap();
+        fs.create_file("/a", b"over a block device").unwrap();
+        assert_eq!(fs.read_file("/a").unwrap(), b"over a block device");
+        assert_eq!(fs.list_files(None).unwrap(), ["a"]);
+    }
+}
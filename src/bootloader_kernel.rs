@@ -1,37 +1,113 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 use core::panic::PanicInfo;
 use core::sync::atomic::{AtomicUsize, Ordering};
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use x86_64::instructions::{port::Port, interrupts};
-use x86_64::VirtAddr;
-use spin::Mutex;
-use alloc::{vec::Vec, string::String, boxed::Box};
+use x86_64::{PrivilegeLevel, VirtAddr};
+use alloc::{boxed::Box, format};
 use lazy_static::lazy_static;
+use bootloader::BootInfo;
 
 extern crate alloc;
 
 mod vga_buffer;
+mod cp437;
+mod framebuffer;
+mod font;
+mod console;
+mod serial;
+mod allocator;
+mod gdt;
+mod pic;
+mod cpu;
+mod apic;
 mod memory;
 mod task;
+mod block;
 mod filesystem;
+mod keyboard;
+mod mouse;
+mod ps2;
+mod ata;
+mod pci;
+mod power;
+mod shell;
+mod commands;
+mod time;
+mod rtc;
+mod rand;
+mod driver;
+mod settings;
+mod watchdog;
+mod speaker;
+mod acpi;
+mod smp;
+mod percpu;
+mod sync;
+// Nothing in the kernel sends over a channel yet; the tests do.
+#[allow(dead_code)]
+mod channel;
+mod log;
+mod editor;
+mod syscall;
+mod usermode;
 
-use vga_buffer::{WRITER, Color};
-use memory::MemoryManager;
+use vga_buffer::WRITER;
+use memory::BootInfoFrameAllocator;
 use task::{Task, SCHEDULER};
+use block::{AtaDisk, BlockDevice, RamDisk};
 use filesystem::FileSystem;
+use shell::ShellContext;
 
 static TIMER_TICKS: AtomicUsize = AtomicUsize::new(0);
 
+// 32 KiB. The RAM disk fallback lives on the heap, which it shares with the
+// framebuffer back buffer, so it is kept small.
+const RAMDISK_BLOCKS: u32 = 64;
+
+// The display mode to switch to at boot, set at build time through the
+// RUSTOS_VIDEO_MODE environment variable as e.g. "800x600". Unset, "text",
+// or a mode the display adapter can't do all leave the VGA text console.
+const VIDEO_MODE: &str = match option_env!("RUSTOS_VIDEO_MODE") {
+    Some(mode) => mode,
+    None => "text",
+};
+
+// Run through the shell at boot, if present, before the first prompt.
+const AUTOEXEC_PATH: &str = "/autoexec";
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+            // User mode may raise this one.
+            idt[syscall::VECTOR as usize]
+                .set_handler_addr(syscall::entry_address())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        idt[apic::TIMER_VECTOR as usize].set_handler_fn(apic_timer_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -39,10 +115,28 @@ lazy_static! {
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
-    Timer = 32,
+    Timer = pic::PIC_1_OFFSET,
     Keyboard,
+    Cascade,
+    Com2,
+    Com1,
+    Lpt2,
+    Floppy,
+    Lpt1,
+    Rtc,
+    Acpi,
+    Irq10,
+    Irq11,
+    Mouse,
+    Fpu,
+    PrimaryAta,
+    SecondaryAta,
 }
 
+// The variants must map IRQ n to vector PIC_1_OFFSET + n.
+const _: () = assert!(InterruptIndex::Rtc as u8 == pic::PIC_2_OFFSET);
+const _: () = assert!(InterruptIndex::SecondaryAta as u8 == pic::PIC_1_OFFSET + 15);
+
 impl InterruptIndex {
     fn as_u8(self) -> u8 {
         self as u8
@@ -54,148 +148,606 @@ impl InterruptIndex {
 }
 
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
-    println!("Initializing RustOS...");
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    log_info!("Initializing RustOS...");
 
-    memory::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let trampoline_reserved = smp::reserve_trampoline(&mut frame_allocator);
+    if !memory::enable_nx() {
+        log_warn!("CPU lacks NX; data pages stay executable");
+    }
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    WRITER.lock().enable_scrollback();
+    gdt::init();
+    percpu::init(0);
     IDT.load();
+    pic::init();
+    time::init(&mut mapper, &mut frame_allocator);
+    rand::init(time::rdtsc());
+    memory::install(mapper, frame_allocator);
+
+    if serial::init() {
+        driver::record("serial", true, "COM1, 38400 baud");
+    } else {
+        driver::record("serial", false, "COM1 failed its loopback test");
+    }
+    if keyboard::init() {
+        driver::record("keyboard", true, "PS/2, scancode set 1");
+    } else {
+        driver::record("keyboard", false, "PS/2 keyboard port failed its test");
+    }
+    let timer = match time::timer_source() {
+        time::TimerSource::Pit => "PIT",
+        time::TimerSource::LocalApic => "local APIC",
+    };
+    driver::record("timer", true, format!("{} at {} Hz", timer, time::timer_frequency_hz()));
+    if mouse::init() {
+        driver::record("mouse", true, "PS/2 auxiliary port");
+    } else {
+        driver::record("mouse", false, "PS/2 mouse not responding");
+    }
+    match rtc::init() {
+        Some(now) => driver::record("rtc", true, format!("CMOS, {}", now)),
+        None => driver::record("rtc", false, "no readable CMOS clock"),
+    }
+    match acpi::init(phys_mem_offset) {
+        Ok(()) => {
+            let io_apics = acpi::madt().map_or(0, |madt| madt.io_apics.len());
+            driver::record("acpi", true, format!("{} CPUs, {} I/O APICs", acpi::cpu_count(), io_apics));
+        }
+        Err(err) => driver::record("acpi", false, format!("{}", err)),
+    }
+    if !trampoline_reserved {
+        driver::record("smp", false, "no free page below 1 MiB for the AP trampoline");
+    } else if acpi::cpu_count() > 1 {
+        smp::init(phys_mem_offset);
+        let detail = format!("{} of {} CPUs online", smp::online_cpus(), acpi::cpu_count());
+        driver::record("smp", smp::online_cpus() == acpi::cpu_count(), detail);
+    }
+    match framebuffer::parse_mode(VIDEO_MODE) {
+        None if VIDEO_MODE != "text" => {
+            driver::record("framebuffer", false, format!("bad video mode {:?}, using text mode", VIDEO_MODE));
+        }
+        None => {}
+        Some((width, height)) => match framebuffer::init_bochs(width, height) {
+            Some(info) => {
+                console::clear();
+                driver::record(
+                    "framebuffer",
+                    true,
+                    format!("{}x{}, {} bpp at {:#x}", info.width, info.height, info.bpp, info.address.as_u64()),
+                );
+            }
+            None => driver::record("framebuffer", false, "no Bochs/QEMU display adapter for the mode, using text mode"),
+        },
+    }
+    match ata::init() {
+        Ok(info) => driver::record(
+            "ata",
+            true,
+            format!("primary master, {} sectors, {}", info.sectors, info.model()),
+        ),
+        Err(err) => driver::record("ata", false, format!("primary master: {}", err)),
+    }
     x86_64::instructions::interrupts::enable();
 
-    let mut fs = FileSystem::new();
-    fs.create_file("welcome.txt", "Welcome to RustOS!".as_bytes());
+    #[cfg(test)]
+    test_main();
+
+    let mut fs = root_filesystem();
+    if fs.stat("/welcome.txt").is_err() {
+        fs.create_file("/welcome.txt", "Welcome to RustOS!".as_bytes())
+            .expect("failed to create /welcome.txt");
+    }
+    let mut shell = ShellContext::new(fs, commands::registry());
 
     SCHEDULER.lock().add_task(Task::new(task1));
     SCHEDULER.lock().add_task(Task::new(task2));
 
-    println!("RustOS initialized successfully!");
+    log_info!("RustOS initialized successfully!");
     println!("Type 'help' for available commands.");
 
+    run_autoexec(&mut shell);
+
     loop {
-        print!("> ");
-        let command = read_line();
-        handle_command(&command, &mut fs);
-        SCHEDULER.lock().run_next_task();
-    }
-}
-
-fn handle_command(command: &str, fs: &mut FileSystem) {
-    match command.trim() {
-        "help" => {
-            println!("Available commands:");
-            println!("  help - Show this help message");
-            println!("  clear - Clear the screen");
-            println!("  reboot - Reboot the system");
-            println!("  ls - List files");
-            println!("  cat <filename> - Display file contents");
-            println!("  write <filename> <content> - Write content to a file");
-        }
-        "clear" => vga_buffer::WRITER.lock().clear_screen(),
-        "reboot" => reboot(),
-        "ls" => {
-            for file in fs.list_files() {
-                println!("{}", file);
-            }
-        }
-        cmd if cmd.starts_with("cat ") => {
-            let filename = &cmd[4..];
-            match fs.read_file(filename) {
-                Some(content) => println!("{}", core::str::from_utf8(&content).unwrap_or("Invalid UTF-8")),
-                None => println!("File not found: {}", filename),
-            }
+        watchdog::pet();
+        task::reap();
+        if let Some(line) = shell::read_line(&shell.prompt(), &mut shell) {
+            shell.execute(&line);
         }
-        cmd if cmd.starts_with("write ") => {
-            let parts: Vec<&str> = cmd.splitn(3, ' ').collect();
-            if parts.len() == 3 {
-                fs.create_file(parts[1], parts[2].as_bytes());
-                println!("File written: {}", parts[1]);
-            } else {
-                println!("Usage: write <filename> <content>");
-            }
-        }
-        _ => println!("Unknown command. Type 'help' for available commands."),
     }
 }
 
-fn read_line() -> String {
-    let mut input = String::new();
-    loop {
-        let key = wait_for_key();
-        match key {
-            b'\r' => {
-                println!();
-                return input;
-            }
-            8 => {
-                if !input.is_empty() {
-                    input.pop();
-                    print!("\x08 \x08");
-                }
-            }
-            32..=126 => {
-                input.push(key as char);
-                print!("{}", key as char);
+// Mounts the filesystem on the ATA drive, formatting the drive first if it is
+// blank. A drive holding anything else is left alone and a RAM disk, which
+// doesn't survive a reboot, is used instead.
+fn root_filesystem() -> FileSystem {
+    if let Some(disk) = AtaDisk::new() {
+        let mut first_block = [0; ata::SECTOR_SIZE];
+        let blank = disk.read_block(0, &mut first_block).is_ok() && first_block.iter().all(|&b| b == 0);
+        let result = if blank {
+            log_info!("Formatting blank ATA drive");
+            FileSystem::format(Box::new(disk))
+        } else {
+            FileSystem::mount(Box::new(disk))
+        };
+        match result {
+            Ok(fs) => {
+                log_info!("Mounted filesystem on ATA drive");
+                return fs;
             }
-            _ => {}
+            Err(err) => log_warn!("Cannot use ATA drive ({}), falling back to a RAM disk", err),
         }
     }
+    FileSystem::format(Box::new(RamDisk::new(RAMDISK_BLOCKS))).expect("failed to format the RAM disk")
 }
 
-fn wait_for_key() -> u8 {
-    let mut port = Port::new(0x64);
-    let mut data_port = Port::new(0x60);
-    unsafe {
-        while port.read() & 1 == 0 {}
-        data_port.read()
+// Lines that fail are reported by run_script and don't stop the boot.
+fn run_autoexec(shell: &mut ShellContext) {
+    if shell.fs.stat(AUTOEXEC_PATH).is_ok() {
+        if let Err(err) = shell.run_script(AUTOEXEC_PATH, false) {
+            log_warn!("{}: {}", AUTOEXEC_PATH, err);
+        }
     }
 }
 
-fn reboot() {
-    unsafe {
-        Port::new(0x64).write(0xFE as u8);
+// Idles the CPU forever. Interrupts are left as they are, so callers that
+// want the CPU to stay parked must disable them first.
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
     }
-    loop {}
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    log_warn!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    #[cfg(test)]
+    fault_probe::catch("double fault", stack_frame.instruction_pointer.as_u64());
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
-    unsafe {
-        Port::new(0x20).write(0x20 as u8);
+macro_rules! report_exception {
+    ($name:expr, $stack_frame:expr) => {
+        log_error!("EXCEPTION: {}\n{:#?}", $name, $stack_frame)
+    };
+    ($name:expr, $stack_frame:expr, $error_code:expr) => {
+        log_error!("EXCEPTION: {} (error code {:#x})\n{:#?}", $name, $error_code, $stack_frame)
+    };
+}
+
+// Exceptions in user mode end the user task and leave the kernel running.
+macro_rules! kill_user_task {
+    ($name:expr, $stack_frame:expr) => {
+        percpu::restore_gs_base($stack_frame.code_segment);
+        if usermode::from_user_mode($stack_frame.code_segment) {
+            log_error!(
+                "User task {} killed: {} at {:#x}",
+                task::current_task_id(),
+                $name,
+                $stack_frame.instruction_pointer.as_u64()
+            );
+            task::exit();
+        }
+    };
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    kill_user_task!("general protection fault", stack_frame);
+    report_exception!("GENERAL PROTECTION FAULT", stack_frame, error_code);
+    #[cfg(test)]
+    fault_probe::catch("general protection fault", stack_frame.instruction_pointer.as_u64());
+    panic!("unrecoverable general protection fault");
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    kill_user_task!("invalid opcode", stack_frame);
+    report_exception!("INVALID OPCODE", stack_frame);
+    #[cfg(test)]
+    fault_probe::catch("invalid opcode", stack_frame.instruction_pointer.as_u64());
+    panic!("unrecoverable invalid opcode");
+}
+
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    kill_user_task!("divide error", stack_frame);
+    report_exception!("DIVIDE ERROR", stack_frame);
+    #[cfg(test)]
+    fault_probe::catch("divide error", stack_frame.instruction_pointer.as_u64());
+    panic!("unrecoverable divide error");
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    kill_user_task!("stack segment fault", stack_frame);
+    report_exception!("STACK SEGMENT FAULT", stack_frame, error_code);
+    panic!("unrecoverable stack segment fault");
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    let address = Cr2::read();
+    kill_user_task!("page fault", stack_frame);
+    log_error!("EXCEPTION: PAGE FAULT");
+    log_error!("Accessed Address: {:?}", address);
+    if memory::is_stack_guard(address) {
+        log_error!("Stack overflow: hit the guard page below a task stack");
     }
+    log_error!(
+        "Cause: {} {} in {} mode{}",
+        if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "protection violation on"
+        } else {
+            "not-present page on"
+        },
+        if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            "write"
+        } else {
+            "read"
+        },
+        if error_code.contains(PageFaultErrorCode::USER_MODE) {
+            "user"
+        } else {
+            "kernel"
+        },
+        if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            " (instruction fetch)"
+        } else {
+            ""
+        },
+    );
+    log_error!("Error Code: {:?}", error_code);
+    log_error!("{:#?}", stack_frame);
+    #[cfg(test)]
+    fault_probe::catch("page fault", address.as_u64());
+    panic!("unrecoverable page fault");
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    // Handle keyboard input here
-    unsafe {
-        Port::new(0x20).write(0x20 as u8);
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs_base(stack_frame.code_segment);
+    // Acknowledge before switching: the next task may run for a whole slice
+    // before this handler returns.
+    pic::notify_end_of_interrupt(InterruptIndex::Timer);
+    timer_tick();
+}
+
+extern "x86-interrupt" fn apic_timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs_base(stack_frame.code_segment);
+    apic::end_of_interrupt();
+    timer_tick();
+}
+
+// The APIC does not expect an EOI for spurious interrupts.
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+// Shared by the PIT and APIC timer handlers, after they have sent EOI.
+fn timer_tick() {
+    let ticks = TIMER_TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    watchdog::check(ticks);
+    if ticks.is_multiple_of(task::time_slice()) {
+        task::preempt();
     }
 }
 
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs_base(stack_frame.code_segment);
+    ps2::handle_interrupt();
+    pic::notify_end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(stack_frame: InterruptStackFrame) {
+    percpu::restore_gs_base(stack_frame.code_segment);
+    ps2::handle_interrupt();
+    pic::notify_end_of_interrupt(InterruptIndex::Mouse);
+}
+
 fn task1() {
     loop {
         println!("Task 1 running");
-        for _ in 0..1000000 {}
+        task::yield_now();
     }
 }
 
 fn task2() {
     loop {
         println!("Task 2 running");
-        for _ in 0..1000000 {}
+        task::yield_now();
     }
 }
 
+const MAX_BACKTRACE_FRAMES: usize = 32;
+
+// Walks the saved rbp chain, printing each frame's return address. This
+// only works when the kernel is built with frame pointers, which the target
+// spec asks for with "frame-pointer": "always"; without them rbp is just
+// another register and the walk stops at the first implausible value.
+fn print_backtrace() {
+    let mut rbp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) rbp);
+    }
+    log_error!("Backtrace:");
+    for depth in 0..MAX_BACKTRACE_FRAMES {
+        // Each frame holds the caller's rbp at [rbp] and the return address
+        // at [rbp + 8]. Stop on anything that can't be such a frame.
+        if rbp == 0 || !rbp.is_multiple_of(8) {
+            break;
+        }
+        let frame = match VirtAddr::try_new(rbp) {
+            Ok(frame) => frame,
+            Err(_) => break,
+        };
+        if memory::is_mapped(frame) == Some(false) || memory::is_mapped(frame + 8u64) == Some(false) {
+            break;
+        }
+        let (caller_rbp, return_address) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+        if return_address == 0 {
+            break;
+        }
+        log_error!("  #{:<2} {:#018x}", depth, return_address);
+        // Stacks grow down, so callers' frames are at higher addresses.
+        if caller_rbp <= rbp {
+            break;
+        }
+        rbp = caller_rbp;
+    }
+}
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("Kernel panic: {}", info);
-    loop {}
+    interrupts::disable();
+    // Whatever held the console locks will never release them now, and
+    // printing skips locked consoles with interrupts disabled.
+    unsafe {
+        WRITER.force_unlock();
+        console::force_unlock();
+        serial::SERIAL1.force_unlock();
+        log::force_unlock();
+    }
+    log_error!("Kernel panic: {}", info);
+    print_backtrace();
+    hlt_loop();
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+    interrupts::disable();
+    hlt_loop();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+// Needs QEMU started with `-device isa-debug-exit,iobase=0xf4,iosize=0x04`.
+// QEMU then exits with status (code << 1) | 1, so a passing run exits with 33;
+// bootimage must be told that via `test-success-exit-code = 33`.
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    unsafe {
+        Port::new(0xf4).write(exit_code as u32);
+    }
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+#[cfg(test)]
+fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+#[test_case]
+fn trivial_assertion() {
+    assert_eq!(core::hint::black_box(1) + 1, 2);
+}
+
+#[test_case]
+fn backtraces_walk_every_frame() {
+    fn nested(depth: usize) {
+        if depth == 0 {
+            print_backtrace();
+        } else {
+            nested(depth - 1);
+        }
+        // Keeps the call from becoming a jump that reuses the frame.
+        core::hint::black_box(depth);
+    }
+    nested(3);
+    let records = log::records();
+    let start = records.iter().rposition(|record| record.text == "Backtrace:").unwrap();
+    let frames = records[start + 1..].iter().take_while(|record| record.text.starts_with("  #")).count();
+    // The four calls to nested() at least.
+    assert!(frames >= 4);
+}
+
+// hlt_loop() never returns, so it is run as a task and killed. With
+// interrupts enabled it only parks that task: ticks keep coming and the
+// scheduler keeps switching away from it.
+#[test_case]
+fn hlt_loop_parks_the_cpu_until_interrupts() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    static HALTING: AtomicBool = AtomicBool::new(false);
+    let id = task::tests::spawn(|| {
+        HALTING.store(true, Ordering::SeqCst);
+        hlt_loop();
+    });
+    task::tests::run_until(|| HALTING.load(Ordering::SeqCst));
+    let ticks = time::uptime_ticks();
+    time::sleep(5);
+    assert!(time::uptime_ticks() >= ticks + 5);
+    assert!(interrupts::without_interrupts(|| SCHEDULER.lock().kill(id)));
+}
+
+// Lets a test run code that is meant to fault. The code runs as a task of
+// its own, and once a handler has reported the fault it ends that task, as
+// it would a user task, instead of panicking.
+#[cfg(test)]
+mod fault_probe {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use spin::Mutex;
+    use x86_64::instructions::interrupts;
+
+    use crate::task::{self, tests::run_until, SCHEDULER};
+
+    const NO_TASK: usize = usize::MAX;
+
+    static PROBED_TASK: AtomicUsize = AtomicUsize::new(NO_TASK);
+    // The exception, and the address it faulted on for page faults or the
+    // instruction pointer otherwise.
+    static CAUGHT: Mutex<Option<(&'static str, u64)>> = Mutex::new(None);
+
+    // Runs `f` as a task and returns what it raised, or None if it returned.
+    pub fn run(f: fn()) -> Option<(&'static str, u64)> {
+        let id = interrupts::without_interrupts(|| {
+            *CAUGHT.lock() = None;
+            let id = SCHEDULER.lock().spawn(f);
+            PROBED_TASK.store(id, Ordering::Relaxed);
+            id
+        });
+        run_until(|| !interrupts::without_interrupts(|| SCHEDULER.lock().task_ids().contains(&id)));
+        PROBED_TASK.store(NO_TASK, Ordering::Relaxed);
+        interrupts::without_interrupts(|| CAUGHT.lock().take())
+    }
+
+    // Called by the exception handlers once they have reported a fault.
+    // Ends the current task if it is the one being probed.
+    pub fn catch(exception: &'static str, address: u64) {
+        if PROBED_TASK.load(Ordering::Relaxed) == task::current_task_id() {
+            *CAUGHT.lock() = Some((exception, address));
+            task::exit();
+        }
+    }
+}
+
+// Pushing the exception frame onto a non-canonical stack pointer faults
+// again, and so does delivering that fault, which the CPU escalates to a
+// double fault. Its handler has a stack of its own and still runs.
+#[test_case]
+fn a_fault_with_no_usable_stack_is_a_double_fault() {
+    fn fault_without_a_stack() {
+        unsafe {
+            core::arch::asm!("mov rsp, {}", "ud2", in(reg) 0x8000_0000_0000u64, options(noreturn));
+        }
+    }
+    let caught = fault_probe::run(fault_without_a_stack);
+    assert_eq!(caught.map(|(exception, _)| exception), Some("double fault"));
+}
+
+#[test_case]
+fn a_page_fault_reports_the_faulting_address() {
+    const UNMAPPED: u64 = 0x_5555_dead_beef;
+    fn read_unmapped() {
+        unsafe { core::ptr::read_volatile(UNMAPPED as *const u8) };
+    }
+    assert_eq!(memory::is_mapped(VirtAddr::new(UNMAPPED)), Some(false));
+    assert_eq!(fault_probe::run(read_unmapped), Some(("page fault", UNMAPPED)));
+    let reported = format!("Accessed Address: {:?}", VirtAddr::new(UNMAPPED));
+    assert!(log::records().iter().any(|record| record.text == reported));
+}
+
+#[test_case]
+fn overflowing_a_task_stack_hits_its_guard_page() {
+    fn recurse(depth: u64) -> u64 {
+        // Volatile so neither the frame nor the recursion is optimized away.
+        let depth = volatile::Volatile::new(depth);
+        if depth.read() == u64::MAX {
+            return 0;
+        }
+        recurse(depth.read() + 1) + depth.read()
+    }
+    fn overflow() {
+        recurse(0);
+    }
+    let (exception, address) = fault_probe::run(overflow).unwrap();
+    assert_eq!(exception, "page fault");
+    assert!(memory::is_stack_guard(VirtAddr::new(address)));
+    let reported = "Stack overflow: hit the guard page below a task stack";
+    assert!(log::records().iter().any(|record| record.text == reported));
+}
+
+#[test_case]
+fn executing_from_a_data_page_is_an_instruction_fetch_fault() {
+    use x86_64::structures::paging::PageTableFlags;
+    static CODE: AtomicUsize = AtomicUsize::new(0);
+    if !memory::Access::ReadWrite.flags().contains(PageTableFlags::NO_EXECUTE) {
+        serial_print!("(NX not supported, skipped) ");
+        return;
+    }
+    // A `ret`, which would return at once if it could run, on the heap,
+    // which is mapped through map_region as plain data.
+    let code = Box::new(0xC3u8);
+    let address = &*code as *const u8 as u64;
+    let data = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    assert!(memory::is_range_mapped(VirtAddr::new(address), 1, data));
+    CODE.store(address as usize, Ordering::SeqCst);
+    fn jump_to_data() {
+        let code: fn() = unsafe { core::mem::transmute(CODE.load(Ordering::SeqCst)) };
+        code();
+    }
+    assert_eq!(fault_probe::run(jump_to_data), Some(("page fault", address)));
+    assert!(log::records().iter().any(|record| record.text.ends_with("in kernel mode (instruction fetch)")));
+}
+
+#[test_case]
+fn bad_instructions_reach_their_handlers() {
+    fn invalid_opcode() {
+        unsafe { core::arch::asm!("ud2") };
+    }
+    fn divide_by_zero() {
+        unsafe {
+            core::arch::asm!("xor ecx, ecx", "div ecx", out("eax") _, out("ecx") _, out("edx") _);
+        }
+    }
+    fn non_canonical_read() {
+        unsafe { core::ptr::read_volatile(0x8000_0000_0000 as *const u8) };
+    }
+    let exception = |f| fault_probe::run(f).map(|(exception, _)| exception);
+    assert_eq!(exception(invalid_opcode), Some("invalid opcode"));
+    assert_eq!(exception(divide_by_zero), Some("divide error"));
+    assert_eq!(exception(non_canonical_read), Some("general protection fault"));
+}
+
+#[test_case]
+fn autoexec_runs_before_the_first_prompt() {
+    let mut fs = FileSystem::format(Box::new(RamDisk::new(RAMDISK_BLOCKS))).unwrap();
+    fs.create_file(AUTOEXEC_PATH, b"nosuchcommand\necho booted > /ran\n").unwrap();
+    let mut shell = ShellContext::new(fs, commands::registry());
+    run_autoexec(&mut shell);
+    assert_eq!(shell.fs.read_file("/ran").unwrap(), b"booted\n");
+
+    // Without the file, booting carries on with nothing run.
+    let fs = FileSystem::format(Box::new(RamDisk::new(RAMDISK_BLOCKS))).unwrap();
+    let mut shell = ShellContext::new(fs, commands::registry());
+    run_autoexec(&mut shell);
+    assert_eq!(shell.status, shell::STATUS_SUCCESS);
 }
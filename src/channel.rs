@@ -0,0 +1,184 @@
+// Bounded multi-producer, single-consumer channels between tasks. Messages
+// are queued in a ring buffer on the heap; a sender waits off the CPU while
+// it is full and the receiver while it is empty. Not for interrupt handlers,
+// which can't wait.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::task;
+
+// The receiver is gone; the message comes back unsent.
+#[derive(Debug)]
+pub struct SendError<T>(pub T);
+
+// Every sender is gone and nothing is left to receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct State<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool,
+    // Ids of senders waiting for room, longest waiting first.
+    waiting_senders: VecDeque<usize>,
+    waiting_receiver: Option<usize>,
+}
+
+type Shared<T> = Arc<Mutex<State<T>>>;
+
+pub struct Sender<T> {
+    shared: Shared<T>,
+}
+
+pub struct Receiver<T> {
+    shared: Shared<T>,
+}
+
+// `capacity` must be at least 1.
+pub fn channel<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be at least 1");
+    let shared = Arc::new(Mutex::new(State {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity,
+        senders: 1,
+        receiver_alive: true,
+        waiting_senders: VecDeque::new(),
+        waiting_receiver: None,
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T: Send> Sender<T> {
+    // Queues `value`, waiting for room if the channel is full.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        interrupts::without_interrupts(|| loop {
+            let mut state = self.shared.lock();
+            if !state.receiver_alive {
+                return Err(SendError(value));
+            }
+            if state.buffer.len() < state.capacity {
+                state.buffer.push_back(value);
+                if let Some(id) = state.waiting_receiver.take() {
+                    task::wake(id);
+                }
+                return Ok(());
+            }
+            state.waiting_senders.push_back(task::current_task_id());
+            task::prepare_to_wait();
+            drop(state);
+            task::wait();
+        })
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        interrupts::without_interrupts(|| self.shared.lock().senders += 1);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| {
+            let mut state = self.shared.lock();
+            state.senders -= 1;
+            // The receiver must find out there is nothing more coming.
+            if state.senders == 0 {
+                if let Some(id) = state.waiting_receiver.take() {
+                    task::wake(id);
+                }
+            }
+        })
+    }
+}
+
+impl<T: Send> Receiver<T> {
+    // Takes the oldest message, waiting for one if the channel is empty.
+    // Messages sent before the last sender was dropped are still delivered.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        interrupts::without_interrupts(|| loop {
+            let mut state = self.shared.lock();
+            if let Some(value) = state.buffer.pop_front() {
+                if let Some(id) = state.waiting_senders.pop_front() {
+                    task::wake(id);
+                }
+                return Ok(value);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state.waiting_receiver = Some(task::current_task_id());
+            task::prepare_to_wait();
+            drop(state);
+            task::wait();
+        })
+    }
+
+    // None if the channel is empty or closed.
+    pub fn try_recv(&self) -> Option<T> {
+        interrupts::without_interrupts(|| {
+            let mut state = self.shared.lock();
+            let value = state.buffer.pop_front()?;
+            if let Some(id) = state.waiting_senders.pop_front() {
+                task::wake(id);
+            }
+            Some(value)
+        })
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| {
+            let mut state = self.shared.lock();
+            state.receiver_alive = false;
+            // Waiting senders get their messages back.
+            while let Some(id) = state.waiting_senders.pop_front() {
+                task::wake(id);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use crate::task::tests::spawn;
+
+    // The small capacity makes the producer wait for room as well as the
+    // consumer wait for messages.
+    #[test_case]
+    fn messages_arrive_in_the_order_sent() {
+        const MESSAGES: usize = 100;
+        let (sender, receiver) = channel(4);
+        spawn(move || {
+            for n in 0..MESSAGES {
+                sender.send(n).unwrap();
+            }
+        });
+        let received: Vec<usize> = (0..MESSAGES).map(|_| receiver.recv().unwrap()).collect();
+        assert!(received.iter().copied().eq(0..MESSAGES));
+        // The producer's sender is dropped when it returns.
+        assert_eq!(receiver.recv(), Err(RecvError));
+    }
+
+    #[test_case]
+    fn sending_without_a_receiver_gives_the_message_back() {
+        let (sender, receiver) = channel(1);
+        drop(receiver);
+        assert!(matches!(sender.send(7), Err(SendError(7))));
+    }
+}
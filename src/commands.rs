@@ -0,0 +1,1731 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::shell::{Command, CommandRegistry, ShellContext};
+use crate::shell::{STATUS_FAILURE, STATUS_SUCCESS, STATUS_USAGE};
+use crate::vga_buffer::{self, Color};
+use crate::task::{TaskInfo, SCHEDULER};
+use crate::rand::{self, Rng};
+use crate::keyboard::{self, Layout};
+use crate::editor::{self, Buffer};
+use crate::filesystem::FsError;
+use crate::{acpi, cpu, driver, framebuffer, log, memory, mouse, power, rtc, settings, speaker, time, usermode, watchdog};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::segmentation::{Segment, CS};
+use crate::{println, println_colored};
+
+pub fn registry() -> CommandRegistry {
+    let mut registry = CommandRegistry::new();
+    registry.register(&Help);
+    registry.register(&Clear);
+    registry.register(&Reboot);
+    registry.register(&Shutdown);
+    registry.register(&SyncFs);
+    registry.register(&Ls);
+    registry.register(&Cat);
+    registry.register(&Hexdump);
+    registry.register(&WriteFile);
+    registry.register(&Touch);
+    registry.register(&Edit);
+    registry.register(&Rm);
+    registry.register(&Mv);
+    registry.register(&Cp);
+    registry.register(&Find);
+    registry.register(&Grep);
+    registry.register(&Wc);
+    registry.register(&Stat);
+    registry.register(&Mkdir);
+    registry.register(&Cd);
+    registry.register(&Uptime);
+    registry.register(&Dmesg);
+    registry.register(&Date);
+    registry.register(&Ps);
+    registry.register(&Top);
+    registry.register(&MemInfo);
+    registry.register(&MemTest);
+    registry.register(&LsDrv);
+    registry.register(&CpuInfo);
+    registry.register(&Kill);
+    registry.register(&Nice);
+    registry.register(&SetColor);
+    registry.register(&Keymap);
+    registry.register(&Mouse);
+    registry.register(&Echo);
+    registry.register(&ShowHistory);
+    registry.register(&Set);
+    registry.register(&Get);
+    registry.register(&Run);
+    registry.register(&UserDemo);
+    registry.register(&Sleep);
+    registry.register(&Beep);
+    registry
+}
+
+struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn help(&self) -> &'static str {
+        "help - Show this help message"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        writeln!(ctx.out, "Available commands:");
+        for command in ctx.registry.commands() {
+            writeln!(ctx.out, "  {}", command.help());
+        }
+        STATUS_SUCCESS
+    }
+}
+
+struct Clear;
+
+impl Command for Clear {
+    fn name(&self) -> &'static str {
+        "clear"
+    }
+
+    fn help(&self) -> &'static str {
+        "clear - Clear the screen"
+    }
+
+    fn run(&self, _args: &[&str], _ctx: &mut ShellContext) -> i32 {
+        vga_buffer::clear_screen();
+        STATUS_SUCCESS
+    }
+}
+
+struct Reboot;
+
+impl Command for Reboot {
+    fn name(&self) -> &'static str {
+        "reboot"
+    }
+
+    fn help(&self) -> &'static str {
+        "reboot - Reboot the system"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        sync_before_power_off(ctx);
+        power::reboot();
+    }
+}
+
+struct Shutdown;
+
+impl Command for Shutdown {
+    fn name(&self) -> &'static str {
+        "shutdown"
+    }
+
+    fn help(&self) -> &'static str {
+        "shutdown - Power off the system"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        sync_before_power_off(ctx);
+        power::shutdown();
+        println_colored!(Color::Red, "Shutdown failed: no power-off method worked");
+        STATUS_FAILURE
+    }
+}
+
+// Saves the shell history and writes out unsynced filesystem changes, which
+// would otherwise be lost. A failing disk shouldn't keep the machine from
+// going down, so errors are only reported.
+fn sync_before_power_off(ctx: &mut ShellContext) {
+    if let Err(err) = ctx.history.save(&mut ctx.fs) {
+        println_colored!(Color::Red, "Cannot save history: {}", err);
+    }
+    if let Err(err) = ctx.fs.sync() {
+        println_colored!(Color::Red, "Sync failed: {}", err);
+    }
+}
+
+struct SyncFs;
+
+impl Command for SyncFs {
+    fn name(&self) -> &'static str {
+        "sync"
+    }
+
+    fn help(&self) -> &'static str {
+        "sync - Write cached filesystem changes to disk"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        match ctx.fs.sync() {
+            Ok(blocks) => {
+                writeln!(ctx.out, "{} blocks written", blocks);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Sync failed: {}", err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Ls;
+
+impl Command for Ls {
+    fn name(&self) -> &'static str {
+        "ls"
+    }
+
+    fn help(&self) -> &'static str {
+        "ls [-l] [dir] - List files"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let (long, args) = match args.split_first() {
+            Some((&"-l", rest)) => (true, rest),
+            _ => (false, args),
+        };
+        let dir = ctx.resolve(args.first().copied().unwrap_or("."));
+        let files = match ctx.fs.list_files(Some(&dir)) {
+            Ok(files) => files,
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot list {}: {}", dir, err);
+                return STATUS_FAILURE;
+            }
+        };
+        for file in files {
+            if !long {
+                writeln!(ctx.out, "{}", file);
+                continue;
+            }
+            match ctx.fs.stat(&crate::filesystem::resolve(&dir, &file)) {
+                Ok(meta) => writeln!(ctx.out, "{:>8} {:>10} {}", meta.size, meta.modified_tick, file),
+                Err(_) => writeln!(ctx.out, "{:>8} {:>10} {}", "-", "-", file),
+            }
+        }
+        if long {
+            writeln!(ctx.out, "{} of {} bytes free", ctx.fs.free_space(), ctx.fs.capacity());
+        }
+        STATUS_SUCCESS
+    }
+}
+
+struct Cat;
+
+impl Command for Cat {
+    fn name(&self) -> &'static str {
+        "cat"
+    }
+
+    fn help(&self) -> &'static str {
+        "cat [filename] - Display file contents, or piped input"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let filename = match (args, &ctx.input) {
+            ([filename], _) => filename,
+            ([], Some(input)) => {
+                ctx.out.write_str(input);
+                return STATUS_SUCCESS;
+            }
+            _ => {
+                println!("Usage: cat <filename>");
+                return STATUS_USAGE;
+            }
+        };
+        match ctx.fs.read_file(&ctx.resolve(filename)) {
+            Ok(content) => {
+                // Invalid UTF-8 shows up as replacement glyphs.
+                writeln!(ctx.out, "{}", String::from_utf8_lossy(&content));
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot read {}: {}", filename, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Hexdump;
+
+const HEXDUMP_ROW_LEN: usize = 16;
+
+// One row of a hex dump: the offset, the bytes in hex split into two groups
+// of eight, and the printable ones as ASCII. A short last row is padded so its
+// ASCII column lines up with the rows above.
+fn hexdump_row(offset: usize, bytes: &[u8]) -> String {
+    let mut row = format!("{:08x} ", offset);
+    for i in 0..HEXDUMP_ROW_LEN {
+        if i % 8 == 0 {
+            row.push(' ');
+        }
+        match bytes.get(i) {
+            Some(byte) => row.push_str(&format!("{:02x} ", byte)),
+            None => row.push_str("   "),
+        }
+    }
+    row.push_str(" |");
+    row.extend(bytes.iter().map(|&b| if b == b' ' || b.is_ascii_graphic() { b as char } else { '.' }));
+    row.push('|');
+    row
+}
+
+impl Command for Hexdump {
+    fn name(&self) -> &'static str {
+        "hexdump"
+    }
+
+    fn help(&self) -> &'static str {
+        "hexdump [-n count] [file] - Show the bytes of a file or piped input in hex and ASCII"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let (limit, args) = match args {
+            ["-n", count, rest @ ..] => match count.parse::<usize>() {
+                Ok(count) => (Some(count), rest),
+                Err(_) => {
+                    println!("Usage: hexdump [-n count] [file]");
+                    return STATUS_USAGE;
+                }
+            },
+            // Not a file named -n: the count is missing.
+            ["-n"] => {
+                println!("Usage: hexdump [-n count] [file]");
+                return STATUS_USAGE;
+            }
+            _ => (None, args),
+        };
+        let data = match (args, &ctx.input) {
+            ([filename], _) => match ctx.fs.read_file(&ctx.resolve(filename)) {
+                Ok(data) => data,
+                Err(err) => {
+                    println_colored!(Color::Red, "Cannot read {}: {}", filename, err);
+                    return STATUS_FAILURE;
+                }
+            },
+            ([], Some(input)) => input.as_bytes().to_vec(),
+            _ => {
+                println!("Usage: hexdump [-n count] [file]");
+                return STATUS_USAGE;
+            }
+        };
+        let data = &data[..limit.unwrap_or(data.len()).min(data.len())];
+        for (row, bytes) in data.chunks(HEXDUMP_ROW_LEN).enumerate() {
+            writeln!(ctx.out, "{}", hexdump_row(row * HEXDUMP_ROW_LEN, bytes));
+        }
+        STATUS_SUCCESS
+    }
+}
+
+struct WriteFile;
+
+impl Command for WriteFile {
+    fn name(&self) -> &'static str {
+        "write"
+    }
+
+    fn help(&self) -> &'static str {
+        "write [-a] <filename> <content> - Write content to a file (-a appends a line)"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let (append, rest) = match args.split_first() {
+            Some((&"-a", rest)) => (true, rest),
+            _ => (false, args),
+        };
+        let first = if append { 2 } else { 1 };
+        let (filename, content) = match rest.split_first() {
+            Some((filename, content)) if !content.is_empty() => {
+                (filename, ctx.joined_args(args, first))
+            }
+            _ => {
+                println!("Usage: write [-a] <filename> <content>");
+                return STATUS_USAGE;
+            }
+        };
+        let path = ctx.resolve(filename);
+        let result = if append {
+            // Start a new line unless the file is empty or already ends one.
+            let needs_newline = ctx
+                .fs
+                .read_file(&path)
+                .is_ok_and(|data| !data.is_empty() && !data.ends_with(b"\n"));
+            let line = if needs_newline {
+                format!("\n{}", content)
+            } else {
+                content
+            };
+            ctx.fs.append_file(&path, line.as_bytes())
+        } else {
+            ctx.fs.create_file(&path, content.as_bytes())
+        };
+        match result {
+            Ok(()) => {
+                println_colored!(Color::Green, "File written: {}", filename);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot write {}: {}", filename, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Touch;
+
+impl Command for Touch {
+    fn name(&self) -> &'static str {
+        "touch"
+    }
+
+    fn help(&self) -> &'static str {
+        "touch <filename> - Create an empty file, or update a file's modified time"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let filename = match args {
+            [filename] => filename,
+            _ => {
+                println!("Usage: touch <filename>");
+                return STATUS_USAGE;
+            }
+        };
+        match ctx.fs.touch(&ctx.resolve(filename)) {
+            Ok(()) => STATUS_SUCCESS,
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot touch {}: {}", filename, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Edit;
+
+impl Command for Edit {
+    fn name(&self) -> &'static str {
+        "edit"
+    }
+
+    fn help(&self) -> &'static str {
+        "edit <filename> - Edit a text file full-screen (Ctrl-S saves, Ctrl-Q quits)"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let filename = match args {
+            [filename] => filename,
+            _ => {
+                println!("Usage: edit <filename>");
+                return STATUS_USAGE;
+            }
+        };
+        if framebuffer::is_enabled() {
+            println_colored!(Color::Red, "edit only works on the VGA text screen");
+            return STATUS_FAILURE;
+        }
+        let path = ctx.resolve(filename);
+        // A new file starts empty and is created on the first save.
+        let buffer = match ctx.fs.read_file(&path) {
+            Ok(data) => match Buffer::from_bytes(&data) {
+                Some(buffer) => buffer,
+                None => {
+                    println_colored!(Color::Red, "Cannot edit {}: not a text file", filename);
+                    return STATUS_FAILURE;
+                }
+            },
+            Err(FsError::NotFound) => Buffer::new(),
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot read {}: {}", filename, err);
+                return STATUS_FAILURE;
+            }
+        };
+        editor::run(&mut ctx.fs, &path, buffer);
+        STATUS_SUCCESS
+    }
+}
+
+struct Rm;
+
+impl Command for Rm {
+    fn name(&self) -> &'static str {
+        "rm"
+    }
+
+    fn help(&self) -> &'static str {
+        "rm <filename> - Delete a file"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let filename = match args {
+            [filename] => filename,
+            _ => {
+                println!("Usage: rm <filename>");
+                return STATUS_USAGE;
+            }
+        };
+        let path = ctx.resolve(filename);
+        match ctx.fs.delete_file(&path) {
+            Ok(()) => {
+                println_colored!(Color::Green, "File deleted: {}", filename);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot delete {}: {}", filename, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Mv;
+
+impl Command for Mv {
+    fn name(&self) -> &'static str {
+        "mv"
+    }
+
+    fn help(&self) -> &'static str {
+        "mv <from> <to> - Rename or move a file or directory"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let (from, to) = match args {
+            [from, to] => (from, to),
+            _ => {
+                println!("Usage: mv <from> <to>");
+                return STATUS_USAGE;
+            }
+        };
+        let from_path = ctx.resolve(from);
+        let to_path = ctx.resolve(to);
+        match ctx.fs.rename(&from_path, &to_path) {
+            Ok(()) => {
+                println_colored!(Color::Green, "Moved {} to {}", from, to);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot move {} to {}: {}", from, to, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Cp;
+
+impl Command for Cp {
+    fn name(&self) -> &'static str {
+        "cp"
+    }
+
+    fn help(&self) -> &'static str {
+        "cp <from> <to> - Copy a file"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let (from, to) = match args {
+            [from, to] => (from, to),
+            _ => {
+                println!("Usage: cp <from> <to>");
+                return STATUS_USAGE;
+            }
+        };
+        let from_path = ctx.resolve(from);
+        let to_path = ctx.resolve(to);
+        match ctx.fs.copy(&from_path, &to_path) {
+            Ok(()) => {
+                println_colored!(Color::Green, "Copied {} to {}", from, to);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot copy {} to {}: {}", from, to, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Find;
+
+impl Command for Find {
+    fn name(&self) -> &'static str {
+        "find"
+    }
+
+    fn help(&self) -> &'static str {
+        "find <pattern> - Find files by name, with * and ? wildcards"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let pattern = match args {
+            [pattern] => pattern,
+            _ => {
+                println!("Usage: find <pattern>");
+                return STATUS_USAGE;
+            }
+        };
+        for path in ctx.fs.find(pattern) {
+            writeln!(ctx.out, "{}", path);
+        }
+        STATUS_SUCCESS
+    }
+}
+
+struct Grep;
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+// Returns the 1-based numbers and contents of the lines containing `pattern`.
+// A final newline ends the last line rather than starting an empty one.
+fn matching_lines<'a>(data: &'a [u8], pattern: &[u8]) -> Vec<(usize, &'a [u8])> {
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    data.split(|&b| b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| contains(line, pattern))
+        .map(|(index, line)| (index + 1, line))
+        .collect()
+}
+
+impl Command for Grep {
+    fn name(&self) -> &'static str {
+        "grep"
+    }
+
+    fn help(&self) -> &'static str {
+        "grep [-n] <pattern> [file] - Print lines of a file or piped input containing pattern"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let (numbered, args) = match args.split_first() {
+            Some((&"-n", rest)) => (true, rest),
+            _ => (false, args),
+        };
+        let data = match (args, &ctx.input) {
+            ([_, filename], _) => match ctx.fs.read_file(&ctx.resolve(filename)) {
+                Ok(data) => data,
+                Err(err) => {
+                    println_colored!(Color::Red, "Cannot read {}: {}", filename, err);
+                    return STATUS_FAILURE;
+                }
+            },
+            ([_], Some(input)) => input.as_bytes().to_vec(),
+            _ => {
+                println!("Usage: grep [-n] <pattern> [file]");
+                return STATUS_USAGE;
+            }
+        };
+        let pattern = args[0];
+        let lines = matching_lines(&data, pattern.as_bytes());
+        for &(number, line) in &lines {
+            let line = String::from_utf8_lossy(line);
+            if numbered {
+                writeln!(ctx.out, "{}:{}", number, line);
+            } else {
+                writeln!(ctx.out, "{}", line);
+            }
+        }
+        // As in Unix, finding nothing is a failure.
+        if lines.is_empty() {
+            STATUS_FAILURE
+        } else {
+            STATUS_SUCCESS
+        }
+    }
+}
+
+struct Wc;
+
+// Lines, words and bytes. Words are runs of anything but ASCII whitespace,
+// and a last line without a newline still counts.
+fn count_words(data: &[u8]) -> (usize, usize, usize) {
+    let mut lines = data.iter().filter(|&&b| b == b'\n').count();
+    if data.last().is_some_and(|&b| b != b'\n') {
+        lines += 1;
+    }
+    let words = data
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .count();
+    (lines, words, data.len())
+}
+
+impl Command for Wc {
+    fn name(&self) -> &'static str {
+        "wc"
+    }
+
+    fn help(&self) -> &'static str {
+        "wc [-l] [-w] [-c] [file] - Count the lines, words and bytes of a file or piped input"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let flags = args.iter().take_while(|arg| arg.starts_with('-')).count();
+        let (flags, args) = args.split_at(flags);
+        let (mut lines, mut words, mut bytes) = (false, false, false);
+        for flag in flags {
+            match *flag {
+                "-l" => lines = true,
+                "-w" => words = true,
+                "-c" => bytes = true,
+                _ => {
+                    println!("Usage: wc [-l] [-w] [-c] [file]");
+                    return STATUS_USAGE;
+                }
+            }
+        }
+        // With no flags, all three are shown.
+        if flags.is_empty() {
+            lines = true;
+            words = true;
+            bytes = true;
+        }
+        let (data, filename) = match (args, &ctx.input) {
+            ([filename], _) => match ctx.fs.read_file(&ctx.resolve(filename)) {
+                Ok(data) => (data, Some(*filename)),
+                Err(err) => {
+                    println_colored!(Color::Red, "Cannot read {}: {}", filename, err);
+                    return STATUS_FAILURE;
+                }
+            },
+            ([], Some(input)) => (input.as_bytes().to_vec(), None),
+            _ => {
+                println!("Usage: wc [-l] [-w] [-c] [file]");
+                return STATUS_USAGE;
+            }
+        };
+        let counts = count_words(&data);
+        let mut columns = Vec::new();
+        for (shown, count) in [(lines, counts.0), (words, counts.1), (bytes, counts.2)].iter() {
+            if *shown {
+                columns.push(format!("{:>7}", count));
+            }
+        }
+        if let Some(filename) = filename {
+            columns.push(String::from(filename));
+        }
+        writeln!(ctx.out, "{}", columns.join(" "));
+        STATUS_SUCCESS
+    }
+}
+
+struct Stat;
+
+impl Command for Stat {
+    fn name(&self) -> &'static str {
+        "stat"
+    }
+
+    fn help(&self) -> &'static str {
+        "stat <filename> - Show file size and timestamps"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let filename = match args {
+            [filename] => filename,
+            _ => {
+                println!("Usage: stat <filename>");
+                return STATUS_USAGE;
+            }
+        };
+        match ctx.fs.stat(&ctx.resolve(filename)) {
+            Ok(meta) => {
+                writeln!(ctx.out, "File: {}", filename);
+                writeln!(ctx.out, "Size: {} bytes", meta.size);
+                writeln!(ctx.out, "Created: tick {}", meta.created_tick);
+                writeln!(ctx.out, "Modified: tick {}", meta.modified_tick);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot stat {}: {}", filename, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Mkdir;
+
+impl Command for Mkdir {
+    fn name(&self) -> &'static str {
+        "mkdir"
+    }
+
+    fn help(&self) -> &'static str {
+        "mkdir <path> - Create a directory"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let path = match args {
+            [path] => path,
+            _ => {
+                println!("Usage: mkdir <path>");
+                return STATUS_USAGE;
+            }
+        };
+        let target = ctx.resolve(path);
+        match ctx.fs.mkdir(&target) {
+            Ok(()) => {
+                println_colored!(Color::Green, "Directory created: {}", path);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot create directory {}: {}", path, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Cd;
+
+impl Command for Cd {
+    fn name(&self) -> &'static str {
+        "cd"
+    }
+
+    fn help(&self) -> &'static str {
+        "cd <path> - Change the current directory"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let path = args.first().copied().unwrap_or("/");
+        let target = ctx.resolve(path);
+        if ctx.fs.is_dir(&target) {
+            ctx.cwd = target;
+            STATUS_SUCCESS
+        } else {
+            println_colored!(Color::Red, "Directory not found: {}", path);
+            STATUS_FAILURE
+        }
+    }
+}
+
+struct Uptime;
+
+impl Command for Uptime {
+    fn name(&self) -> &'static str {
+        "uptime"
+    }
+
+    fn help(&self) -> &'static str {
+        "uptime - Show time since boot"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        writeln!(ctx.out, "Up {} ms", time::uptime_ms());
+        STATUS_SUCCESS
+    }
+}
+
+struct Dmesg;
+
+impl Command for Dmesg {
+    fn name(&self) -> &'static str {
+        "dmesg"
+    }
+
+    fn help(&self) -> &'static str {
+        "dmesg - Show recent kernel log messages at or above the log level"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let min_level = log::min_level();
+        for record in log::records().into_iter().filter(|record| record.level >= min_level) {
+            let line = format!("[{:>5}.{:03}] {}\n", record.timestamp_ms / 1000, record.timestamp_ms % 1000, record.text);
+            match record.level.color() {
+                Some(color) => ctx.out.write_colored(color, format_args!("{}", line)),
+                None => ctx.out.write_str(&line),
+            }
+        }
+        STATUS_SUCCESS
+    }
+}
+
+struct Date;
+
+impl Command for Date {
+    fn name(&self) -> &'static str {
+        "date"
+    }
+
+    fn help(&self) -> &'static str {
+        "date - Show the current date and time"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        writeln!(ctx.out, "{}", rtc::read_datetime());
+        STATUS_SUCCESS
+    }
+}
+
+struct Ps;
+
+impl Command for Ps {
+    fn name(&self) -> &'static str {
+        "ps"
+    }
+
+    fn help(&self) -> &'static str {
+        "ps - List tasks"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let tasks = interrupts::without_interrupts(|| SCHEDULER.lock().task_states());
+        writeln!(ctx.out, "{:>4}  {:>4}  {:>8}  STATE", "ID", "PRIO", "CPU MS");
+        for task in tasks {
+            // Task 0 is the kernel's boot context, which runs the shell.
+            let note = if task.id == 0 { "  (shell)" } else { "" };
+            let cpu_ms = task.cpu_ticks * 1000 / time::PIT_FREQUENCY_HZ;
+            writeln!(
+                ctx.out,
+                "{:>4}  {:>4}  {:>8}  {}{}",
+                task.id,
+                task.priority,
+                cpu_ms,
+                task.state.name(),
+                note
+            );
+        }
+        STATUS_SUCCESS
+    }
+}
+
+// How often top redraws, and how often it looks for a key in between.
+const TOP_REFRESH_MS: usize = 1000;
+const TOP_POLL_MS: usize = 50;
+
+struct Top;
+
+impl Command for Top {
+    fn name(&self) -> &'static str {
+        "top"
+    }
+
+    fn help(&self) -> &'static str {
+        "top - Show tasks and their CPU use until a key is pressed"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        // The first screen shows each task's share since accounting started,
+        // later ones the share since the previous screen. Redirected, top
+        // writes the one screen without clearing the display.
+        let redirected = ctx.out.is_captured();
+        let mut previous: (usize, Vec<TaskInfo>) = (0, Vec::new());
+        watchdog::paused(|| loop {
+            let (total, tasks) = interrupts::without_interrupts(|| {
+                let scheduler = SCHEDULER.lock();
+                (scheduler.total_ticks(), scheduler.task_states())
+            });
+            let elapsed = total.saturating_sub(previous.0).max(1);
+            if !redirected {
+                vga_buffer::clear_screen();
+            }
+            writeln!(
+                ctx.out,
+                "up {} s, {} tasks. Press any key to quit.",
+                time::uptime_ms() / 1000,
+                tasks.len()
+            );
+            writeln!(ctx.out, "{:>4}  {:>4}  {:<8}  {:>4}", "ID", "PRIO", "STATE", "CPU%");
+            for task in &tasks {
+                let before = previous
+                    .1
+                    .iter()
+                    .find(|old| old.id == task.id)
+                    .map_or(0, |old| old.cpu_ticks);
+                let share = task.cpu_ticks.saturating_sub(before) * 100 / elapsed;
+                writeln!(
+                    ctx.out,
+                    "{:>4}  {:>4}  {:<8}  {:>3}%",
+                    task.id,
+                    task.priority,
+                    task.state.name(),
+                    share
+                );
+            }
+            if redirected {
+                return;
+            }
+            previous = (total, tasks);
+
+            for _ in 0..TOP_REFRESH_MS / TOP_POLL_MS {
+                if keyboard::try_read_char().is_some() {
+                    return;
+                }
+                time::sleep(time::ms_to_ticks(TOP_POLL_MS));
+            }
+        });
+        STATUS_SUCCESS
+    }
+}
+
+struct MemInfo;
+
+impl Command for MemInfo {
+    fn name(&self) -> &'static str {
+        "meminfo"
+    }
+
+    fn help(&self) -> &'static str {
+        "meminfo - Show physical memory and heap usage"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let stats = memory::stats();
+        writeln!(
+            ctx.out,
+            "Frames: {} total, {} used, {} free ({} KiB free)",
+            stats.total_frames,
+            stats.allocated_frames,
+            stats.free_frames(),
+            stats.free_frames() * 4,
+        );
+        writeln!(
+            ctx.out,
+            "Heap:   {} bytes used, {} bytes free",
+            stats.heap_used, stats.heap_free
+        );
+        writeln!(
+            ctx.out,
+            "Files:  {} bytes used, {} bytes free",
+            ctx.fs.used_space(),
+            ctx.fs.free_space()
+        );
+        STATUS_SUCCESS
+    }
+}
+
+struct MemTest;
+
+const MEMTEST_DEFAULT_BLOCKS: usize = 64;
+const MEMTEST_MAX_BLOCK: usize = 1024;
+
+// Allocates `count` randomly sized blocks, each filled with a byte derived
+// from its index, then frees them in random order, checking each block's
+// contents as it goes. Returns a description of the first failure. Blocks
+// still held on an early return are freed when `blocks` is dropped.
+fn run_memtest(count: usize, seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let mut blocks: Vec<(usize, Vec<u8>)> = Vec::new();
+    blocks
+        .try_reserve_exact(count)
+        .map_err(|_| format!("cannot allocate the table for {} blocks", count))?;
+    for index in 0..count {
+        let size = rng.next_range(1, MEMTEST_MAX_BLOCK as u64 + 1) as usize;
+        let mut block = Vec::new();
+        block
+            .try_reserve_exact(size)
+            .map_err(|_| format!("allocation {} of {} bytes failed", index, size))?;
+        block.resize(size, index as u8);
+        blocks.push((index, block));
+    }
+    while !blocks.is_empty() {
+        let victim = rng.next_range(0, blocks.len() as u64) as usize;
+        let (index, block) = blocks.swap_remove(victim);
+        if block.iter().any(|&b| b != index as u8) {
+            return Err(format!("block {} was corrupted", index));
+        }
+    }
+    Ok(())
+}
+
+impl Command for MemTest {
+    fn name(&self) -> &'static str {
+        "memtest"
+    }
+
+    fn help(&self) -> &'static str {
+        "memtest [blocks] - Stress the heap with random allocations"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let count = match args {
+            [] => MEMTEST_DEFAULT_BLOCKS,
+            [count] => match count.parse::<usize>() {
+                Ok(count) => count,
+                Err(_) => {
+                    println_colored!(Color::Red, "Invalid block count: {}", count);
+                    return STATUS_FAILURE;
+                }
+            },
+            _ => {
+                println!("Usage: memtest [blocks]");
+                return STATUS_USAGE;
+            }
+        };
+        let before = memory::stats().heap_used;
+        let (result, cycles) = time::measure(|| run_memtest(count, rand::next_u64()));
+        let after = memory::stats().heap_used;
+        let status = match result {
+            Ok(()) => {
+                println_colored!(Color::Green, "memtest: {} blocks ok in {} cycles", count, cycles);
+                STATUS_SUCCESS
+            }
+            Err(err) => {
+                println_colored!(Color::Red, "memtest failed: {}", err);
+                STATUS_FAILURE
+            }
+        };
+        writeln!(ctx.out, "Heap used: {} bytes before, {} bytes after", before, after);
+        status
+    }
+}
+
+struct LsDrv;
+
+impl Command for LsDrv {
+    fn name(&self) -> &'static str {
+        "lsdrv"
+    }
+
+    fn help(&self) -> &'static str {
+        "lsdrv - Show driver initialization status"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        for status in driver::statuses() {
+            let state = if status.ok { "ok" } else { "FAILED" };
+            writeln!(ctx.out, "{:<10} {:<6} {}", status.name, state, status.detail);
+        }
+        STATUS_SUCCESS
+    }
+}
+
+struct CpuInfo;
+
+impl Command for CpuInfo {
+    fn name(&self) -> &'static str {
+        "cpuinfo"
+    }
+
+    fn help(&self) -> &'static str {
+        "cpuinfo - Show the CPU vendor, model, features and processors"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let info = &*cpu::CPU_INFO;
+        writeln!(ctx.out, "Vendor:   {}", info.vendor());
+        writeln!(ctx.out, "Model:    {}", info.brand().unwrap_or("(not reported)"));
+        write!(ctx.out, "Features:");
+        for (name, present) in info.features.flags() {
+            if present {
+                write!(ctx.out, " {}", name);
+            }
+        }
+        writeln!(ctx.out);
+        let madt = match acpi::madt() {
+            Some(madt) => madt,
+            None => {
+                writeln!(ctx.out, "CPUs:     1 (no ACPI MADT)");
+                return STATUS_SUCCESS;
+            }
+        };
+        writeln!(ctx.out, "CPUs:     {}", acpi::cpu_count());
+        for apic in &madt.local_apics {
+            let state = if apic.enabled { "" } else { " (offline)" };
+            writeln!(ctx.out, "  processor {}: APIC ID {}{}", apic.processor_id, apic.apic_id, state);
+        }
+        writeln!(ctx.out, "Local APIC at {:#x}", madt.local_apic_address);
+        for io_apic in &madt.io_apics {
+            writeln!(
+                ctx.out,
+                "I/O APIC {} at {:#x}, GSI base {}",
+                io_apic.id, io_apic.address, io_apic.gsi_base
+            );
+        }
+        STATUS_SUCCESS
+    }
+}
+
+struct Kill;
+
+impl Command for Kill {
+    fn name(&self) -> &'static str {
+        "kill"
+    }
+
+    fn help(&self) -> &'static str {
+        "kill <id> - Stop a task"
+    }
+
+    fn run(&self, args: &[&str], _ctx: &mut ShellContext) -> i32 {
+        let id = match args {
+            [id] => match id.parse::<usize>() {
+                Ok(id) => id,
+                Err(_) => {
+                    println_colored!(Color::Red, "Invalid task id: {}", id);
+                    return STATUS_FAILURE;
+                }
+            },
+            _ => {
+                println!("Usage: kill <id>");
+                return STATUS_USAGE;
+            }
+        };
+        let (killed, current) = interrupts::without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            (scheduler.kill(id), scheduler.current_task_id())
+        });
+        if killed {
+            println_colored!(Color::Green, "Task {} killed", id);
+            return STATUS_SUCCESS;
+        }
+        if id == current {
+            println_colored!(Color::Red, "Cannot kill the running task {}", id);
+        } else {
+            println_colored!(Color::Red, "No such task: {}", id);
+        }
+        STATUS_FAILURE
+    }
+}
+
+struct Nice;
+
+impl Command for Nice {
+    fn name(&self) -> &'static str {
+        "nice"
+    }
+
+    fn help(&self) -> &'static str {
+        "nice <id> <priority> - Set a task's priority (0-255)"
+    }
+
+    fn run(&self, args: &[&str], _ctx: &mut ShellContext) -> i32 {
+        let (id, priority) = match args {
+            [id, priority] => match (id.parse::<usize>(), priority.parse::<u8>()) {
+                (Ok(id), Ok(priority)) => (id, priority),
+                _ => {
+                    println_colored!(Color::Red, "Invalid task id or priority");
+                    return STATUS_FAILURE;
+                }
+            },
+            _ => {
+                println!("Usage: nice <id> <priority>");
+                return STATUS_USAGE;
+            }
+        };
+        if interrupts::without_interrupts(|| SCHEDULER.lock().set_priority(id, priority)) {
+            println_colored!(Color::Green, "Task {} priority set to {}", id, priority);
+            STATUS_SUCCESS
+        } else {
+            println_colored!(Color::Red, "No such task: {}", id);
+            STATUS_FAILURE
+        }
+    }
+}
+
+struct SetColor;
+
+impl Command for SetColor {
+    fn name(&self) -> &'static str {
+        "color"
+    }
+
+    fn help(&self) -> &'static str {
+        "color <fg> <bg> - Set the text color"
+    }
+
+    fn run(&self, args: &[&str], _ctx: &mut ShellContext) -> i32 {
+        let (fg, bg) = match args {
+            [fg, bg] => (fg, bg),
+            _ => {
+                println!("Usage: color <fg> <bg>");
+                return STATUS_USAGE;
+            }
+        };
+        match (Color::from_name(fg), Color::from_name(bg)) {
+            (Some(fg), Some(bg)) => {
+                vga_buffer::set_color(fg, bg);
+                STATUS_SUCCESS
+            }
+            (None, _) => {
+                println_colored!(Color::Red, "Unknown color: {}", fg);
+                STATUS_FAILURE
+            }
+            (_, None) => {
+                println_colored!(Color::Red, "Unknown color: {}", bg);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Keymap;
+
+impl Command for Keymap {
+    fn name(&self) -> &'static str {
+        "keymap"
+    }
+
+    fn help(&self) -> &'static str {
+        "keymap [layout] - Show or set the keyboard layout"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        match args {
+            [] => {
+                let current = keyboard::layout();
+                for layout in Layout::ALL {
+                    let marker = if layout == current { "*" } else { " " };
+                    writeln!(ctx.out, "{} {}", marker, layout.name());
+                }
+                STATUS_SUCCESS
+            }
+            [name] => match Layout::from_name(name) {
+                Some(layout) => {
+                    keyboard::set_layout(layout);
+                    STATUS_SUCCESS
+                }
+                None => {
+                    println_colored!(Color::Red, "Unknown layout: {}", name);
+                    STATUS_FAILURE
+                }
+            },
+            _ => {
+                println!("Usage: keymap [layout]");
+                STATUS_USAGE
+            }
+        }
+    }
+}
+
+struct Mouse;
+
+impl Command for Mouse {
+    fn name(&self) -> &'static str {
+        "mouse"
+    }
+
+    fn help(&self) -> &'static str {
+        "mouse - Show the last mouse packet"
+    }
+
+    fn run(&self, _args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let state = mouse::latest_state();
+        writeln!(
+            ctx.out,
+            "dx={} dy={} left={} right={} middle={}",
+            state.x_delta, state.y_delta, state.left, state.right, state.middle
+        );
+        STATUS_SUCCESS
+    }
+}
+
+struct Echo;
+
+impl Command for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn help(&self) -> &'static str {
+        "echo <text> - Print text"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        writeln!(ctx.out, "{}", ctx.joined_args(args, 0));
+        STATUS_SUCCESS
+    }
+}
+
+struct ShowHistory;
+
+impl Command for ShowHistory {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn help(&self) -> &'static str {
+        "history [-c] - Show the command history, or clear it"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        match args {
+            [] => {
+                for (index, entry) in ctx.history.iter().enumerate() {
+                    writeln!(ctx.out, "{:>4}  {}", index + 1, entry);
+                }
+                STATUS_SUCCESS
+            }
+            ["-c"] => {
+                ctx.history.clear();
+                STATUS_SUCCESS
+            }
+            _ => {
+                println!("Usage: history [-c]");
+                STATUS_USAGE
+            }
+        }
+    }
+}
+
+struct Set;
+
+impl Command for Set {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn help(&self) -> &'static str {
+        "set [key value] - Change a setting (prompt, color, timeslice, watchdog, loglevel, logkeep), or list them"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        match args.split_first() {
+            None => {
+                for (key, value) in settings::all() {
+                    writeln!(ctx.out, "{}={}", key, value);
+                }
+                STATUS_SUCCESS
+            }
+            Some((key, value)) if !value.is_empty() => match settings::set(key, &ctx.joined_args(args, 1)) {
+                Ok(()) => STATUS_SUCCESS,
+                Err(err) => {
+                    println_colored!(Color::Red, "Cannot set {}: {}", key, err);
+                    STATUS_FAILURE
+                }
+            },
+            Some(_) => {
+                println!("Usage: set <key> <value>");
+                STATUS_USAGE
+            }
+        }
+    }
+}
+
+struct Get;
+
+impl Command for Get {
+    fn name(&self) -> &'static str {
+        "get"
+    }
+
+    fn help(&self) -> &'static str {
+        "get <key> - Show a setting, or the last exit status with ?"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let key = match args {
+            [key] => key,
+            _ => {
+                println!("Usage: get <key>");
+                return STATUS_USAGE;
+            }
+        };
+        // The last command's exit status, like $? in Unix shells.
+        if *key == "?" {
+            writeln!(ctx.out, "{}", ctx.status);
+            return STATUS_SUCCESS;
+        }
+        match settings::get(key) {
+            Some(value) => {
+                writeln!(ctx.out, "{}", value);
+                STATUS_SUCCESS
+            }
+            None => {
+                println_colored!(Color::Red, "Not set: {}", key);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+struct Run;
+
+impl Command for Run {
+    fn name(&self) -> &'static str {
+        "run"
+    }
+
+    fn help(&self) -> &'static str {
+        "run [-e] <filename> - Run the commands in a file, stopping at the first failure with -e"
+    }
+
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+        let (stop_on_error, filename) = match args {
+            ["-e", filename] => (true, filename),
+            [filename] => (false, filename),
+            _ => {
+                println!("Usage: run [-e] <filename>");
+                return STATUS_USAGE;
+            }
+        };
+        let path = ctx.resolve(filename);
+        match ctx.run_script(&path, stop_on_error) {
+            Ok(status) => status,
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot run {}: {}", filename, err);
+                STATUS_FAILURE
+            }
+        }
+    }
+}
+
+// How often userdemo checks whether its task has finished.
+const USER_POLL_MS: usize = 10;
+
+struct UserDemo;
+
+impl Command for UserDemo {
+    fn name(&self) -> &'static str {
+        "userdemo"
+    }
+
+    fn help(&self) -> &'static str {
+        "userdemo - Run a small program in ring 3 and wait for it to exit"
+    }
+
+    fn run(&self, args: &[&str], _ctx: &mut ShellContext) -> i32 {
+        if !args.is_empty() {
+            println!("Usage: userdemo");
+            return STATUS_USAGE;
+        }
+        let id = match usermode::spawn_hello() {
+            Ok(id) => id,
+            Err(err) => {
+                println_colored!(Color::Red, "Cannot start user task: {}", err);
+                return STATUS_FAILURE;
+            }
+        };
+        println!("User task {} started", id);
+        while interrupts::without_interrupts(|| SCHEDULER.lock().task_ids().contains(&id)) {
+            if keyboard::try_read_char() == Some(keyboard::KEY_CTRL_C) {
+                interrupts::without_interrupts(|| SCHEDULER.lock().kill(id));
+                println_colored!(Color::Red, "User task {} killed", id);
+                return STATUS_FAILURE;
+            }
+            time::sleep(time::ms_to_ticks(USER_POLL_MS));
+        }
+        let ring = CS::get_reg().rpl() as u8;
+        println_colored!(Color::Green, "User task {} exited; shell running in ring {}", id, ring);
+        STATUS_SUCCESS
+    }
+}
+
+struct Sleep;
+
+impl Command for Sleep {
+    fn name(&self) -> &'static str {
+        "sleep"
+    }
+
+    fn help(&self) -> &'static str {
+        "sleep <ms> - Pause for a number of milliseconds"
+    }
+
+    fn run(&self, args: &[&str], _ctx: &mut ShellContext) -> i32 {
+        let ms = match args {
+            // u32 keeps the conversion to ticks from overflowing.
+            [ms] => match ms.parse::<u32>() {
+                Ok(ms) => ms as usize,
+                Err(_) => {
+                    println_colored!(Color::Red, "Invalid duration: {}", ms);
+                    return STATUS_FAILURE;
+                }
+            },
+            _ => {
+                println!("Usage: sleep <ms>");
+                return STATUS_USAGE;
+            }
+        };
+        watchdog::paused(|| time::sleep(time::ms_to_ticks(ms)));
+        STATUS_SUCCESS
+    }
+}
+
+struct Beep;
+
+impl Command for Beep {
+    fn name(&self) -> &'static str {
+        "beep"
+    }
+
+    fn help(&self) -> &'static str {
+        "beep <freq> <ms> - Sound the PC speaker at freq Hz"
+    }
+
+    fn run(&self, args: &[&str], _ctx: &mut ShellContext) -> i32 {
+        let (freq, ms) = match args {
+            [freq, ms] => match (freq.parse::<u32>(), ms.parse::<u32>()) {
+                (Ok(freq), Ok(ms)) => (freq, ms as usize),
+                _ => {
+                    println_colored!(Color::Red, "Invalid frequency or duration");
+                    return STATUS_FAILURE;
+                }
+            },
+            _ => {
+                println!("Usage: beep <freq> <ms>");
+                return STATUS_USAGE;
+            }
+        };
+        if watchdog::paused(|| speaker::beep(freq, ms)) {
+            STATUS_SUCCESS
+        } else {
+            println_colored!(Color::Red, "Frequency out of range: {} Hz", freq);
+            STATUS_FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::tests::{new_shell, run_captured};
+    use crate::shell::STATUS_NOT_FOUND;
+
+    #[test_case]
+    fn grep_numbers_the_matching_lines() {
+        let data = b"hello\nworld\nyellow\n\nmellow";
+        assert_eq!(matching_lines(data, b"ell"), [(1, &b"hello"[..]), (3, b"yellow"), (5, b"mellow")]);
+        assert_eq!(matching_lines(b"a\n", b"").len(), 1);
+
+        let mut shell = new_shell();
+        shell.fs.create_file("/f", data).unwrap();
+        assert_eq!(run_captured(&mut shell, "grep -n ell f"), "1:hello\n3:yellow\n5:mellow\n");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        assert_eq!(run_captured(&mut shell, "grep xyz f"), "");
+        assert_eq!(shell.status, STATUS_FAILURE);
+    }
+
+    #[test_case]
+    fn lsdrv_shows_working_and_failed_drivers() {
+        driver::record("fakegood", true, "ready");
+        driver::record("fakebad", false, "no such device");
+        let statuses = driver::statuses();
+        let fake = |name| statuses.iter().find(|status| status.name == name).map(|status| status.ok);
+        assert_eq!((fake("fakegood"), fake("fakebad")), (Some(true), Some(false)));
+
+        let mut shell = new_shell();
+        let table = run_captured(&mut shell, "lsdrv");
+        assert!(table.lines().any(|line| line == "fakegood   ok     ready"));
+        assert!(table.lines().any(|line| line == "fakebad    FAILED no such device"));
+    }
+
+    #[test_case]
+    fn memtest_gives_back_everything_it_allocates() {
+        let baseline = memory::stats().heap_used;
+        for seed in 1..4 {
+            assert_eq!(run_memtest(16, seed), Ok(()));
+            assert_eq!(memory::stats().heap_used, baseline);
+        }
+    }
+
+    #[test_case]
+    fn history_lists_and_clears_entries() {
+        let mut shell = new_shell();
+        shell.history.push("ls");
+        shell.history.push("cat a");
+        assert_eq!(run_captured(&mut shell, "history"), "   1  ls\n   2  cat a\n");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        run_captured(&mut shell, "history -c");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        assert_eq!(run_captured(&mut shell, "history"), "");
+        run_captured(&mut shell, "history -x");
+        assert_eq!(shell.status, STATUS_USAGE);
+    }
+
+    #[test_case]
+    fn set_values_come_back_from_get_and_the_prompt() {
+        let mut shell = new_shell();
+        run_captured(&mut shell, "set testkey two  words");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        assert_eq!(run_captured(&mut shell, "get testkey"), "two  words\n");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        run_captured(&mut shell, "get nosuchkey");
+        assert_eq!(shell.status, STATUS_FAILURE);
+        run_captured(&mut shell, "set timeslice soon");
+        assert_eq!(shell.status, STATUS_FAILURE);
+
+        run_captured(&mut shell, "cd /");
+        run_captured(&mut shell, "set prompt [{status}]{cwd}$ ");
+        assert_eq!(shell.prompt(), "[0]/$");
+        run_captured(&mut shell, "get nosuchkey");
+        assert_eq!(shell.prompt(), "[1]/$");
+        settings::set(settings::PROMPT, "{cwd}> ").unwrap();
+    }
+
+    #[test_case]
+    fn commands_report_their_exit_status() {
+        let mut shell = new_shell();
+        shell.fs.create_file("/f", b"x").unwrap();
+        for (line, status) in [
+            ("ls", STATUS_SUCCESS),
+            ("cat f", STATUS_SUCCESS),
+            ("cat missing", STATUS_FAILURE),
+            ("cat", STATUS_USAGE),
+            ("nosuchcommand", STATUS_NOT_FOUND),
+        ] {
+            run_captured(&mut shell, line);
+            assert_eq!(shell.status, status);
+        }
+        run_captured(&mut shell, "cat missing");
+        assert_eq!(run_captured(&mut shell, "get ?"), "1\n");
+    }
+
+    #[test_case]
+    fn sleep_waits_at_least_the_requested_time() {
+        let mut shell = new_shell();
+        let start = time::uptime_ticks();
+        run_captured(&mut shell, "sleep 50");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        assert!(time::uptime_ticks() - start >= time::ms_to_ticks(50));
+        run_captured(&mut shell, "sleep -5");
+        assert_eq!(shell.status, STATUS_FAILURE);
+        run_captured(&mut shell, "sleep soon");
+        assert_eq!(shell.status, STATUS_FAILURE);
+        run_captured(&mut shell, "sleep");
+        assert_eq!(shell.status, STATUS_USAGE);
+    }
+
+    #[test_case]
+    fn dmesg_shows_the_latest_lines_last() {
+        crate::log_info!("dmesg test info");
+        crate::log_warn!("dmesg test warn");
+        let mut shell = new_shell();
+        let output = run_captured(&mut shell, "dmesg");
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[lines.len() - 2].ends_with("] dmesg test info"));
+        assert!(lines[lines.len() - 1].ends_with("] dmesg test warn"));
+        assert!(lines.iter().all(|line| line.starts_with('[')));
+    }
+
+    #[test_case]
+    fn hexdump_rows_pad_a_short_last_row() {
+        const FULL: &str = "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 7f  |Hello, world!...|";
+        const SHORT: &str = "00000010  61 62 63                                          |abc|";
+        assert_eq!(hexdump_row(0, b"Hello, world!\n\x00\x7f"), FULL);
+        assert_eq!(hexdump_row(0x10, b"abc"), SHORT);
+        assert_eq!(SHORT.len(), FULL.len() - 13);
+
+        let mut shell = new_shell();
+        shell.fs.create_file("/f", b"Hello, world!\n\x00\x7fabc").unwrap();
+        assert_eq!(run_captured(&mut shell, "hexdump f"), format!("{}\n{}\n", FULL, SHORT));
+        assert_eq!(run_captured(&mut shell, "hexdump -n 16 f"), format!("{}\n", FULL));
+        run_captured(&mut shell, "hexdump -n");
+        assert_eq!(shell.status, STATUS_USAGE);
+    }
+
+    #[test_case]
+    fn wc_counts_lines_words_and_bytes() {
+        assert_eq!(count_words(b""), (0, 0, 0));
+        assert_eq!(count_words(b"one two\n  three\n"), (2, 3, 16));
+        // The last line counts without its newline.
+        assert_eq!(count_words(b"a\n\nb"), (3, 2, 4));
+        assert_eq!(count_words(b" \t\n"), (1, 0, 3));
+
+        let mut shell = new_shell();
+        shell.fs.create_file("/f", b"one two\n  three\n").unwrap();
+        assert_eq!(run_captured(&mut shell, "wc f"), "      2       3      16 f\n");
+        assert_eq!(run_captured(&mut shell, "wc -l f"), "      2 f\n");
+        assert_eq!(run_captured(&mut shell, "wc -w -c f"), "      3      16 f\n");
+        assert_eq!(run_captured(&mut shell, "echo a b | wc -w"), "      2\n");
+        run_captured(&mut shell, "wc -x f");
+        assert_eq!(shell.status, STATUS_USAGE);
+    }
+}
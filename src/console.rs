@@ -0,0 +1,247 @@
+// Text output on the framebuffer, used in place of the VGA text buffer once a
+// framebuffer is enabled. Characters are drawn from the font module into a
+// grid of GLYPH_WIDTH x GLYPH_HEIGHT cells, using the VGA text colors.
+
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::cp437;
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::framebuffer::{self, Framebuffer};
+use crate::vga_buffer::Color;
+
+// Moves the write position back one cell without erasing it.
+const BACKSPACE: u8 = 0x08;
+
+// The cursor is an underline in the bottom rows of its cell, below the
+// font's descenders.
+const CURSOR_HEIGHT: usize = 2;
+
+// The standard VGA palette, indexed by Color.
+const PALETTE: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+    0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+
+fn rgb(color: Color) -> u32 {
+    PALETTE[color as usize]
+}
+
+pub struct Console {
+    row: usize,
+    column: usize,
+    foreground: Color,
+    background: Color,
+    // What clear() resets to; changed by set_color.
+    default_foreground: Color,
+    default_background: Color,
+}
+
+impl Console {
+    const fn new() -> Self {
+        Console {
+            row: 0,
+            column: 0,
+            foreground: Color::Yellow,
+            background: Color::Black,
+            default_foreground: Color::Yellow,
+            default_background: Color::Black,
+        }
+    }
+
+    fn columns(framebuffer: &Framebuffer) -> usize {
+        framebuffer.width() / GLYPH_WIDTH
+    }
+
+    fn rows(framebuffer: &Framebuffer) -> usize {
+        framebuffer.height() / GLYPH_HEIGHT
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.foreground = foreground;
+        self.background = background;
+        self.default_foreground = foreground;
+        self.default_background = background;
+    }
+
+    // Keeps the cursor on screen after a switch to a smaller mode.
+    fn fit(&mut self, framebuffer: &Framebuffer) {
+        self.row = self.row.min(Self::rows(framebuffer).saturating_sub(1));
+        self.column = self.column.min(Self::columns(framebuffer));
+    }
+
+    fn draw_glyph(&self, framebuffer: &mut Framebuffer, byte: u8) {
+        let (foreground, background) = (rgb(self.foreground), rgb(self.background));
+        let (x, y) = (self.column * GLYPH_WIDTH, self.row * GLYPH_HEIGHT);
+        for (dy, bits) in font::glyph(byte).iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                let lit = bits & (0x80 >> dx) != 0;
+                framebuffer.put_pixel(x + dx, y + dy, if lit { foreground } else { background });
+            }
+        }
+    }
+
+    // Draws or erases the cursor. A full row parks it on its last cell.
+    fn draw_cursor(&self, framebuffer: &mut Framebuffer, visible: bool) {
+        let column = self.column.min(Self::columns(framebuffer).saturating_sub(1));
+        let color = if visible { self.foreground } else { self.background };
+        framebuffer.fill_rect(
+            column * GLYPH_WIDTH,
+            (self.row + 1) * GLYPH_HEIGHT - CURSOR_HEIGHT,
+            GLYPH_WIDTH,
+            CURSOR_HEIGHT,
+            rgb(color),
+        );
+    }
+
+    // Moves to the next row, scrolling the text up a row once the bottom row
+    // is reached.
+    fn new_line(&mut self, framebuffer: &mut Framebuffer) {
+        self.column = 0;
+        let rows = Self::rows(framebuffer);
+        if self.row + 1 < rows {
+            self.row += 1;
+            return;
+        }
+        let text_height = rows * GLYPH_HEIGHT;
+        framebuffer.copy_rows(GLYPH_HEIGHT, 0, text_height - GLYPH_HEIGHT);
+        let width = framebuffer.width();
+        let background = rgb(self.background);
+        framebuffer.fill_rect(0, text_height - GLYPH_HEIGHT, width, GLYPH_HEIGHT, background);
+    }
+
+    fn write_byte(&mut self, framebuffer: &mut Framebuffer, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(framebuffer),
+            BACKSPACE => {
+                if self.column > 0 {
+                    self.column -= 1;
+                }
+            }
+            byte => {
+                if self.column >= Self::columns(framebuffer) {
+                    self.new_line(framebuffer);
+                }
+                self.draw_glyph(framebuffer, byte);
+                self.column += 1;
+            }
+        }
+    }
+
+    pub fn write_string(&mut self, framebuffer: &mut Framebuffer, s: &str) {
+        // A screen too small for one cell has nowhere to draw.
+        if Self::rows(framebuffer) == 0 || Self::columns(framebuffer) == 0 {
+            return;
+        }
+        self.draw_cursor(framebuffer, false);
+        // The font only has ASCII, so anything else takes one replacement
+        // glyph per character rather than one per UTF-8 byte.
+        for c in s.chars() {
+            let byte = if c.is_ascii() { c as u8 } else { cp437::REPLACEMENT };
+            self.write_byte(framebuffer, byte);
+        }
+        self.draw_cursor(framebuffer, true);
+    }
+
+    // Blanks the screen with the default colors and homes the cursor.
+    pub fn clear(&mut self, framebuffer: &mut Framebuffer) {
+        self.foreground = self.default_foreground;
+        self.background = self.default_background;
+        framebuffer.clear(rgb(self.background));
+        self.row = 0;
+        self.column = 0;
+        if Self::rows(framebuffer) > 0 && Self::columns(framebuffer) > 0 {
+            self.draw_cursor(framebuffer, true);
+        }
+    }
+}
+
+struct ConsoleWriter<'a> {
+    console: &'a mut Console,
+    framebuffer: &'a mut Framebuffer,
+}
+
+impl fmt::Write for ConsoleWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.console.write_string(self.framebuffer, s);
+        Ok(())
+    }
+}
+
+static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
+
+// Runs `f` and shows the result. Like vga_buffer's print path: with
+// interrupts already disabled we may be in a handler that interrupted the lock
+// holder, so the text is dropped rather than spinning forever.
+fn with_console(f: impl FnOnce(&mut Console, &mut Framebuffer)) {
+    let update = |console: &mut Console, framebuffer: &mut Framebuffer| {
+        console.fit(framebuffer);
+        f(console, framebuffer);
+        framebuffer.present();
+    };
+    if interrupts::are_enabled() {
+        interrupts::without_interrupts(|| {
+            let mut console = CONSOLE.lock();
+            framebuffer::with_framebuffer(|framebuffer| update(&mut console, framebuffer));
+        });
+    } else if let Some(mut console) = CONSOLE.try_lock() {
+        framebuffer::try_with_framebuffer(|framebuffer| update(&mut console, framebuffer));
+    }
+}
+
+// Prints `args`, in `color` on the current background if given.
+pub fn print(color: Option<Color>, args: fmt::Arguments) {
+    use core::fmt::Write;
+    with_console(|console, framebuffer| {
+        let previous = console.foreground;
+        if let Some(color) = color {
+            console.foreground = color;
+        }
+        let _ = ConsoleWriter {
+            console: &mut *console,
+            framebuffer,
+        }
+        .write_fmt(args);
+        console.foreground = previous;
+    });
+}
+
+pub fn clear() {
+    with_console(|console, framebuffer| console.clear(framebuffer));
+}
+
+pub fn set_color(foreground: Color, background: Color) {
+    with_console(|console, _| console.set_color(foreground, background));
+}
+
+// For the panic handler, like WRITER.force_unlock().
+#[cfg_attr(test, allow(dead_code))]
+pub unsafe fn force_unlock() {
+    CONSOLE.force_unlock();
+    framebuffer::force_unlock();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::tests::{drawn, in_memory};
+
+    #[test_case]
+    fn text_is_drawn_from_the_font() {
+        let (mut framebuffer, _screen) = in_memory(4 * GLYPH_WIDTH, 2 * GLYPH_HEIGHT, 32);
+        let mut console = Console::new();
+        console.write_string(&mut framebuffer, "A!");
+        let foreground = rgb(console.foreground).to_le_bytes();
+        let background = rgb(console.background).to_le_bytes();
+        for (column, &byte) in b"A!".iter().enumerate() {
+            for (dy, bits) in font::glyph(byte).iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    let expected = if bits & (0x80 >> dx) != 0 { foreground } else { background };
+                    assert_eq!(drawn(&framebuffer, column * GLYPH_WIDTH + dx, dy), expected);
+                }
+            }
+        }
+        assert_eq!((console.row, console.column), (0, 2));
+    }
+}
@@ -0,0 +1,101 @@
+// Code page 437, the VGA text mode character set. Printable ASCII maps to
+// itself; the rest of the 256 glyphs are looked up by the Unicode character
+// they draw. Characters the code page lacks get the nearest glyph where there
+// is an obvious one, and the replacement box otherwise.
+
+// The filled square, also drawn for U+FFFD, which lossy UTF-8 decoding puts
+// in place of invalid bytes.
+pub const REPLACEMENT: u8 = 0xFE;
+
+// Glyphs 0x01-0x1F, which stand in for control characters when written
+// straight to the screen. 0x00 is blank.
+static LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', '►',
+    '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+// Glyphs 0x7F-0xFF.
+static HIGH: [char; 129] = [
+    '⌂', 'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+// Characters outside the code page that have a close match in it.
+static NEAREST: [(char, u8); 13] = [
+    ('‘', b'\''),
+    ('’', b'\''),
+    ('“', b'"'),
+    ('”', b'"'),
+    ('–', b'-'),
+    ('—', b'-'),
+    ('−', b'-'),
+    ('×', b'x'),
+    ('β', 0xE1),
+    ('μ', 0xE6),
+    ('Ø', 0xED),
+    ('ø', 0xED),
+    ('€', b'E'),
+];
+
+// The glyph to draw for `c`. Control characters are the caller's business:
+// they are drawn as their CP437 pictures here.
+pub fn glyph(c: char) -> u8 {
+    if (' '..='~').contains(&c) {
+        return c as u8;
+    }
+    if let Some(index) = LOW.iter().position(|&low| low == c) {
+        return index as u8 + 0x01;
+    }
+    if let Some(index) = HIGH.iter().position(|&high| high == c) {
+        return index as u8 + 0x7F;
+    }
+    if let Some(&(_, byte)) = NEAREST.iter().find(|&&(near, _)| near == c) {
+        return byte;
+    }
+    if (c as u32) < 0x20 || c == '\x7F' {
+        return c as u8;
+    }
+    REPLACEMENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn glyphs_for_known_code_points() {
+        assert_eq!(glyph('A'), 0x41);
+        assert_eq!(glyph('~'), 0x7E);
+        assert_eq!(glyph('☺'), 0x01);
+        assert_eq!(glyph('▼'), 0x1F);
+        assert_eq!(glyph('⌂'), 0x7F);
+        assert_eq!(glyph('Ç'), 0x80);
+        assert_eq!(glyph('░'), 0xB0);
+        assert_eq!(glyph('█'), 0xDB);
+        assert_eq!(glyph('ß'), 0xE1);
+        assert_eq!(glyph('■'), 0xFE);
+        assert_eq!(glyph('\u{A0}'), 0xFF);
+        assert_eq!(glyph('\n'), 0x0A);
+    }
+
+    #[test_case]
+    fn missing_characters_get_the_nearest_or_the_replacement() {
+        assert_eq!(glyph('’'), b'\'');
+        assert_eq!(glyph('—'), b'-');
+        assert_eq!(glyph('μ'), glyph('µ'));
+        assert_eq!(glyph('\u{FFFD}'), REPLACEMENT);
+        assert_eq!(glyph('漢'), REPLACEMENT);
+    }
+
+    #[test_case]
+    fn every_table_entry_maps_back_to_its_position() {
+        assert!(LOW.iter().enumerate().all(|(index, &c)| glyph(c) == index as u8 + 0x01));
+        assert!(HIGH.iter().enumerate().all(|(index, &c)| glyph(c) == index as u8 + 0x7F));
+    }
+}
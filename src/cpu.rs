@@ -0,0 +1,154 @@
+use core::arch::x86_64::{CpuidResult, __cpuid};
+use lazy_static::lazy_static;
+
+// CPUID.1 EDX
+const EDX_TSC: u32 = 1 << 4;
+const EDX_APIC: u32 = 1 << 9;
+const EDX_SSE: u32 = 1 << 25;
+const EDX_SSE2: u32 = 1 << 26;
+// CPUID.1 ECX
+const ECX_SSE3: u32 = 1 << 0;
+const ECX_SSSE3: u32 = 1 << 9;
+const ECX_SSE4_1: u32 = 1 << 19;
+const ECX_SSE4_2: u32 = 1 << 20;
+const ECX_X2APIC: u32 = 1 << 21;
+const ECX_AVX: u32 = 1 << 28;
+const ECX_RDRAND: u32 = 1 << 30;
+// CPUID.80000001h EDX
+const EXT_EDX_NX: u32 = 1 << 20;
+
+const EXTENDED_BASE: u32 = 0x8000_0000;
+const BRAND_LEAVES: core::ops::RangeInclusive<u32> = 0x8000_0002..=0x8000_0004;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    pub tsc: bool,
+    pub apic: bool,
+    pub x2apic: bool,
+    pub nx: bool,
+    pub sse: bool,
+    pub sse2: bool,
+    pub sse3: bool,
+    pub ssse3: bool,
+    pub sse4_1: bool,
+    pub sse4_2: bool,
+    pub avx: bool,
+    pub rdrand: bool,
+}
+
+impl Features {
+    // Name and presence of each flag, for display.
+    pub fn flags(&self) -> [(&'static str, bool); 12] {
+        [
+            ("tsc", self.tsc),
+            ("apic", self.apic),
+            ("x2apic", self.x2apic),
+            ("nx", self.nx),
+            ("sse", self.sse),
+            ("sse2", self.sse2),
+            ("sse3", self.sse3),
+            ("ssse3", self.ssse3),
+            ("sse4.1", self.sse4_1),
+            ("sse4.2", self.sse4_2),
+            ("avx", self.avx),
+            ("rdrand", self.rdrand),
+        ]
+    }
+}
+
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    // None if the CPU doesn't implement the brand string leaves.
+    brand: Option<[u8; 48]>,
+    pub features: Features,
+}
+
+impl CpuInfo {
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    // The brand string is NUL-padded and often space-padded on the left.
+    pub fn brand(&self) -> Option<&str> {
+        let brand = self.brand.as_ref()?;
+        let len = brand.iter().position(|&b| b == 0).unwrap_or(brand.len());
+        core::str::from_utf8(&brand[..len]).ok().map(str::trim)
+    }
+}
+
+fn cpuid(leaf: u32) -> CpuidResult {
+    __cpuid(leaf)
+}
+
+fn detect() -> CpuInfo {
+    let leaf0 = cpuid(0);
+    let mut vendor = [0; 12];
+    // The vendor string is spread over ebx, edx, ecx in that order.
+    vendor[0..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&leaf0.ecx.to_le_bytes());
+
+    let leaf1 = cpuid(1);
+    let max_extended = cpuid(EXTENDED_BASE).eax;
+    let ext_edx = if max_extended > EXTENDED_BASE {
+        cpuid(EXTENDED_BASE + 1).edx
+    } else {
+        0
+    };
+
+    let brand = if max_extended >= *BRAND_LEAVES.end() {
+        let mut brand = [0; 48];
+        for (i, leaf) in BRAND_LEAVES.enumerate() {
+            let regs = cpuid(leaf);
+            for (j, reg) in [regs.eax, regs.ebx, regs.ecx, regs.edx].iter().enumerate() {
+                let offset = i * 16 + j * 4;
+                brand[offset..offset + 4].copy_from_slice(&reg.to_le_bytes());
+            }
+        }
+        Some(brand)
+    } else {
+        None
+    };
+
+    let features = Features {
+        tsc: leaf1.edx & EDX_TSC != 0,
+        apic: leaf1.edx & EDX_APIC != 0,
+        x2apic: leaf1.ecx & ECX_X2APIC != 0,
+        nx: ext_edx & EXT_EDX_NX != 0,
+        sse: leaf1.edx & EDX_SSE != 0,
+        sse2: leaf1.edx & EDX_SSE2 != 0,
+        sse3: leaf1.ecx & ECX_SSE3 != 0,
+        ssse3: leaf1.ecx & ECX_SSSE3 != 0,
+        sse4_1: leaf1.ecx & ECX_SSE4_1 != 0,
+        sse4_2: leaf1.ecx & ECX_SSE4_2 != 0,
+        avx: leaf1.ecx & ECX_AVX != 0,
+        rdrand: leaf1.ecx & ECX_RDRAND != 0,
+    };
+
+    CpuInfo {
+        vendor,
+        brand,
+        features,
+    }
+}
+
+lazy_static! {
+    pub static ref CPU_INFO: CpuInfo = detect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn the_vendor_is_a_known_one() {
+        // TCGTCGTCGTCG is QEMU without acceleration; with KVM the host's
+        // vendor shows through.
+        let known = ["GenuineIntel", "AuthenticAMD", "HygonGenuine", "TCGTCGTCGTCG"];
+        assert!(known.contains(&CPU_INFO.vendor()));
+        assert!(CPU_INFO.features.tsc);
+        if let Some(brand) = CPU_INFO.brand() {
+            assert!(!brand.is_empty());
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::log_warn;
+
+#[derive(Debug, Clone)]
+pub struct DriverStatus {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+static DRIVERS: Mutex<Vec<DriverStatus>> = Mutex::new(Vec::new());
+
+// Records the outcome of a driver's initialization. Failures are logged here
+// so callers can carry on without the driver instead of panicking.
+pub fn record(name: &'static str, ok: bool, detail: impl Into<String>) {
+    let detail = detail.into();
+    if !ok {
+        log_warn!("{}: {}", name, detail);
+    }
+    interrupts::without_interrupts(|| {
+        DRIVERS.lock().push(DriverStatus { name, ok, detail });
+    });
+}
+
+pub fn statuses() -> Vec<DriverStatus> {
+    interrupts::without_interrupts(|| DRIVERS.lock().clone())
+}
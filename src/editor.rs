@@ -0,0 +1,336 @@
+// A full-screen editor for text files, run by the `edit` command. The file
+// is held in memory as lines of printable ASCII, so byte and character
+// indices coincide, and the screen is redrawn after every key. Ctrl-S saves
+// and Ctrl-Q quits. Only the VGA text screen is drawn on.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::filesystem::FileSystem;
+use crate::keyboard;
+use crate::vga_buffer::{self, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+// The bottom row is the status line.
+const TEXT_ROWS: usize = BUFFER_HEIGHT - 1;
+// Tab inserts spaces up to the next multiple of this.
+const TAB_WIDTH: usize = 4;
+
+// The text and the cursor, apart from how they are shown. There is always at
+// least one line, and the cursor is always within the text.
+pub struct Buffer {
+    lines: Vec<String>,
+    row: usize,
+    col: usize,
+    modified: bool,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Buffer {
+            lines: vec![String::new()],
+            row: 0,
+            col: 0,
+            modified: false,
+        }
+    }
+
+    // None if `data` holds anything but printable ASCII, tabs and line
+    // endings. Tabs are expanded to spaces the way the Tab key inserts them,
+    // and CRs are dropped, so CRLF files load and save back with plain LFs.
+    // One trailing newline ends the last line rather than starting another.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data = data.strip_suffix(b"\n").unwrap_or(data);
+        let mut lines = vec![String::new()];
+        for &byte in data {
+            let line = lines.last_mut().unwrap();
+            match byte {
+                b'\n' => lines.push(String::new()),
+                b'\r' => {}
+                b'\t' => {
+                    for _ in 0..TAB_WIDTH - line.len() % TAB_WIDTH {
+                        line.push(' ');
+                    }
+                }
+                0x20..=0x7E => line.push(char::from(byte)),
+                _ => return None,
+            }
+        }
+        let mut buffer = Buffer::new();
+        buffer.lines = lines;
+        Some(buffer)
+    }
+
+    // The text as it is saved, with every line ending in a newline. An empty
+    // buffer saves as an empty file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.lines.len() == 1 && self.lines[0].is_empty() {
+            return Vec::new();
+        }
+        let mut data = Vec::new();
+        for line in &self.lines {
+            data.extend_from_slice(line.as_bytes());
+            data.push(b'\n');
+        }
+        data
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+    }
+
+    // `c` must be printable ASCII.
+    pub fn insert(&mut self, c: char) {
+        self.lines[self.row].insert(self.col, c);
+        self.col += 1;
+        self.modified = true;
+    }
+
+    pub fn insert_tab(&mut self) {
+        for _ in 0..TAB_WIDTH - self.col % TAB_WIDTH {
+            self.insert(' ');
+        }
+    }
+
+    // Splits the line at the cursor, which moves to the start of the new one.
+    pub fn newline(&mut self) {
+        let rest = self.lines[self.row].split_off(self.col);
+        self.row += 1;
+        self.col = 0;
+        self.lines.insert(self.row, rest);
+        self.modified = true;
+    }
+
+    // Deletes the character before the cursor, joining the line onto the one
+    // above at the start of a line.
+    pub fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+            self.lines[self.row].remove(self.col);
+            self.modified = true;
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.col = self.lines[self.row].len();
+            self.lines[self.row].push_str(&line);
+            self.modified = true;
+        }
+    }
+
+    // Deletes the character under the cursor, joining the next line onto
+    // this one at the end of a line.
+    pub fn delete(&mut self) {
+        if self.col < self.lines[self.row].len() {
+            self.lines[self.row].remove(self.col);
+            self.modified = true;
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].push_str(&line);
+            self.modified = true;
+        }
+    }
+
+    pub fn left(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.col = self.lines[self.row].len();
+        }
+    }
+
+    pub fn right(&mut self) {
+        if self.col < self.lines[self.row].len() {
+            self.col += 1;
+        } else if self.row + 1 < self.lines.len() {
+            self.row += 1;
+            self.col = 0;
+        }
+    }
+
+    // Moves up (negative) or down by `rows` lines, keeping the column where
+    // the new line is long enough.
+    pub fn move_rows(&mut self, rows: isize) {
+        let last = self.lines.len() - 1;
+        self.row = if rows < 0 {
+            self.row.saturating_sub(rows.unsigned_abs())
+        } else {
+            (self.row + rows as usize).min(last)
+        };
+        self.col = self.col.min(self.lines[self.row].len());
+    }
+
+    pub fn home(&mut self) {
+        self.col = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.col = self.lines[self.row].len();
+    }
+}
+
+struct Editor {
+    buffer: Buffer,
+    path: String,
+    // The first line and column shown.
+    top: usize,
+    left: usize,
+    // Shown in the status line until the next key.
+    message: Option<String>,
+}
+
+impl Editor {
+    // Scrolls just far enough to bring the cursor on screen.
+    fn scroll_to_cursor(&mut self) {
+        let (row, col) = self.buffer.cursor();
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + TEXT_ROWS {
+            self.top = row + 1 - TEXT_ROWS;
+        }
+        if col < self.left {
+            self.left = col;
+        } else if col >= self.left + BUFFER_WIDTH {
+            self.left = col + 1 - BUFFER_WIDTH;
+        }
+    }
+
+    fn render(&self) {
+        let (row, col) = self.buffer.cursor();
+        let status = format!(
+            " {}{}  line {}/{}, col {}  {}",
+            self.path,
+            if self.buffer.is_modified() { " [modified]" } else { "" },
+            row + 1,
+            self.buffer.lines().len(),
+            col + 1,
+            self.message.as_deref().unwrap_or("^S save  ^Q quit"),
+        );
+        vga_buffer::with_writer(|writer| {
+            for screen_row in 0..TEXT_ROWS {
+                // Rows past the end of the file are marked as in vi.
+                let text = match self.buffer.lines().get(self.top + screen_row) {
+                    Some(line) => line.get(self.left..).unwrap_or(""),
+                    None => "~",
+                };
+                writer.put_row(screen_row, text, false);
+            }
+            writer.put_row(TEXT_ROWS, &status, true);
+            writer.move_to(row - self.top, col - self.left);
+        });
+    }
+
+    // A file that didn't exist is created here.
+    fn save(&mut self, fs: &mut FileSystem) {
+        let data = self.buffer.to_bytes();
+        match fs.create_file(&self.path, &data) {
+            Ok(()) => {
+                self.buffer.mark_saved();
+                self.message = Some(format!("Wrote {} bytes", data.len()));
+            }
+            Err(err) => self.message = Some(format!("Cannot write: {}", err)),
+        }
+    }
+}
+
+// Edits `buffer`, saving it to `path`, until the user quits. Quitting with
+// unsaved changes takes a second Ctrl-Q.
+pub fn run(fs: &mut FileSystem, path: &str, buffer: Buffer) {
+    let mut editor = Editor {
+        buffer,
+        path: String::from(path),
+        top: 0,
+        left: 0,
+        message: None,
+    };
+    let mut quit_pending = false;
+    loop {
+        editor.render();
+        let key = keyboard::read_char();
+        editor.message = None;
+        if key != keyboard::KEY_CTRL_Q {
+            quit_pending = false;
+        }
+        let buffer = &mut editor.buffer;
+        match key {
+            keyboard::KEY_CTRL_S => editor.save(fs),
+            keyboard::KEY_CTRL_Q => {
+                if !buffer.is_modified() || quit_pending {
+                    break;
+                }
+                quit_pending = true;
+                editor.message = Some(String::from("Unsaved changes; ^Q again to quit"));
+            }
+            b'\n' => buffer.newline(),
+            8 => buffer.backspace(),
+            b'\t' => buffer.insert_tab(),
+            keyboard::KEY_DELETE => buffer.delete(),
+            keyboard::KEY_LEFT => buffer.left(),
+            keyboard::KEY_RIGHT => buffer.right(),
+            keyboard::KEY_UP => buffer.move_rows(-1),
+            keyboard::KEY_DOWN => buffer.move_rows(1),
+            keyboard::KEY_PAGE_UP => buffer.move_rows(-(TEXT_ROWS as isize)),
+            keyboard::KEY_PAGE_DOWN => buffer.move_rows(TEXT_ROWS as isize),
+            keyboard::KEY_HOME => buffer.home(),
+            keyboard::KEY_END => buffer.end(),
+            32..=126 => buffer.insert(key as char),
+            _ => {}
+        }
+        editor.scroll_to_cursor();
+    }
+    vga_buffer::clear_screen();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn editing_inserts_splits_and_joins_lines() {
+        let mut buffer = Buffer::from_bytes(b"ac\nd\n").unwrap();
+        assert!(!buffer.is_modified());
+        buffer.right();
+        buffer.insert('b');
+        assert_eq!(buffer.lines(), ["abc", "d"]);
+        assert_eq!(buffer.cursor(), (0, 2));
+        buffer.newline();
+        assert_eq!(buffer.lines(), ["ab", "c", "d"]);
+        assert_eq!(buffer.cursor(), (1, 0));
+        // Backspace at the start of a line joins it onto the one above.
+        buffer.backspace();
+        assert_eq!(buffer.lines(), ["abc", "d"]);
+        assert_eq!(buffer.cursor(), (0, 2));
+        // Delete at the end of a line pulls the next one up.
+        buffer.end();
+        buffer.delete();
+        assert_eq!(buffer.lines(), ["abcd"]);
+        buffer.home();
+        buffer.delete();
+        buffer.backspace();
+        assert_eq!(buffer.lines(), ["bcd"]);
+        assert!(buffer.is_modified());
+        assert_eq!(buffer.to_bytes(), b"bcd\n");
+    }
+
+    #[test_case]
+    fn from_bytes_expands_tabs_and_drops_carriage_returns() {
+        let buffer = Buffer::from_bytes(b"a\tb\r\n\tc\r\n").unwrap();
+        assert_eq!(buffer.lines(), ["a   b", "    c"]);
+        assert_eq!(buffer.to_bytes(), b"a   b\n    c\n");
+        assert!(Buffer::from_bytes(b"bin\x00ary").is_none());
+        assert_eq!(Buffer::from_bytes(b"").unwrap().to_bytes(), b"");
+    }
+}
@@ -0,0 +1,1324 @@
+// A small persistent filesystem. On the device it is laid out as:
+//
+//   block 0            superblock
+//   bitmap_start..     free-block bitmap
+//   inode_start..      fixed inode table, INODE_SIZE bytes per inode
+//   data_start..       file data
+//
+// Each inode records its name and parent directory's inode, so directories
+// need no data blocks of their own; the tree is rebuilt in memory on mount.
+// Inode 0 is the root directory. All integers are little-endian.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::block::{BlockCache, BlockDevice, BlockError};
+use crate::time;
+
+pub const MAX_NAME_LEN: usize = 64;
+
+const MAGIC: [u8; 4] = *b"RSFS";
+const VERSION: u32 = 1;
+
+const INODE_SIZE: usize = 256;
+// Byte offsets of the inode fields. The direct block pointers fill the rest
+// of the inode.
+const INODE_KIND: usize = 0;
+const INODE_NAME_LEN: usize = 1;
+const INODE_PARENT: usize = 2;
+const INODE_SIZE_FIELD: usize = 4;
+const INODE_CREATED: usize = 8;
+const INODE_MODIFIED: usize = 16;
+const INODE_NAME: usize = 24;
+const INODE_BLOCKS: usize = INODE_NAME + MAX_NAME_LEN;
+pub const DIRECT_BLOCKS: usize = (INODE_SIZE - INODE_BLOCKS) / 4;
+
+const KIND_FREE: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_DIR: u8 = 2;
+
+const ROOT_INODE: u16 = 0;
+const MAX_INODES: u32 = 1024;
+// The bitmap is kept on the heap, so larger devices only have their first
+// 8 MiB (of 512-byte blocks) used.
+const MAX_BLOCKS: u32 = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NameTooLong,
+    OutOfSpace,
+    InvalidName,
+    IsADirectory,
+    NotADirectory,
+    FileTooLarge,
+    NotFormatted,
+    Corrupt,
+    Io(BlockError),
+}
+
+impl From<BlockError> for FsError {
+    fn from(err: BlockError) -> Self {
+        FsError::Io(err)
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::AlreadyExists => "already exists",
+            FsError::NameTooLong => "name too long",
+            FsError::OutOfSpace => "no space left",
+            FsError::InvalidName => "invalid name",
+            FsError::IsADirectory => "is a directory",
+            FsError::NotADirectory => "not a directory",
+            FsError::FileTooLarge => "file too large",
+            FsError::NotFormatted => "no filesystem on device",
+            FsError::Corrupt => "filesystem is corrupt",
+            FsError::Io(err) => return write!(f, "I/O error: {}", err),
+        };
+        f.write_str(message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMeta {
+    pub size: usize,
+    pub created_tick: usize,
+    pub modified_tick: usize,
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Superblock {
+    block_size: u32,
+    block_count: u32,
+    bitmap_start: u32,
+    bitmap_blocks: u32,
+    inode_start: u32,
+    inode_count: u32,
+    data_start: u32,
+}
+
+impl Superblock {
+    // Lays out a filesystem on a device of the given geometry, with one inode
+    // for every two blocks up to MAX_INODES.
+    fn for_device(block_size: usize, block_count: u32) -> Result<Self, FsError> {
+        if block_size < INODE_SIZE || !block_size.is_multiple_of(INODE_SIZE) {
+            return Err(FsError::Corrupt);
+        }
+        let bits_per_block = block_size as u32 * 8;
+        let bitmap_blocks = block_count.div_ceil(bits_per_block);
+        let inode_count = (block_count / 2).clamp(1, MAX_INODES);
+        let inodes_per_block = (block_size / INODE_SIZE) as u32;
+        let inode_blocks = inode_count.div_ceil(inodes_per_block);
+        let superblock = Superblock {
+            block_size: block_size as u32,
+            block_count,
+            bitmap_start: 1,
+            bitmap_blocks,
+            inode_start: 1 + bitmap_blocks,
+            inode_count,
+            data_start: 1 + bitmap_blocks + inode_blocks,
+        };
+        if superblock.data_start >= block_count {
+            return Err(FsError::OutOfSpace);
+        }
+        Ok(superblock)
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        buf[0..4].copy_from_slice(&MAGIC);
+        write_u32(buf, 4, VERSION);
+        write_u32(buf, 8, self.block_size);
+        write_u32(buf, 12, self.block_count);
+        write_u32(buf, 16, self.bitmap_start);
+        write_u32(buf, 20, self.bitmap_blocks);
+        write_u32(buf, 24, self.inode_start);
+        write_u32(buf, 28, self.inode_count);
+        write_u32(buf, 32, self.data_start);
+    }
+
+    // Checks the superblock against the device it was read from.
+    fn decode(buf: &[u8], device: &dyn BlockDevice) -> Result<Self, FsError> {
+        if buf[0..4] != MAGIC {
+            return Err(FsError::NotFormatted);
+        }
+        let superblock = Superblock {
+            block_size: read_u32(buf, 8),
+            block_count: read_u32(buf, 12),
+            bitmap_start: read_u32(buf, 16),
+            bitmap_blocks: read_u32(buf, 20),
+            inode_start: read_u32(buf, 24),
+            inode_count: read_u32(buf, 28),
+            data_start: read_u32(buf, 32),
+        };
+        // format() never writes more, and the bitmap would not fit in the heap.
+        if superblock.block_count > MAX_BLOCKS {
+            return Err(FsError::Corrupt);
+        }
+        let expected = Superblock::for_device(device.block_size(), superblock.block_count)
+            .map_err(|_| FsError::Corrupt)?;
+        if read_u32(buf, 4) != VERSION
+            || superblock.block_count > device.block_count()
+            || superblock.block_size != expected.block_size
+            || superblock.bitmap_start != expected.bitmap_start
+            || superblock.bitmap_blocks != expected.bitmap_blocks
+            || superblock.inode_start != expected.inode_start
+            || superblock.inode_count != expected.inode_count
+            || superblock.data_start != expected.data_start
+        {
+            return Err(FsError::Corrupt);
+        }
+        Ok(superblock)
+    }
+
+    fn inodes_per_block(&self) -> u32 {
+        self.block_size / INODE_SIZE as u32
+    }
+
+    fn is_data_block(&self, block: u32) -> bool {
+        (self.data_start..self.block_count).contains(&block)
+    }
+}
+
+// One bit per block, set while the block is in use. Blocks before the data
+// area are always set, so only data blocks are ever handed out.
+struct Bitmap {
+    bits: Vec<u8>,
+    block_count: u32,
+    free: usize,
+    // Per bitmap block, whether it changed since the last write_back().
+    dirty: Vec<bool>,
+    bits_per_block: u32,
+}
+
+impl Bitmap {
+    fn new(superblock: &Superblock) -> Self {
+        let bytes = superblock.bitmap_blocks as usize * superblock.block_size as usize;
+        let mut bitmap = Bitmap {
+            bits: vec![0; bytes],
+            block_count: superblock.block_count,
+            free: superblock.block_count as usize,
+            dirty: vec![true; superblock.bitmap_blocks as usize],
+            bits_per_block: superblock.block_size * 8,
+        };
+        for block in 0..superblock.data_start {
+            bitmap.set(block, true);
+        }
+        bitmap
+    }
+
+    fn is_set(&self, block: u32) -> bool {
+        self.bits[block as usize / 8] & (1 << (block % 8)) != 0
+    }
+
+    fn set(&mut self, block: u32, used: bool) {
+        let byte = &mut self.bits[block as usize / 8];
+        let mask = 1 << (block % 8);
+        if used {
+            *byte |= mask;
+            self.free -= 1;
+        } else {
+            *byte &= !mask;
+            self.free += 1;
+        }
+        self.dirty[(block / self.bits_per_block) as usize] = true;
+    }
+
+    // Hands out the lowest free block, so freed blocks are reused first.
+    fn alloc_block(&mut self) -> Option<u32> {
+        let byte = self.bits.iter().position(|&byte| byte != 0xFF)?;
+        let block = byte as u32 * 8 + self.bits[byte].trailing_ones();
+        if block >= self.block_count {
+            return None;
+        }
+        self.set(block, true);
+        Some(block)
+    }
+
+    // Refuses blocks that are out of range or already free, since freeing
+    // one of those would corrupt the count or hand a block out twice.
+    fn free_block(&mut self, block: u32) -> Result<(), FsError> {
+        if block >= self.block_count || !self.is_set(block) {
+            return Err(FsError::Corrupt);
+        }
+        self.set(block, false);
+        Ok(())
+    }
+
+    fn read(&mut self, device: &dyn BlockDevice, start: u32) -> Result<(), FsError> {
+        for (i, chunk) in self.bits.chunks_mut(device.block_size()).enumerate() {
+            device.read_block(start + i as u32, chunk)?;
+        }
+        self.free = (0..self.block_count).filter(|&block| !self.is_set(block)).count();
+        self.dirty.fill(false);
+        Ok(())
+    }
+
+    // Writes out the bitmap blocks that changed.
+    fn write_back(&mut self, device: &dyn BlockDevice, start: u32) -> Result<(), FsError> {
+        for (i, chunk) in self.bits.chunks(device.block_size()).enumerate() {
+            if self.dirty[i] {
+                device.write_block(start + i as u32, chunk)?;
+                self.dirty[i] = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+// An inode as stored on the device.
+struct Inode {
+    kind: u8,
+    parent: u16,
+    name: String,
+    meta: FileMeta,
+    blocks: Vec<u32>,
+}
+
+impl Inode {
+    fn free() -> Self {
+        Inode {
+            kind: KIND_FREE,
+            parent: 0,
+            name: String::new(),
+            meta: FileMeta {
+                size: 0,
+                created_tick: 0,
+                modified_tick: 0,
+            },
+            blocks: Vec::new(),
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        buf[INODE_KIND] = self.kind;
+        buf[INODE_NAME_LEN] = self.name.len() as u8;
+        write_u16(buf, INODE_PARENT, self.parent);
+        write_u32(buf, INODE_SIZE_FIELD, self.meta.size as u32);
+        write_u64(buf, INODE_CREATED, self.meta.created_tick as u64);
+        write_u64(buf, INODE_MODIFIED, self.meta.modified_tick as u64);
+        buf[INODE_NAME..INODE_NAME + self.name.len()].copy_from_slice(self.name.as_bytes());
+        for (i, block) in self.blocks.iter().enumerate() {
+            write_u32(buf, INODE_BLOCKS + i * 4, *block);
+        }
+    }
+
+    fn decode(buf: &[u8], superblock: &Superblock) -> Result<Self, FsError> {
+        let kind = buf[INODE_KIND];
+        if kind == KIND_FREE {
+            return Ok(Inode::free());
+        }
+        let name_len = buf[INODE_NAME_LEN] as usize;
+        let size = read_u32(buf, INODE_SIZE_FIELD) as usize;
+        let block_size = superblock.block_size as usize;
+        let block_count = size.div_ceil(block_size);
+        if (kind != KIND_FILE && kind != KIND_DIR) || name_len > MAX_NAME_LEN || block_count > DIRECT_BLOCKS {
+            return Err(FsError::Corrupt);
+        }
+        let name = core::str::from_utf8(&buf[INODE_NAME..INODE_NAME + name_len]).map_err(|_| FsError::Corrupt)?;
+        let blocks: Vec<u32> = (0..block_count).map(|i| read_u32(buf, INODE_BLOCKS + i * 4)).collect();
+        if !blocks.iter().all(|&block| superblock.is_data_block(block)) {
+            return Err(FsError::Corrupt);
+        }
+        Ok(Inode {
+            kind,
+            parent: read_u16(buf, INODE_PARENT),
+            name: String::from(name),
+            meta: FileMeta {
+                size,
+                created_tick: read_u64(buf, INODE_CREATED) as usize,
+                modified_tick: read_u64(buf, INODE_MODIFIED) as usize,
+            },
+            blocks,
+        })
+    }
+}
+
+struct File {
+    inode: u16,
+    // Device blocks holding the contents in order; the last may be partly
+    // used.
+    blocks: Vec<u32>,
+    meta: FileMeta,
+}
+
+impl File {
+    fn to_inode(&self, parent: u16, name: &str) -> Inode {
+        Inode {
+            kind: KIND_FILE,
+            parent,
+            name: String::from(name),
+            meta: self.meta,
+            blocks: self.blocks.clone(),
+        }
+    }
+}
+
+struct Dir {
+    inode: u16,
+    entries: BTreeMap<String, Node>,
+}
+
+enum Node {
+    Dir(Dir),
+    File(File),
+}
+
+impl Node {
+    fn inode_number(&self) -> u16 {
+        match self {
+            Node::Dir(dir) => dir.inode,
+            Node::File(file) => file.inode,
+        }
+    }
+
+    // The inode describing this node as entry `name` of directory `parent`.
+    fn to_inode(&self, parent: u16, name: &str) -> Inode {
+        match self {
+            Node::Dir(_) => Inode {
+                kind: KIND_DIR,
+                parent,
+                name: String::from(name),
+                ..Inode::free()
+            },
+            Node::File(file) => file.to_inode(parent, name),
+        }
+    }
+}
+
+// Paths are slash-separated; leading, trailing and repeated slashes are
+// ignored, so "docs/readme.txt" and "/docs//readme.txt" name the same file.
+pub struct FileSystem {
+    root: Dir,
+    storage: Storage,
+}
+
+// The device plus what is free on it. Writes are cached until sync().
+struct Storage {
+    device: BlockCache,
+    superblock: Superblock,
+    bitmap: Bitmap,
+    free_inodes: Vec<u16>,
+}
+
+impl Storage {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn blocks_for(&self, len: usize) -> usize {
+        len.div_ceil(self.block_size())
+    }
+
+    // Allocates blocks for `data` and writes it out, zero-padding the last
+    // block. Nothing stays allocated if a write fails.
+    fn store(&mut self, data: &[u8]) -> Result<Vec<u32>, FsError> {
+        if self.blocks_for(data.len()) > self.bitmap.free {
+            return Err(FsError::OutOfSpace);
+        }
+        let mut blocks = Vec::new();
+        let mut buf = vec![0; self.block_size()];
+        for chunk in data.chunks(self.block_size()) {
+            let block = self.bitmap.alloc_block().ok_or(FsError::OutOfSpace)?;
+            blocks.push(block);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            buf[chunk.len()..].fill(0);
+            if let Err(err) = self.device.write_block(block, &buf) {
+                self.release(&blocks)?;
+                return Err(err.into());
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn load(&self, file: &File) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![0; file.blocks.len() * self.block_size()];
+        for (block, chunk) in file.blocks.iter().zip(data.chunks_mut(self.block_size())) {
+            self.device.read_block(*block, chunk)?;
+        }
+        data.truncate(file.meta.size);
+        Ok(data)
+    }
+
+    fn release(&mut self, blocks: &[u32]) -> Result<(), FsError> {
+        blocks.iter().try_for_each(|&block| self.bitmap.free_block(block))
+    }
+
+    // Frees blocks once no inode on the device refers to them, writing the
+    // bitmap straight away. Freeing them any earlier could let them be
+    // handed out while a committed inode still points at them.
+    fn release_unreferenced(&mut self, blocks: &[u32]) -> Result<(), FsError> {
+        self.release(blocks)?;
+        self.bitmap.write_back(&self.device, self.superblock.bitmap_start)
+    }
+
+    fn alloc_inode(&mut self) -> Result<u16, FsError> {
+        self.free_inodes.pop().ok_or(FsError::OutOfSpace)
+    }
+
+    // Where inode `index` lives: its block and byte offset within it.
+    fn inode_location(&self, index: u16) -> (u32, usize) {
+        let per_block = self.superblock.inodes_per_block();
+        let block = self.superblock.inode_start + index as u32 / per_block;
+        (block, (index as u32 % per_block) as usize * INODE_SIZE)
+    }
+
+    fn read_inode(&self, index: u16) -> Result<Inode, FsError> {
+        let (block, offset) = self.inode_location(index);
+        let mut buf = vec![0; self.block_size()];
+        self.device.read_block(block, &mut buf)?;
+        Inode::decode(&buf[offset..offset + INODE_SIZE], &self.superblock)
+    }
+
+    fn write_inode(&mut self, index: u16, inode: &Inode) -> Result<(), FsError> {
+        let (block, offset) = self.inode_location(index);
+        let mut buf = vec![0; self.block_size()];
+        self.device.read_block(block, &mut buf)?;
+        inode.encode(&mut buf[offset..offset + INODE_SIZE]);
+        self.device.write_block(block, &buf)?;
+        Ok(())
+    }
+
+    // Writes an inode along with the bitmap changes made for it.
+    fn commit_inode(&mut self, index: u16, inode: &Inode) -> Result<(), FsError> {
+        self.bitmap.write_back(&self.device, self.superblock.bitmap_start)?;
+        self.write_inode(index, inode)
+    }
+
+    // Writes a fresh inode for `node`, giving the inode number back if that
+    // fails so the caller only has to drop the node.
+    fn write_new_inode(&mut self, node: &Node, parent: u16, name: &str) -> Result<(), FsError> {
+        let result = self.commit_inode(node.inode_number(), &node.to_inode(parent, name));
+        if result.is_err() {
+            self.free_inodes.push(node.inode_number());
+        }
+        result
+    }
+}
+
+// Builds directory `index` from the inodes that name it as their parent.
+// Each inode is taken as it is placed, so a cycle of parent links can't
+// recurse forever.
+fn build_dir(index: u16, inodes: &mut [Option<Inode>], children: &[Vec<u16>]) -> Dir {
+    let mut dir = Dir {
+        inode: index,
+        entries: BTreeMap::new(),
+    };
+    for &child in &children[index as usize] {
+        let inode = match inodes[child as usize].take() {
+            Some(inode) => inode,
+            None => continue,
+        };
+        let node = if inode.kind == KIND_DIR {
+            Node::Dir(build_dir(child, inodes, children))
+        } else {
+            Node::File(File {
+                inode: child,
+                blocks: inode.blocks,
+                meta: inode.meta,
+            })
+        };
+        dir.entries.insert(inode.name, node);
+    }
+    dir
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+// Splits a path into its parent directory and final component.
+fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return None;
+    }
+    Some(path.rsplit_once('/').unwrap_or(("", path)))
+}
+
+// Checks a name about to be created. "." and ".." are reserved for
+// navigation, and names are limited to printable ASCII.
+fn validate_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidName);
+    }
+    if !name.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return Err(FsError::InvalidName);
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(())
+}
+
+// Turns `path` into an absolute path, interpreting it relative to `cwd`
+// unless it starts with '/', and folding away "." and "..".
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        components(cwd).collect()
+    };
+    for component in components(path) {
+        match component {
+            "." => {}
+            ".." => {
+                parts.pop();
+            }
+            name => parts.push(name),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+// Matches `text` against a glob where '*' matches any run of characters,
+// including '/' and the empty run, and '?' matches exactly one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume if the current attempt fails: just after the most
+    // recent '*', with that star swallowing one more character of text.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+impl FileSystem {
+    // Writes an empty filesystem over whatever the device holds.
+    pub fn format(device: Box<dyn BlockDevice>) -> Result<Self, FsError> {
+        let device = BlockCache::new(device);
+        let block_count = device.block_count().min(MAX_BLOCKS);
+        let superblock = Superblock::for_device(device.block_size(), block_count)?;
+        let mut buf = vec![0; device.block_size()];
+        for block in superblock.bitmap_start..superblock.data_start {
+            device.write_block(block, &buf)?;
+        }
+        let mut storage = Storage {
+            device,
+            superblock,
+            bitmap: Bitmap::new(&superblock),
+            free_inodes: (ROOT_INODE + 1..superblock.inode_count as u16).rev().collect(),
+        };
+        let root = Dir {
+            inode: ROOT_INODE,
+            entries: BTreeMap::new(),
+        };
+        let mut inode = Inode::free();
+        inode.kind = KIND_DIR;
+        storage.commit_inode(ROOT_INODE, &inode)?;
+        // The superblock goes last, so an interrupted format isn't mountable.
+        // The cache writes back in no particular order, so everything else
+        // is flushed ahead of it.
+        storage.device.flush()?;
+        superblock.encode(&mut buf);
+        storage.device.write_block(0, &buf)?;
+        storage.device.flush()?;
+        Ok(FileSystem { root, storage })
+    }
+
+    // Reads back a filesystem written by format().
+    pub fn mount(device: Box<dyn BlockDevice>) -> Result<Self, FsError> {
+        let device = BlockCache::new(device);
+        let mut buf = vec![0; device.block_size()];
+        device.read_block(0, &mut buf)?;
+        let superblock = Superblock::decode(&buf, &device)?;
+        let mut storage = Storage {
+            device,
+            superblock,
+            bitmap: Bitmap::new(&superblock),
+            free_inodes: Vec::new(),
+        };
+        storage.bitmap.read(&storage.device, superblock.bitmap_start)?;
+        if !(0..superblock.data_start).all(|block| storage.bitmap.is_set(block)) {
+            return Err(FsError::Corrupt);
+        }
+
+        let mut inodes = Vec::new();
+        for index in 0..superblock.inode_count as u16 {
+            let inode = storage.read_inode(index)?;
+            if inode.kind == KIND_FREE {
+                storage.free_inodes.push(index);
+                inodes.push(None);
+            } else {
+                inodes.push(Some(inode));
+            }
+        }
+        storage.free_inodes.reverse();
+        match &inodes[ROOT_INODE as usize] {
+            Some(root) if root.kind == KIND_DIR => {}
+            _ => return Err(FsError::Corrupt),
+        }
+
+        // A block claimed twice or marked free in the bitmap, or a parent
+        // that isn't a directory, means corruption.
+        let mut used = vec![false; superblock.block_count as usize];
+        let mut children = vec![Vec::new(); inodes.len()];
+        for (index, inode) in inodes.iter().enumerate().skip(1) {
+            let inode = match inode {
+                Some(inode) => inode,
+                None => continue,
+            };
+            for &block in &inode.blocks {
+                if core::mem::replace(&mut used[block as usize], true) || !storage.bitmap.is_set(block) {
+                    return Err(FsError::Corrupt);
+                }
+            }
+            match inodes.get(inode.parent as usize) {
+                Some(Some(parent)) if parent.kind == KIND_DIR => children[inode.parent as usize].push(index as u16),
+                _ => return Err(FsError::Corrupt),
+            }
+        }
+
+        let root = build_dir(ROOT_INODE, &mut inodes, &children);
+        Ok(FileSystem { root, storage })
+    }
+
+    // Writes all cached changes to the device, returning the number of
+    // blocks written.
+    pub fn sync(&self) -> Result<usize, FsError> {
+        Ok(self.storage.device.flush()?)
+    }
+
+    // Bytes of file data the data blocks can hold and how many are in use,
+    // in whole blocks.
+    pub fn capacity(&self) -> usize {
+        let superblock = &self.storage.superblock;
+        (superblock.block_count - superblock.data_start) as usize * self.storage.block_size()
+    }
+
+    pub fn used_space(&self) -> usize {
+        self.capacity() - self.free_space()
+    }
+
+    pub fn free_space(&self) -> usize {
+        self.storage.bitmap.free * self.storage.block_size()
+    }
+
+    fn dir(&self, path: &str) -> Result<&Dir, FsError> {
+        let mut dir = &self.root;
+        for component in components(path) {
+            match dir.entries.get(component) {
+                Some(Node::Dir(entries)) => dir = entries,
+                Some(Node::File(_)) => return Err(FsError::NotADirectory),
+                None => return Err(FsError::NotFound),
+            }
+        }
+        Ok(dir)
+    }
+
+    // Takes the root rather than `self` so the storage can be used while the
+    // directory is borrowed.
+    fn dir_mut<'a>(root: &'a mut Dir, path: &str) -> Result<&'a mut Dir, FsError> {
+        let mut dir = root;
+        for component in components(path) {
+            match dir.entries.get_mut(component) {
+                Some(Node::Dir(entries)) => dir = entries,
+                Some(Node::File(_)) => return Err(FsError::NotADirectory),
+                None => return Err(FsError::NotFound),
+            }
+        }
+        Ok(dir)
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = split_parent(path).ok_or(FsError::AlreadyExists)?;
+        validate_name(name)?;
+        let dir = FileSystem::dir_mut(&mut self.root, parent)?;
+        if dir.entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let node = Node::Dir(Dir {
+            inode: self.storage.alloc_inode()?,
+            entries: BTreeMap::new(),
+        });
+        self.storage.write_new_inode(&node, dir.inode, name)?;
+        dir.entries.insert(String::from(name), node);
+        Ok(())
+    }
+
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.dir(path).is_ok()
+    }
+
+    // Replaces the contents if the file already exists. The new contents are
+    // written, and the inode committed, before the old blocks are freed, so
+    // they must fit alongside the old ones; on any failure the existing
+    // contents are left alone.
+    pub fn create_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let (parent, name) = split_parent(path).ok_or(FsError::IsADirectory)?;
+        validate_name(name)?;
+        let storage = &mut self.storage;
+        if storage.blocks_for(data.len()) > DIRECT_BLOCKS {
+            return Err(FsError::FileTooLarge);
+        }
+        let dir = FileSystem::dir_mut(&mut self.root, parent)?;
+        let parent_inode = dir.inode;
+        let now = time::uptime_ticks();
+        match dir.entries.get_mut(name) {
+            Some(Node::Dir(_)) => Err(FsError::IsADirectory),
+            Some(Node::File(file)) => {
+                let blocks = storage.store(data)?;
+                let old_blocks = core::mem::replace(&mut file.blocks, blocks);
+                let old_meta = file.meta;
+                file.meta.size = data.len();
+                file.meta.modified_tick = now;
+                if let Err(err) = storage.commit_inode(file.inode, &file.to_inode(parent_inode, name)) {
+                    let blocks = core::mem::replace(&mut file.blocks, old_blocks);
+                    file.meta = old_meta;
+                    storage.release(&blocks)?;
+                    return Err(err);
+                }
+                storage.release_unreferenced(&old_blocks)
+            }
+            None => {
+                let inode = storage.alloc_inode()?;
+                let blocks = match storage.store(data) {
+                    Ok(blocks) => blocks,
+                    Err(err) => {
+                        storage.free_inodes.push(inode);
+                        return Err(err);
+                    }
+                };
+                let meta = FileMeta {
+                    size: data.len(),
+                    created_tick: now,
+                    modified_tick: now,
+                };
+                let node = Node::File(File { inode, blocks, meta });
+                if let Err(err) = storage.write_new_inode(&node, parent_inode, name) {
+                    if let Node::File(file) = node {
+                        storage.release(&file.blocks)?;
+                    }
+                    return Err(err);
+                }
+                dir.entries.insert(String::from(name), node);
+                Ok(())
+            }
+        }
+    }
+
+    // Extends the file, creating it if it doesn't exist. The contents are
+    // read back and rewritten as a whole.
+    pub fn append_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let mut contents = match self.read_file(path) {
+            Ok(contents) => contents,
+            Err(FsError::NotFound) => return self.create_file(path, data),
+            Err(err) => return Err(err),
+        };
+        contents.extend_from_slice(data);
+        self.create_file(path, &contents)
+    }
+
+    // Creates an empty file, or updates the modified tick of an existing one
+    // without touching its contents. Directories have no timestamps and are
+    // left as they are.
+    pub fn touch(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = match split_parent(path) {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let dir = FileSystem::dir_mut(&mut self.root, parent)?;
+        let parent_inode = dir.inode;
+        match dir.entries.get_mut(name) {
+            Some(Node::Dir(_)) => Ok(()),
+            Some(Node::File(file)) => {
+                file.meta.modified_tick = time::uptime_ticks();
+                self.storage.commit_inode(file.inode, &file.to_inode(parent_inode, name))
+            }
+            None => self.create_file(path, &[]),
+        }
+    }
+
+    fn file(&self, path: &str) -> Result<&File, FsError> {
+        let (parent, name) = split_parent(path).ok_or(FsError::IsADirectory)?;
+        match self.dir(parent)?.entries.get(name) {
+            Some(Node::File(file)) => Ok(file),
+            Some(Node::Dir(_)) => Err(FsError::IsADirectory),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        self.storage.load(self.file(path)?)
+    }
+
+    pub fn stat(&self, path: &str) -> Result<FileMeta, FsError> {
+        self.file(path).map(|file| file.meta)
+    }
+
+    // The file's blocks are freed with it. Directories are not removed.
+    pub fn delete_file(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = split_parent(path).ok_or(FsError::IsADirectory)?;
+        let dir = FileSystem::dir_mut(&mut self.root, parent)?;
+        match dir.entries.get(name) {
+            Some(Node::File(file)) => {
+                let inode = file.inode;
+                self.storage.commit_inode(inode, &Inode::free())?;
+                self.storage.free_inodes.push(inode);
+                if let Some(Node::File(file)) = dir.entries.remove(name) {
+                    self.storage.release_unreferenced(&file.blocks)?;
+                }
+                Ok(())
+            }
+            Some(Node::Dir(_)) => Err(FsError::IsADirectory),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    // Moves a file or directory, keeping its metadata. Fails if `to` already
+    // exists or would place a directory inside itself.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        let (from_parent, from_name) = split_parent(from).ok_or(FsError::InvalidName)?;
+        let (to_parent, to_name) = split_parent(to).ok_or(FsError::AlreadyExists)?;
+        validate_name(to_name)?;
+        let node = self.dir(from_parent)?.entries.get(from_name).ok_or(FsError::NotFound)?;
+        let to_dir = self.dir(to_parent)?;
+        if to_dir.entries.contains_key(to_name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let from_components: Vec<&str> = components(from).collect();
+        let to_components: Vec<&str> = components(to).collect();
+        if to_components.starts_with(&from_components) {
+            return Err(FsError::InvalidName);
+        }
+
+        // Only the moved node's own inode changes; anything below a moved
+        // directory still names it as parent.
+        let index = node.inode_number();
+        let inode = node.to_inode(to_dir.inode, to_name);
+        self.storage.write_inode(index, &inode)?;
+        let node = FileSystem::dir_mut(&mut self.root, from_parent)?
+            .entries
+            .remove(from_name)
+            .ok_or(FsError::NotFound)?;
+        FileSystem::dir_mut(&mut self.root, to_parent)?
+            .entries
+            .insert(String::from(to_name), node);
+        Ok(())
+    }
+
+    // Copies a file's contents to a new file with fresh timestamps,
+    // replacing `to` if it is a file. Copying a file onto itself is refused.
+    pub fn copy(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+        if resolve("/", from) == resolve("/", to) {
+            return Err(FsError::AlreadyExists);
+        }
+        let data = self.read_file(from)?;
+        if self.is_dir(to) {
+            return Err(FsError::IsADirectory);
+        }
+        // Check before deleting the old `to` so a failed copy leaves it intact.
+        let replaced = self.stat(to).map_or(0, |meta| self.storage.blocks_for(meta.size));
+        if self.storage.blocks_for(data.len()) > self.storage.bitmap.free + replaced {
+            return Err(FsError::OutOfSpace);
+        }
+        match self.delete_file(to) {
+            Ok(()) | Err(FsError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+        self.create_file(to, &data)
+    }
+
+    // Returns the absolute paths of all files and directories matching the
+    // glob. A pattern containing '/' is matched against the whole path, any
+    // other pattern against the last component, so "*.txt" finds text files
+    // in every directory.
+    pub fn find(&self, pattern: &str) -> Vec<String> {
+        let mut matches = Vec::new();
+        let mut pending = vec![(String::new(), &self.root)];
+        while let Some((prefix, dir)) = pending.pop() {
+            for (name, node) in &dir.entries {
+                let path = format!("{}/{}", prefix, name);
+                let subject = if pattern.contains('/') { path.as_str() } else { name.as_str() };
+                if glob_match(pattern, subject) {
+                    matches.push(path.clone());
+                }
+                if let Node::Dir(subdir) = node {
+                    pending.push((path, subdir));
+                }
+            }
+        }
+        matches.sort();
+        matches
+    }
+
+    // Lists the given directory (the root if None). Subdirectories are
+    // suffixed with '/'.
+    pub fn list_files(&self, dir: Option<&str>) -> Result<Vec<String>, FsError> {
+        let dir = self.dir(dir.unwrap_or("/"))?;
+        Ok(dir
+            .entries
+            .iter()
+            .map(|(name, node)| match node {
+                Node::Dir(_) => format!("{}/", name),
+                Node::File(_) => name.clone(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::{contents, SharedDisk};
+    use crate::block::{RamDisk, RAMDISK_BLOCK_SIZE};
+    use alloc::sync::Arc;
+
+    const DISK_BLOCKS: u32 = 256;
+
+    fn new_fs() -> FileSystem {
+        FileSystem::format(Box::new(RamDisk::new(DISK_BLOCKS))).unwrap()
+    }
+
+    #[test_case]
+    fn sync_writes_changes_to_the_device() {
+        let disk = Arc::new(RamDisk::new(DISK_BLOCKS));
+        let mut fs = FileSystem::format(Box::new(SharedDisk(disk.clone()))).unwrap();
+        let formatted = contents(&disk);
+        fs.create_file("/a", b"hello").unwrap();
+        assert!(contents(&disk) == formatted);
+        assert!(fs.sync().unwrap() > 0);
+        assert!(contents(&disk) != formatted);
+        let fs = FileSystem::mount(Box::new(SharedDisk(disk))).unwrap();
+        assert_eq!(fs.read_file("/a").unwrap(), b"hello");
+    }
+
+    #[test_case]
+    fn deleted_files_are_gone_from_the_listing() {
+        let mut fs = new_fs();
+        fs.create_file("/a", b"first").unwrap();
+        fs.create_file("/b", b"second").unwrap();
+        fs.delete_file("/a").unwrap();
+        assert_eq!(fs.list_files(None).unwrap(), ["b"]);
+        assert_eq!(fs.read_file("/a"), Err(FsError::NotFound));
+        assert_eq!(fs.delete_file("/a"), Err(FsError::NotFound));
+    }
+
+    #[test_case]
+    fn nested_directories_hold_their_own_entries() {
+        let mut fs = new_fs();
+        fs.mkdir("/docs").unwrap();
+        fs.mkdir("/docs/notes").unwrap();
+        fs.create_file("/docs/notes/todo.txt", b"write tests").unwrap();
+        fs.create_file("docs/readme", b"hi").unwrap();
+        assert_eq!(fs.list_files(None).unwrap(), ["docs/"]);
+        assert_eq!(fs.list_files(Some("/docs")).unwrap(), ["notes/", "readme"]);
+        assert_eq!(fs.list_files(Some("/docs/notes")).unwrap(), ["todo.txt"]);
+        assert_eq!(fs.read_file("/docs//notes/todo.txt/").unwrap(), b"write tests");
+        assert!(fs.is_dir("/docs/notes"));
+        assert_eq!(fs.list_files(Some("/missing")), Err(FsError::NotFound));
+    }
+
+    #[test_case]
+    fn stat_tracks_size_and_modification() {
+        let mut fs = new_fs();
+        fs.create_file("/f", b"hello").unwrap();
+        let before = fs.stat("/f").unwrap();
+        assert_eq!(before.size, 5);
+        assert_eq!(before.created_tick, before.modified_tick);
+        time::sleep(2);
+        fs.create_file("/f", &[b'x'; 1000]).unwrap();
+        let after = fs.stat("/f").unwrap();
+        assert_eq!(after.size, 1000);
+        assert_eq!(after.created_tick, before.created_tick);
+        assert!(after.modified_tick > before.modified_tick);
+    }
+
+    #[test_case]
+    fn touch_creates_or_updates_without_writing() {
+        let mut fs = new_fs();
+        fs.touch("/new").unwrap();
+        assert_eq!(fs.read_file("/new").unwrap(), b"");
+        assert_eq!(fs.stat("/new").unwrap().size, 0);
+
+        fs.create_file("/old", b"keep me").unwrap();
+        let before = fs.stat("/old").unwrap();
+        time::sleep(2);
+        fs.touch("/old").unwrap();
+        let after = fs.stat("/old").unwrap();
+        assert_eq!(fs.read_file("/old").unwrap(), b"keep me");
+        assert_eq!((after.size, after.created_tick), (before.size, before.created_tick));
+        assert!(after.modified_tick > before.modified_tick);
+        assert_eq!(fs.touch("/missing/file"), Err(FsError::NotFound));
+    }
+
+    #[test_case]
+    fn rename_moves_a_file_and_keeps_its_metadata() {
+        let mut fs = new_fs();
+        fs.create_file("/a", b"data").unwrap();
+        fs.create_file("/b", b"other").unwrap();
+        let meta = fs.stat("/a").unwrap();
+        time::sleep(1);
+        fs.rename("/a", "/c").unwrap();
+        assert_eq!(fs.read_file("/c").unwrap(), b"data");
+        assert_eq!(fs.stat("/c").unwrap(), meta);
+        assert_eq!(fs.read_file("/a"), Err(FsError::NotFound));
+        assert_eq!(fs.rename("/a", "/d"), Err(FsError::NotFound));
+        assert_eq!(fs.rename("/c", "/b"), Err(FsError::AlreadyExists));
+        assert_eq!(fs.read_file("/b").unwrap(), b"other");
+        fs.mkdir("/dir").unwrap();
+        assert_eq!(fs.rename("/dir", "/dir/inside"), Err(FsError::InvalidName));
+    }
+
+    #[test_case]
+    fn copies_are_independent_of_their_source() {
+        let mut fs = new_fs();
+        fs.create_file("/src", b"original").unwrap();
+        fs.copy("/src", "/dst").unwrap();
+        fs.create_file("/src", b"changed").unwrap();
+        fs.append_file("/src", b" again").unwrap();
+        assert_eq!(fs.read_file("/dst").unwrap(), b"original");
+        fs.delete_file("/src").unwrap();
+        assert_eq!(fs.read_file("/dst").unwrap(), b"original");
+        assert_eq!(fs.copy("/src", "/other"), Err(FsError::NotFound));
+        assert_eq!(fs.copy("/dst", "/dst"), Err(FsError::AlreadyExists));
+        assert_eq!(fs.read_file("/dst").unwrap(), b"original");
+    }
+
+    #[test_case]
+    fn each_misuse_gives_its_own_error() {
+        let mut fs = new_fs();
+        fs.mkdir("/dir").unwrap();
+        fs.create_file("/f", b"file").unwrap();
+        assert_eq!(fs.read_file("/missing"), Err(FsError::NotFound));
+        assert_eq!(fs.mkdir("/dir"), Err(FsError::AlreadyExists));
+        let long_name = format!("/{}", "n".repeat(MAX_NAME_LEN + 1));
+        assert_eq!(fs.create_file(&long_name, b""), Err(FsError::NameTooLong));
+        assert_eq!(fs.create_file("/tab\there", b""), Err(FsError::InvalidName));
+        assert_eq!(fs.create_file("/dir/..", b""), Err(FsError::InvalidName));
+        assert_eq!(fs.read_file("/dir"), Err(FsError::IsADirectory));
+        assert_eq!(fs.create_file("/f/x", b""), Err(FsError::NotADirectory));
+        let too_large = vec![0; DIRECT_BLOCKS * RAMDISK_BLOCK_SIZE + 1];
+        assert_eq!(fs.create_file("/big", &too_large), Err(FsError::FileTooLarge));
+        // Too small to hold anything past the metadata.
+        assert!(matches!(FileSystem::format(Box::new(RamDisk::new(2))), Err(FsError::OutOfSpace)));
+    }
+
+    #[test_case]
+    fn mounting_a_bad_device_fails_cleanly() {
+        fn mount(disk: impl BlockDevice + 'static) -> Option<FsError> {
+            FileSystem::mount(Box::new(disk)).err()
+        }
+        assert_eq!(mount(RamDisk::new(DISK_BLOCKS)), Some(FsError::NotFormatted));
+        assert_eq!(mount(RamDisk::new(0)), Some(FsError::Io(BlockError::OutOfRange)));
+
+        let disk = Arc::new(RamDisk::new(DISK_BLOCKS));
+        FileSystem::format(Box::new(SharedDisk(disk.clone()))).unwrap();
+        // Clear the bitmap, which then claims the metadata blocks are free.
+        disk.write_block(1, &[0; RAMDISK_BLOCK_SIZE]).unwrap();
+        assert_eq!(mount(SharedDisk(disk)), Some(FsError::Corrupt));
+
+        // A large disk whose superblock is well formed but claims far more
+        // blocks than format() ever uses.
+        struct Huge(RamDisk);
+        impl BlockDevice for Huge {
+            fn block_size(&self) -> usize {
+                self.0.block_size()
+            }
+
+            fn block_count(&self) -> u32 {
+                u32::MAX
+            }
+
+            fn read_block(&self, index: u32, buf: &mut [u8]) -> Result<(), BlockError> {
+                self.0.read_block(index, buf)
+            }
+
+            fn write_block(&self, index: u32, buf: &[u8]) -> Result<(), BlockError> {
+                self.0.write_block(index, buf)
+            }
+        }
+        let disk = Huge(RamDisk::new(DISK_BLOCKS));
+        let mut buf = [0; RAMDISK_BLOCK_SIZE];
+        Superblock::for_device(RAMDISK_BLOCK_SIZE, u32::MAX).unwrap().encode(&mut buf);
+        disk.write_block(0, &buf).unwrap();
+        assert_eq!(mount(disk), Some(FsError::Corrupt));
+    }
+
+    #[test_case]
+    fn a_full_filesystem_rejects_writes_and_keeps_its_files() {
+        let mut fs = new_fs();
+        let chunk = vec![0xA5; DIRECT_BLOCKS * RAMDISK_BLOCK_SIZE];
+        let mut written = 0;
+        let err = loop {
+            match fs.create_file(&format!("/f{}", written), &chunk) {
+                Ok(()) => written += 1,
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err, FsError::OutOfSpace);
+        assert!(written > 0);
+        assert!(fs.free_space() < chunk.len());
+        assert_eq!(fs.stat(&format!("/f{}", written)), Err(FsError::NotFound));
+        let free = fs.free_space();
+        assert_eq!(fs.create_file("/extra", &chunk[..free + 1]), Err(FsError::OutOfSpace));
+        assert_eq!(fs.append_file("/f0", b"more"), Err(FsError::FileTooLarge));
+        for n in 0..written {
+            assert!(fs.read_file(&format!("/f{}", n)).unwrap() == chunk);
+        }
+        assert_eq!(fs.free_space(), free);
+        assert_eq!(fs.used_space() + free, fs.capacity());
+    }
+
+    #[test_case]
+    fn appends_follow_the_existing_contents() {
+        let mut fs = new_fs();
+        fs.append_file("/log", b"one\n").unwrap();
+        let created = fs.stat("/log").unwrap();
+        time::sleep(1);
+        fs.append_file("/log", b"two\n").unwrap();
+        fs.append_file("/log", b"three\n").unwrap();
+        assert_eq!(fs.read_file("/log").unwrap(), b"one\ntwo\nthree\n");
+        let meta = fs.stat("/log").unwrap();
+        assert_eq!(meta.size, 14);
+        assert_eq!(meta.created_tick, created.created_tick);
+        assert!(meta.modified_tick > created.modified_tick);
+    }
+
+    #[test_case]
+    fn glob_wildcards_match_as_documented() {
+        assert!(glob_match("*.txt", "notes.txt"));
+        assert!(glob_match("*.txt", ".txt"));
+        assert!(!glob_match("*.txt", "notes.txt.bak"));
+        assert!(glob_match("doc?", "docs"));
+        assert!(!glob_match("doc?", "doc"));
+        assert!(glob_match("/a*/b", "/a/x/y/b"));
+        assert!(glob_match("readme", "readme"));
+        assert!(!glob_match("readme", "readme2"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test_case]
+    fn find_searches_the_whole_tree() {
+        let mut fs = new_fs();
+        fs.mkdir("/docs").unwrap();
+        fs.mkdir("/docs/doc1").unwrap();
+        fs.create_file("/a.txt", b"").unwrap();
+        fs.create_file("/docs/b.txt", b"").unwrap();
+        fs.create_file("/docs/doc1/c.md", b"").unwrap();
+        assert_eq!(fs.find("*.txt"), ["/a.txt", "/docs/b.txt"]);
+        assert_eq!(fs.find("doc?"), ["/docs", "/docs/doc1"]);
+        assert_eq!(fs.find("c.md"), ["/docs/doc1/c.md"]);
+        assert_eq!(fs.find("/docs/*.md"), ["/docs/doc1/c.md"]);
+        assert!(fs.find("missing").is_empty());
+    }
+
+    #[test_case]
+    fn files_and_directories_survive_a_remount() {
+        let disk = Arc::new(RamDisk::new(DISK_BLOCKS));
+        let mut fs = FileSystem::format(Box::new(SharedDisk(disk.clone()))).unwrap();
+        let large: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        fs.create_file("/small", b"tiny").unwrap();
+        fs.mkdir("/dir").unwrap();
+        fs.create_file("/dir/large", &large).unwrap();
+        fs.create_file("/dir/empty", b"").unwrap();
+        let meta = fs.stat("/dir/large").unwrap();
+        let free = fs.free_space();
+        fs.sync().unwrap();
+        drop(fs);
+
+        let mut fs = FileSystem::mount(Box::new(SharedDisk(disk))).unwrap();
+        assert_eq!(fs.list_files(None).unwrap(), ["dir/", "small"]);
+        assert_eq!(fs.list_files(Some("/dir")).unwrap(), ["empty", "large"]);
+        assert_eq!(fs.read_file("/small").unwrap(), b"tiny");
+        assert!(fs.read_file("/dir/large").unwrap() == large);
+        assert_eq!(fs.read_file("/dir/empty").unwrap(), b"");
+        assert_eq!(fs.stat("/dir/large").unwrap(), meta);
+        assert_eq!(fs.free_space(), free);
+        // Still writable after mounting.
+        fs.create_file("/dir/new", b"after").unwrap();
+        assert_eq!(fs.read_file("/dir/new").unwrap(), b"after");
+    }
+
+    #[test_case]
+    fn freed_blocks_are_handed_out_before_running_out() {
+        let superblock = Superblock::for_device(RAMDISK_BLOCK_SIZE, 100).unwrap();
+        let mut bitmap = Bitmap::new(&superblock);
+        let mut blocks = Vec::new();
+        while let Some(block) = bitmap.alloc_block() {
+            blocks.push(block);
+        }
+        assert_eq!(blocks.len(), (100 - superblock.data_start) as usize);
+        assert_eq!(bitmap.free, 0);
+        for &block in &[blocks[40], blocks[3], blocks[17]] {
+            bitmap.free_block(block).unwrap();
+        }
+        assert_eq!(bitmap.alloc_block(), Some(blocks[3]));
+        assert_eq!(bitmap.alloc_block(), Some(blocks[17]));
+        assert_eq!(bitmap.alloc_block(), Some(blocks[40]));
+        assert_eq!(bitmap.alloc_block(), None);
+        // Double and out-of-range frees are refused.
+        bitmap.free_block(blocks[0]).unwrap();
+        assert_eq!(bitmap.free_block(blocks[0]), Err(FsError::Corrupt));
+        assert_eq!(bitmap.free_block(100), Err(FsError::Corrupt));
+        assert_eq!(bitmap.free, 1);
+    }
+
+    #[test_case]
+    fn deleted_files_give_their_blocks_back() {
+        let mut fs = new_fs();
+        let free = fs.free_space();
+        fs.create_file("/a", &[1; 2000]).unwrap();
+        assert!(fs.free_space() < free);
+        fs.delete_file("/a").unwrap();
+        assert_eq!(fs.free_space(), free);
+    }
+}
@@ -0,0 +1,267 @@
+// An 8x16 bitmap font for printable ASCII (0x20-0x7E), rasterized from
+// DejaVu Sans Mono Bold. Each glyph is 16 rows from the top; the most
+// significant bit of a row is its leftmost pixel.
+//
+// DejaVu fonts are (c) Bitstream, as below; DejaVu changes are in the public
+// domain. The glyphs here are a modified rasterization, so by the terms
+// below this font is not called Bitstream Vera.
+//
+// Bitstream Vera Fonts Copyright
+//
+// Copyright (c) 2003 by Bitstream, Inc. All Rights Reserved. Bitstream Vera
+// is a trademark of Bitstream, Inc.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of the fonts accompanying this license ("Fonts") and associated
+// documentation files (the "Font Software"), to reproduce and distribute the
+// Font Software, including without limitation the rights to use, copy,
+// merge, publish, distribute, and/or sell copies of the Font Software, and
+// to permit persons to whom the Font Software is furnished to do so, subject
+// to the following conditions:
+//
+// The above copyright and trademark notices and this permission notice
+// shall be included in all copies of one or more of the Font Software
+// typefaces.
+//
+// The Font Software may be modified, altered, or added to, and in particular
+// the designs of glyphs or characters in the Fonts may be modified and
+// additional glyphs or characters may be added to the Fonts, only if the
+// fonts are renamed to names not containing either the words "Bitstream" or
+// the word "Vera".
+//
+// This License becomes null and void to the extent applicable to Fonts or
+// Font Software that has been modified and is distributed under the
+// "Bitstream Vera" names.
+//
+// The Font Software may be sold as part of a larger software package but no
+// copy of one or more of the Bitstream Vera Font Software typefaces may be
+// sold by itself.
+//
+// THE FONT SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO ANY WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT OF
+// COPYRIGHT, PATENT, TRADEMARK, OR OTHER RIGHT. IN NO EVENT SHALL BITSTREAM
+// OR THE GNOME FOUNDATION BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, INCLUDING ANY GENERAL, SPECIAL, INDIRECT, INCIDENTAL, OR
+// CONSEQUENTIAL DAMAGES, WHETHER IN AN ACTION OF CONTRACT, TORT OR
+// OTHERWISE, ARISING FROM, OUT OF THE USE OR INABILITY TO USE THE FONT
+// SOFTWARE OR FROM OTHER DEALINGS IN THE FONT SOFTWARE.
+//
+// Except as contained in this notice, the names of Gnome, the Gnome
+// Foundation, and Bitstream Inc., shall not be used in advertising or
+// otherwise to promote the sale, use or other dealings in this Font Software
+// without prior written authorization from the Gnome Foundation or Bitstream
+// Inc., respectively. For further information, contact: fonts at gnome dot
+// org.
+
+pub const GLYPH_WIDTH: usize = 8;
+pub const GLYPH_HEIGHT: usize = 16;
+
+const FIRST: u8 = 0x20;
+const LAST: u8 = 0x7E;
+
+// Drawn for bytes outside the table.
+const REPLACEMENT: [u8; GLYPH_HEIGHT] = [
+    0x00, 0x00, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x7E, 0x00, 0x00, 0x00, 0x00,
+];
+
+pub fn glyph(byte: u8) -> &'static [u8; GLYPH_HEIGHT] {
+    if (FIRST..=LAST).contains(&byte) {
+        &FONT[usize::from(byte - FIRST)]
+    } else {
+        &REPLACEMENT
+    }
+}
+
+const FONT: [[u8; GLYPH_HEIGHT]; (LAST - FIRST + 1) as usize] = [
+    // space
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // !
+    [0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // "
+    [0x00, 0x00, 0x24, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // #
+    [0x00, 0x00, 0x12, 0x12, 0x16, 0x7F, 0x34, 0x24, 0xFE, 0xFE, 0x68, 0x48, 0x00, 0x00, 0x00, 0x00],
+    // $
+    [0x00, 0x00, 0x18, 0x18, 0x7E, 0x78, 0x78, 0x3C, 0x1E, 0x1E, 0x7E, 0x7C, 0x18, 0x18, 0x00, 0x00],
+    // %
+    [0x00, 0x00, 0x00, 0x70, 0xD0, 0xD0, 0x66, 0x18, 0x4E, 0x09, 0x0B, 0x06, 0x00, 0x00, 0x00, 0x00],
+    // &
+    [0x00, 0x00, 0x3C, 0x3C, 0x60, 0x30, 0x70, 0x79, 0xCF, 0xCF, 0x6E, 0x7F, 0x00, 0x00, 0x00, 0x00],
+    // quote
+    [0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // (
+    [0x00, 0x00, 0x0C, 0x08, 0x18, 0x18, 0x10, 0x30, 0x30, 0x18, 0x18, 0x18, 0x08, 0x0C, 0x00, 0x00],
+    // )
+    [0x00, 0x00, 0x30, 0x10, 0x18, 0x18, 0x08, 0x0C, 0x0C, 0x18, 0x18, 0x18, 0x10, 0x30, 0x00, 0x00],
+    // *
+    [0x00, 0x00, 0x00, 0x5A, 0x7E, 0x3C, 0x7E, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // +
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0xFF, 0x7E, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // ,
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x10, 0x00, 0x00],
+    // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3C, 0x3C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // .
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // /
+    [0x00, 0x00, 0x02, 0x06, 0x04, 0x0C, 0x08, 0x18, 0x10, 0x30, 0x20, 0x60, 0x60, 0x00, 0x00, 0x00],
+    // 0
+    [0x00, 0x00, 0x3C, 0x7E, 0x66, 0x66, 0x66, 0x7E, 0x66, 0x66, 0x7E, 0x3C, 0x00, 0x00, 0x00, 0x00],
+    // 1
+    [0x00, 0x00, 0x38, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x7E, 0x00, 0x00, 0x00, 0x00],
+    // 2
+    [0x00, 0x00, 0x7C, 0x7E, 0x06, 0x06, 0x0C, 0x1C, 0x38, 0x30, 0x7E, 0x7E, 0x00, 0x00, 0x00, 0x00],
+    // 3
+    [0x00, 0x00, 0x7C, 0x7E, 0x06, 0x06, 0x3C, 0x1C, 0x06, 0x06, 0x7E, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // 4
+    [0x00, 0x00, 0x0C, 0x0C, 0x1C, 0x3C, 0x2C, 0x6C, 0x7E, 0x7F, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x00],
+    // 5
+    [0x00, 0x00, 0x7C, 0x7E, 0x60, 0x60, 0x7C, 0x0E, 0x06, 0x06, 0x7E, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // 6
+    [0x00, 0x00, 0x1C, 0x3E, 0x60, 0x60, 0x7E, 0x66, 0x66, 0x66, 0x76, 0x3C, 0x00, 0x00, 0x00, 0x00],
+    // 7
+    [0x00, 0x00, 0x7E, 0x7E, 0x06, 0x0C, 0x0C, 0x0C, 0x18, 0x18, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00],
+    // 8
+    [0x00, 0x00, 0x3C, 0x7E, 0x66, 0x66, 0x3C, 0x3C, 0x66, 0x66, 0x6E, 0x3C, 0x00, 0x00, 0x00, 0x00],
+    // 9
+    [0x00, 0x00, 0x38, 0x7C, 0x66, 0x66, 0x66, 0x7E, 0x3E, 0x06, 0x4E, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // :
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // ;
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x18, 0x10, 0x00, 0x00],
+    // <
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0x3C, 0xE0, 0x70, 0x1E, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // =
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0xFF, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // >
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x3C, 0x07, 0x0E, 0x78, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // ?
+    [0x00, 0x00, 0x3C, 0x7E, 0x06, 0x06, 0x0C, 0x18, 0x18, 0x10, 0x10, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // @
+    [0x00, 0x00, 0x00, 0x3C, 0x66, 0x43, 0xDF, 0x93, 0xB3, 0x93, 0xDF, 0x40, 0x72, 0x3E, 0x00, 0x00],
+    // A
+    [0x00, 0x00, 0x18, 0x3C, 0x3C, 0x3C, 0x24, 0x66, 0x7E, 0x7E, 0x66, 0xC3, 0x00, 0x00, 0x00, 0x00],
+    // B
+    [0x00, 0x00, 0x78, 0x7E, 0x66, 0x66, 0x7C, 0x7E, 0x66, 0x67, 0x7E, 0x7E, 0x00, 0x00, 0x00, 0x00],
+    // C
+    [0x00, 0x00, 0x1E, 0x3E, 0x70, 0x60, 0x60, 0x60, 0x60, 0x60, 0x3E, 0x1E, 0x00, 0x00, 0x00, 0x00],
+    // D
+    [0x00, 0x00, 0x78, 0x7C, 0x66, 0x66, 0x66, 0x67, 0x66, 0x66, 0x7E, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // E
+    [0x00, 0x00, 0x7E, 0x7E, 0x60, 0x60, 0x7E, 0x7E, 0x60, 0x60, 0x7E, 0x7E, 0x00, 0x00, 0x00, 0x00],
+    // F
+    [0x00, 0x00, 0x7E, 0x7E, 0x60, 0x60, 0x7E, 0x7E, 0x60, 0x60, 0x60, 0x60, 0x00, 0x00, 0x00, 0x00],
+    // G
+    [0x00, 0x00, 0x1E, 0x3E, 0x60, 0x60, 0x60, 0x6E, 0x66, 0x62, 0x7E, 0x3E, 0x00, 0x00, 0x00, 0x00],
+    // H
+    [0x00, 0x00, 0x66, 0x66, 0x66, 0x66, 0x7E, 0x7E, 0x66, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // I
+    [0x00, 0x00, 0x7E, 0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x7E, 0x00, 0x00, 0x00, 0x00],
+    // J
+    [0x00, 0x00, 0x3C, 0x3E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x0E, 0x7C, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // K
+    [0x00, 0x00, 0x62, 0x66, 0x6C, 0x78, 0x78, 0x7C, 0x6C, 0x6E, 0x66, 0x67, 0x00, 0x00, 0x00, 0x00],
+    // L
+    [0x00, 0x00, 0x20, 0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x7E, 0x7F, 0x00, 0x00, 0x00, 0x00],
+    // M
+    [0x00, 0x00, 0x66, 0xE7, 0xE7, 0xFF, 0xFF, 0xDB, 0xC3, 0xC3, 0xC3, 0xC3, 0x00, 0x00, 0x00, 0x00],
+    // N
+    [0x00, 0x00, 0x62, 0x66, 0x76, 0x76, 0x76, 0x7E, 0x6E, 0x6E, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // O
+    [0x00, 0x00, 0x3C, 0x7E, 0x66, 0x66, 0x66, 0xE7, 0x66, 0x66, 0x7E, 0x3C, 0x00, 0x00, 0x00, 0x00],
+    // P
+    [0x00, 0x00, 0x78, 0x7E, 0x66, 0x67, 0x66, 0x7E, 0x60, 0x60, 0x60, 0x60, 0x00, 0x00, 0x00, 0x00],
+    // Q
+    [0x00, 0x00, 0x3C, 0x7E, 0x66, 0x66, 0x66, 0xE7, 0x66, 0x66, 0x7E, 0x3C, 0x06, 0x04, 0x00, 0x00],
+    // R
+    [0x00, 0x00, 0x78, 0x7E, 0x66, 0x66, 0x7E, 0x7C, 0x6C, 0x66, 0x66, 0x63, 0x00, 0x00, 0x00, 0x00],
+    // S
+    [0x00, 0x00, 0x3C, 0x7E, 0x60, 0x60, 0x78, 0x1E, 0x06, 0x06, 0x7E, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // T
+    [0x00, 0x00, 0x7E, 0xFF, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // U
+    [0x00, 0x00, 0x42, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x7E, 0x3C, 0x00, 0x00, 0x00, 0x00],
+    // V
+    [0x00, 0x00, 0x42, 0x66, 0x66, 0x66, 0x66, 0x24, 0x3C, 0x3C, 0x3C, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // W
+    [0x00, 0x00, 0xC3, 0xC3, 0xC3, 0xDB, 0xDB, 0x7A, 0x7E, 0x76, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // X
+    [0x00, 0x00, 0x42, 0x66, 0x7E, 0x3C, 0x18, 0x18, 0x3C, 0x3C, 0x66, 0xC3, 0x00, 0x00, 0x00, 0x00],
+    // Y
+    [0x00, 0x00, 0xC3, 0x66, 0x66, 0x3C, 0x3C, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // Z
+    [0x00, 0x00, 0x7E, 0x7F, 0x06, 0x0C, 0x1C, 0x18, 0x30, 0x70, 0x7E, 0x7F, 0x00, 0x00, 0x00, 0x00],
+    // [
+    [0x00, 0x00, 0x1C, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1C, 0x1C, 0x00, 0x00],
+    // backslash
+    [0x00, 0x00, 0x40, 0x60, 0x20, 0x30, 0x10, 0x18, 0x08, 0x0C, 0x0C, 0x06, 0x06, 0x00, 0x00, 0x00],
+    // ]
+    [0x00, 0x00, 0x38, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x38, 0x38, 0x00, 0x00],
+    // ^
+    [0x00, 0x00, 0x18, 0x3C, 0x66, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // _
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00],
+    // `
+    [0x00, 0x20, 0x30, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // a
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x7E, 0x06, 0x3E, 0x7E, 0x66, 0x66, 0x7E, 0x00, 0x00, 0x00, 0x00],
+    // b
+    [0x00, 0x00, 0x60, 0x60, 0x60, 0x7E, 0x76, 0x66, 0x67, 0x66, 0x76, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // c
+    [0x00, 0x00, 0x00, 0x00, 0x08, 0x3E, 0x72, 0x60, 0x60, 0x60, 0x32, 0x3E, 0x00, 0x00, 0x00, 0x00],
+    // d
+    [0x00, 0x00, 0x06, 0x06, 0x06, 0x7E, 0x6E, 0x66, 0xE6, 0x66, 0x6E, 0x3E, 0x00, 0x00, 0x00, 0x00],
+    // e
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3E, 0x66, 0x7E, 0xFF, 0x60, 0x72, 0x3E, 0x00, 0x00, 0x00, 0x00],
+    // f
+    [0x00, 0x00, 0x1E, 0x18, 0x18, 0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // g
+    [0x00, 0x00, 0x00, 0x00, 0x10, 0x7E, 0x66, 0x66, 0x66, 0x66, 0x7E, 0x3E, 0x06, 0x7E, 0x38, 0x00],
+    // h
+    [0x00, 0x00, 0x60, 0x60, 0x60, 0x7E, 0x76, 0x66, 0x66, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // i
+    [0x00, 0x18, 0x18, 0x08, 0x00, 0x78, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x7F, 0x00, 0x00, 0x00, 0x00],
+    // j
+    [0x00, 0x08, 0x1C, 0x08, 0x00, 0x3C, 0x1C, 0x1C, 0x1C, 0x1C, 0x1C, 0x1C, 0x1C, 0x78, 0x70, 0x00],
+    // k
+    [0x00, 0x00, 0x60, 0x60, 0x60, 0x66, 0x6C, 0x78, 0x7C, 0x6C, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // l
+    [0x00, 0x00, 0xF8, 0x38, 0x38, 0x38, 0x38, 0x38, 0x38, 0x38, 0x1E, 0x1E, 0x00, 0x00, 0x00, 0x00],
+    // m
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xFE, 0xDB, 0xDB, 0xDB, 0xDB, 0xDB, 0xDB, 0x00, 0x00, 0x00, 0x00],
+    // n
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0x76, 0x66, 0x66, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // o
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3C, 0x66, 0x66, 0x66, 0x66, 0x7E, 0x3C, 0x00, 0x00, 0x00, 0x00],
+    // p
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0x76, 0x66, 0x67, 0x66, 0x7E, 0x7C, 0x60, 0x60, 0x60, 0x00],
+    // q
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0x6E, 0x66, 0xE6, 0x66, 0x7E, 0x3E, 0x06, 0x06, 0x06, 0x00],
+    // r
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3F, 0x38, 0x30, 0x30, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00],
+    // s
+    [0x00, 0x00, 0x00, 0x00, 0x08, 0x3E, 0x60, 0x70, 0x3C, 0x06, 0x46, 0x7C, 0x00, 0x00, 0x00, 0x00],
+    // t
+    [0x00, 0x00, 0x00, 0x38, 0x38, 0x7E, 0x38, 0x38, 0x38, 0x38, 0x1E, 0x1E, 0x00, 0x00, 0x00, 0x00],
+    // u
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x66, 0x66, 0x66, 0x66, 0x7E, 0x3E, 0x00, 0x00, 0x00, 0x00],
+    // v
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x66, 0x66, 0x2C, 0x3C, 0x3C, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // w
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0xC3, 0xC3, 0xDB, 0x5A, 0x7E, 0x7E, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // x
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x3C, 0x1C, 0x18, 0x3C, 0x6E, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // y
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x66, 0x66, 0x3C, 0x3C, 0x18, 0x18, 0x18, 0x70, 0x60, 0x00],
+    // z
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0x0E, 0x0C, 0x18, 0x30, 0x7E, 0x7E, 0x00, 0x00, 0x00, 0x00],
+    // {
+    [0x00, 0x00, 0x0E, 0x18, 0x18, 0x18, 0x18, 0x38, 0x70, 0x18, 0x18, 0x18, 0x18, 0x0E, 0x00, 0x00],
+    // |
+    [0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00],
+    // }
+    [0x00, 0x00, 0x70, 0x18, 0x18, 0x18, 0x18, 0x1C, 0x0E, 0x18, 0x18, 0x18, 0x18, 0x70, 0x00, 0x00],
+    // ~
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7F, 0x0E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
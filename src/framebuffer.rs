@@ -0,0 +1,385 @@
+// A linear framebuffer with a pixel-drawing API. Colors are 0xRRGGBB and are
+// converted to the framebuffer's pixel format when drawn. Drawing goes to a
+// back buffer in RAM, which present() copies to the screen, so the screen
+// never shows a half-drawn update.
+//
+// Bootloader 0.9 leaves the machine in VGA text mode and passes no
+// framebuffer, so init_bochs() can set a mode itself on the display adapter
+// QEMU and Bochs emulate.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::{self, Access};
+use crate::pci;
+
+// Where the framebuffer is mapped.
+const FRAMEBUFFER_VIRT_ADDR: u64 = 0x_7777_7777_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    pub address: PhysAddr,
+    pub width: usize,
+    pub height: usize,
+    // Bytes from the start of one row to the next.
+    pub pitch: usize,
+    // 16 (RGB565), 24 or 32 bits per pixel, blue in the lowest byte.
+    pub bpp: u8,
+}
+
+impl FramebufferInfo {
+    pub fn size(&self) -> usize {
+        self.pitch * self.height
+    }
+}
+
+pub struct Framebuffer {
+    // The hardware framebuffer, only written by present().
+    front: *mut u8,
+    // Everything is drawn here first, in the front buffer's format.
+    back: Vec<u8>,
+    info: FramebufferInfo,
+    // Rows drawn to since the last present().
+    dirty: Range<usize>,
+}
+
+// The framebuffer is only reached through FRAMEBUFFER's lock.
+unsafe impl Send for Framebuffer {}
+
+fn encode_rgb565(rgb: u32) -> u16 {
+    let (r, g, b) = ((rgb >> 16) & 0xFF, (rgb >> 8) & 0xFF, rgb & 0xFF);
+    ((r >> 3) << 11 | (g >> 2) << 5 | (b >> 3)) as u16
+}
+
+impl Framebuffer {
+    pub fn width(&self) -> usize {
+        self.info.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.info.height
+    }
+
+    fn mark_dirty(&mut self, rows: Range<usize>) {
+        if self.dirty.is_empty() {
+            self.dirty = rows;
+        } else {
+            self.dirty = self.dirty.start.min(rows.start)..self.dirty.end.max(rows.end);
+        }
+    }
+
+    // Pixels outside the screen are ignored.
+    pub fn put_pixel(&mut self, x: usize, y: usize, rgb: u32) {
+        if x >= self.info.width || y >= self.info.height {
+            return;
+        }
+        let offset = y * self.info.pitch + x * usize::from(self.info.bpp / 8);
+        let pixel = &mut self.back[offset..];
+        match self.info.bpp {
+            32 => pixel[..4].copy_from_slice(&rgb.to_le_bytes()),
+            24 => pixel[..3].copy_from_slice(&rgb.to_le_bytes()[..3]),
+            16 => pixel[..2].copy_from_slice(&encode_rgb565(rgb).to_le_bytes()),
+            _ => return,
+        }
+        self.mark_dirty(y..y + 1);
+    }
+
+    // Fills the part of the rectangle that is on screen.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, rgb: u32) {
+        let x_end = x.saturating_add(width).min(self.info.width);
+        let y_end = y.saturating_add(height).min(self.info.height);
+        for row in y..y_end {
+            for column in x..x_end {
+                self.put_pixel(column, row, rgb);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, rgb: u32) {
+        self.fill_rect(0, 0, self.info.width, self.info.height, rgb);
+    }
+
+    // Copies `count` pixel rows starting at row `from` to row `to`. The
+    // ranges may overlap; rows past the bottom of the screen are skipped.
+    pub fn copy_rows(&mut self, from: usize, to: usize, count: usize) {
+        let height = self.info.height;
+        if from >= height || to >= height {
+            return;
+        }
+        let count = count.min(height - from).min(height - to);
+        let pitch = self.info.pitch;
+        self.back.copy_within(from * pitch..(from + count) * pitch, to * pitch);
+        self.mark_dirty(to..to + count);
+    }
+
+    // Copies the rows drawn to since the last call to the screen.
+    pub fn present(&mut self) {
+        let rows = core::mem::replace(&mut self.dirty, 0..0);
+        if rows.is_empty() {
+            return;
+        }
+        let pitch = self.info.pitch;
+        let (start, len) = (rows.start * pitch, rows.len() * pitch);
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.back[start..].as_ptr(), self.front.add(start), len);
+        }
+    }
+}
+
+static FRAMEBUFFER: Mutex<Option<Framebuffer>> = Mutex::new(None);
+// Mirrors FRAMEBUFFER.is_some() so the print path can check it without
+// taking the lock.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Runs `f` on the framebuffer with interrupts held off. Returns None if there
+// is no framebuffer.
+pub fn with_framebuffer<R>(f: impl FnOnce(&mut Framebuffer) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| FRAMEBUFFER.lock().as_mut().map(f))
+}
+
+// Would make present() wait for vertical retrace. Nothing waits yet; this is
+// where a driver that can will hook in.
+#[allow(dead_code)]
+pub fn set_vsync_hint(_enabled: bool) {}
+
+// For callers that already have interrupts disabled: gives up rather than
+// waiting if the framebuffer is locked.
+pub fn try_with_framebuffer<R>(f: impl FnOnce(&mut Framebuffer) -> R) -> Option<R> {
+    FRAMEBUFFER.try_lock()?.as_mut().map(f)
+}
+
+// For the panic handler.
+#[cfg_attr(test, allow(dead_code))]
+pub unsafe fn force_unlock() {
+    FRAMEBUFFER.force_unlock();
+}
+
+fn map(info: FramebufferInfo) -> Option<Framebuffer> {
+    let virt = VirtAddr::new(FRAMEBUFFER_VIRT_ADDR);
+    memory::map_physical(info.address, virt, info.size() as u64, Access::Mmio).ok()?;
+    Some(Framebuffer {
+        front: virt.as_mut_ptr(),
+        back: Vec::new(),
+        info,
+        dirty: 0..0,
+    })
+}
+
+// Gives `framebuffer` a back buffer of its own size. Any current display is
+// dropped first so that both buffers needn't fit in the heap at once. None
+// if the buffer doesn't fit.
+fn alloc_back_buffer(framebuffer: &mut Framebuffer) -> Option<()> {
+    interrupts::without_interrupts(|| {
+        ENABLED.store(false, Ordering::Relaxed);
+        *FRAMEBUFFER.lock() = None;
+    });
+    let size = framebuffer.info.size();
+    framebuffer.back.try_reserve_exact(size).ok()?;
+    framebuffer.back.resize(size, 0);
+    Some(())
+}
+
+// Makes `framebuffer`, which has its back buffer, the display.
+fn install(framebuffer: Framebuffer) {
+    interrupts::without_interrupts(|| *FRAMEBUFFER.lock() = Some(framebuffer));
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Maps a framebuffer that is already being displayed, such as one a
+// bootloader set up, and makes it the display. Must run after
+// memory::install(). Nothing hands the kernel a bootloader framebuffer yet.
+#[allow(dead_code)]
+pub fn init(info: FramebufferInfo) -> bool {
+    let mut framebuffer = match map(info) {
+        Some(framebuffer) => framebuffer,
+        None => return false,
+    };
+    if alloc_back_buffer(&mut framebuffer).is_none() {
+        return false;
+    }
+    install(framebuffer);
+    true
+}
+
+// The Bochs display adapter (also QEMU's -vga std), programmed through an
+// index/data port pair.
+const BOCHS_INDEX_PORT: u16 = 0x01CE;
+const BOCHS_DATA_PORT: u16 = 0x01CF;
+const BOCHS_REG_ID: u16 = 0;
+const BOCHS_REG_XRES: u16 = 1;
+const BOCHS_REG_YRES: u16 = 2;
+const BOCHS_REG_BPP: u16 = 3;
+const BOCHS_REG_ENABLE: u16 = 4;
+const BOCHS_ID_MIN: u16 = 0xB0C0;
+const BOCHS_ID_MAX: u16 = 0xB0C5;
+const BOCHS_ENABLED: u16 = 0x01;
+const BOCHS_LFB_ENABLED: u16 = 0x40;
+const BOCHS_BPP: u8 = 32;
+
+const BOCHS_PCI_VENDOR: u16 = 0x1234;
+const BOCHS_PCI_DEVICE: u16 = 0x1111;
+// Where Bochs puts the framebuffer when no PCI device reports it.
+const BOCHS_DEFAULT_LFB: u64 = 0xE000_0000;
+
+fn bochs_write(register: u16, value: u16) {
+    unsafe {
+        Port::<u16>::new(BOCHS_INDEX_PORT).write(register);
+        Port::<u16>::new(BOCHS_DATA_PORT).write(value);
+    }
+}
+
+fn bochs_read(register: u16) -> u16 {
+    unsafe {
+        Port::<u16>::new(BOCHS_INDEX_PORT).write(register);
+        Port::<u16>::new(BOCHS_DATA_PORT).read()
+    }
+}
+
+// Parses a display mode written as "<width>x<height>", e.g. "800x600".
+// "text" and anything malformed give None, meaning stay in VGA text mode.
+pub fn parse_mode(mode: &str) -> Option<(u16, u16)> {
+    let (width, height) = mode.split_once('x')?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Some((width, height)),
+        _ => None,
+    }
+}
+
+// Switches a Bochs/QEMU display adapter to a `width` x `height` 32-bit
+// mode and makes its framebuffer the display. The framebuffer is mapped and
+// its back buffer allocated first, so on failure the screen is left in text
+// mode.
+pub fn init_bochs(width: u16, height: u16) -> Option<FramebufferInfo> {
+    let id = bochs_read(BOCHS_REG_ID);
+    if !(BOCHS_ID_MIN..=BOCHS_ID_MAX).contains(&id) {
+        return None;
+    }
+    // BAR0 is the framebuffer; its low bits are flags.
+    let address = pci::find_device(BOCHS_PCI_VENDOR, BOCHS_PCI_DEVICE)
+        .map_or(BOCHS_DEFAULT_LFB, |device| u64::from(device.bar(0) & !0xF));
+    let info = FramebufferInfo {
+        address: PhysAddr::new(address),
+        width: usize::from(width),
+        height: usize::from(height),
+        pitch: usize::from(width) * usize::from(BOCHS_BPP / 8),
+        bpp: BOCHS_BPP,
+    };
+    let mut framebuffer = map(info)?;
+    alloc_back_buffer(&mut framebuffer)?;
+
+    bochs_write(BOCHS_REG_ENABLE, 0);
+    bochs_write(BOCHS_REG_XRES, width);
+    bochs_write(BOCHS_REG_YRES, height);
+    bochs_write(BOCHS_REG_BPP, u16::from(BOCHS_BPP));
+    bochs_write(BOCHS_REG_ENABLE, BOCHS_ENABLED | BOCHS_LFB_ENABLED);
+    // The adapter clamps modes it can't do; don't draw into the wrong shape.
+    if bochs_read(BOCHS_REG_XRES) != width || bochs_read(BOCHS_REG_YRES) != height {
+        bochs_write(BOCHS_REG_ENABLE, 0);
+        return None;
+    }
+    install(framebuffer);
+    Some(info)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // A framebuffer whose screen is a plain buffer in RAM, returned alongside
+    // it. The buffer must outlive the framebuffer.
+    pub fn in_memory(width: usize, height: usize, bpp: u8) -> (Framebuffer, Vec<u8>) {
+        let info = FramebufferInfo {
+            address: PhysAddr::new(0),
+            width,
+            height,
+            pitch: width * usize::from(bpp / 8),
+            bpp,
+        };
+        let mut screen = vec![0; info.size()];
+        let framebuffer = Framebuffer {
+            front: screen.as_mut_ptr(),
+            back: vec![0; info.size()],
+            info,
+            dirty: 0..0,
+        };
+        (framebuffer, screen)
+    }
+
+    // The bytes of one pixel in the back buffer.
+    pub fn drawn(framebuffer: &Framebuffer, x: usize, y: usize) -> &[u8] {
+        let bytes = usize::from(framebuffer.info.bpp / 8);
+        let offset = y * framebuffer.info.pitch + x * bytes;
+        &framebuffer.back[offset..offset + bytes]
+    }
+
+    #[test_case]
+    fn pixels_are_stored_in_the_framebuffer_format() {
+        for (bpp, expected) in [(32, &[0x56, 0x34, 0x12, 0x00][..]), (24, &[0x56, 0x34, 0x12]), (16, &[0xAA, 0x11])] {
+            let (mut framebuffer, _screen) = in_memory(16, 8, bpp);
+            framebuffer.clear(0x123456);
+            assert_eq!(drawn(&framebuffer, 0, 0), expected);
+            assert_eq!(drawn(&framebuffer, 15, 7), expected);
+        }
+    }
+
+    #[test_case]
+    fn drawing_is_clipped_to_the_screen() {
+        let (mut framebuffer, _screen) = in_memory(16, 8, 32);
+        framebuffer.put_pixel(16, 0, 0xFFFFFF);
+        framebuffer.put_pixel(0, 8, 0xFFFFFF);
+        assert!(framebuffer.back.iter().all(|&b| b == 0));
+        framebuffer.fill_rect(12, 6, 100, 100, 0xFF0000);
+        assert_eq!(drawn(&framebuffer, 11, 6), [0, 0, 0, 0]);
+        assert_eq!(drawn(&framebuffer, 12, 6), [0, 0, 0xFF, 0]);
+        assert_eq!(drawn(&framebuffer, 15, 7), [0, 0, 0xFF, 0]);
+        assert_eq!(drawn(&framebuffer, 12, 5), [0, 0, 0, 0]);
+    }
+
+    #[test_case]
+    fn the_screen_only_changes_on_present() {
+        let (mut framebuffer, screen) = in_memory(16, 8, 32);
+        framebuffer.fill_rect(0, 2, 16, 3, 0x00FF00);
+        assert!(screen.iter().all(|&b| b == 0));
+        framebuffer.present();
+        assert!(screen == framebuffer.back);
+        let pitch = framebuffer.info.pitch;
+        assert!(screen[2 * pitch..5 * pitch].chunks(4).all(|pixel| pixel == [0, 0xFF, 0, 0]));
+        // Nothing drawn since, so nothing is copied.
+        assert!(framebuffer.dirty.is_empty());
+        framebuffer.put_pixel(0, 0, 0xFFFFFF);
+        assert_eq!(screen[..4], [0, 0, 0, 0]);
+        framebuffer.present();
+        assert_eq!(screen[..4], [0xFF, 0xFF, 0xFF, 0]);
+    }
+
+    // 256 MiB, far more than the heap. Nothing is on screen to drop while
+    // the tests run.
+    #[test_case]
+    fn a_back_buffer_too_big_for_the_heap_is_refused() {
+        let info = FramebufferInfo {
+            address: PhysAddr::new(0),
+            width: 8192,
+            height: 8192,
+            pitch: 8192 * 4,
+            bpp: 32,
+        };
+        let mut framebuffer = Framebuffer {
+            front: core::ptr::null_mut(),
+            back: Vec::new(),
+            info,
+            dirty: 0..0,
+        };
+        assert_eq!(alloc_back_buffer(&mut framebuffer), None);
+        assert!(framebuffer.back.is_empty());
+        assert!(!is_enabled());
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use lazy_static::lazy_static;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+
+// A double fault caused by a kernel stack overflow must not try to push its
+// frame onto the overflowed stack, so it gets a stack of its own. So does the
+// page fault raised when a task runs into its stack's guard page.
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
+// Interrupts and system calls from user mode switch to this stack, the TSS's
+// rsp0. There is one per CPU rather than one per task, so only one user task
+// may exist at a time; see the usermode module.
+const USER_INTERRUPT_STACK_SIZE: usize = 4096 * 5;
+
+lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+            static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(core::ptr::addr_of!(STACK));
+            // Stacks grow down, so the IST entry points at the end.
+            stack_start + DOUBLE_FAULT_STACK_SIZE
+        };
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            static mut STACK: [u8; PAGE_FAULT_STACK_SIZE] = [0; PAGE_FAULT_STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(core::ptr::addr_of!(STACK));
+            stack_start + PAGE_FAULT_STACK_SIZE
+        };
+        tss.privilege_stack_table[0] = {
+            static mut STACK: [u8; USER_INTERRUPT_STACK_SIZE] = [0; USER_INTERRUPT_STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(core::ptr::addr_of!(STACK));
+            stack_start + USER_INTERRUPT_STACK_SIZE
+        };
+        tss
+    };
+}
+
+struct Selectors {
+    code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    tss_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
+}
+
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        (
+            gdt,
+            Selectors {
+                code_selector,
+                data_selector,
+                tss_selector,
+                user_code_selector,
+                user_data_selector,
+            },
+        )
+    };
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
+    use x86_64::instructions::segmentation::{Segment, CS, SS};
+    use x86_64::instructions::tables::load_tss;
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(selectors.code_selector);
+        SS::set_reg(selectors.data_selector);
+        load_tss(selectors.tss_selector);
+    }
+}
+
+pub fn init() {
+    load(&GDT.0, &GDT.1);
+}
+
+// The code and data selectors for ring 3, which are the same on every CPU.
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
+}
+
+fn leak_stack(size: usize) -> VirtAddr {
+    let stack = Box::leak(vec![0u8; size].into_boxed_slice());
+    VirtAddr::from_ptr(stack.as_ptr()) + size
+}
+
+// Loading a TSS marks it busy, so each application processor needs a TSS and
+// IST stacks of its own, and so a GDT of its own to hold the TSS. They live
+// as long as the processor does, which is until reboot.
+pub fn init_ap() {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = leak_stack(DOUBLE_FAULT_STACK_SIZE);
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = leak_stack(PAGE_FAULT_STACK_SIZE);
+    tss.privilege_stack_table[0] = leak_stack(USER_INTERRUPT_STACK_SIZE);
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
+    let mut gdt = GlobalDescriptorTable::new();
+    let selectors = Selectors {
+        code_selector: gdt.add_entry(Descriptor::kernel_code_segment()),
+        data_selector: gdt.add_entry(Descriptor::kernel_data_segment()),
+        tss_selector: gdt.add_entry(Descriptor::tss_segment(tss)),
+        user_data_selector: gdt.add_entry(Descriptor::user_data_segment()),
+        user_code_selector: gdt.add_entry(Descriptor::user_code_segment()),
+    };
+    load(Box::leak(Box::new(gdt)), &selectors);
+}
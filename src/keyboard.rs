@@ -0,0 +1,409 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::ps2::PS2;
+use crate::watchdog;
+
+const QUEUE_SIZE: usize = 256;
+
+const TEST_FIRST_PORT: u8 = 0xAB;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const KEYMAP_LEN: usize = 0x3A;
+
+// Scancode set 1, index = make code. 0 means the key has no ASCII mapping.
+static US_QWERTY: [u8; KEYMAP_LEN] = [
+    0, 27, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', b'-', b'=', 8, b'\t',
+    b'q', b'w', b'e', b'r', b't', b'y', b'u', b'i', b'o', b'p', b'[', b']', b'\n', 0, b'a', b's',
+    b'd', b'f', b'g', b'h', b'j', b'k', b'l', b';', b'\'', b'`', 0, b'\\', b'z', b'x', b'c', b'v',
+    b'b', b'n', b'm', b',', b'.', b'/', 0, b'*', 0, b' ',
+];
+
+static US_QWERTY_SHIFTED: [u8; KEYMAP_LEN] = [
+    0, 27, b'!', b'@', b'#', b'$', b'%', b'^', b'&', b'*', b'(', b')', b'_', b'+', 8, b'\t',
+    b'Q', b'W', b'E', b'R', b'T', b'Y', b'U', b'I', b'O', b'P', b'{', b'}', b'\n', 0, b'A', b'S',
+    b'D', b'F', b'G', b'H', b'J', b'K', b'L', b':', b'"', b'~', 0, b'|', b'Z', b'X', b'C', b'V',
+    b'B', b'N', b'M', b'<', b'>', b'?', 0, b'*', 0, b' ',
+];
+
+// French AZERTY. Keys that produce accented letters or other non-ASCII
+// characters are left unmapped, and '^' is typed directly rather than acting
+// as a dead key.
+static FR_AZERTY: [u8; KEYMAP_LEN] = [
+    0, 27, b'&', 0, b'"', b'\'', b'(', b'-', 0, b'_', 0, 0, b')', b'=', 8, b'\t',
+    b'a', b'z', b'e', b'r', b't', b'y', b'u', b'i', b'o', b'p', b'^', b'$', b'\n', 0, b'q', b's',
+    b'd', b'f', b'g', b'h', b'j', b'k', b'l', b'm', 0, 0, 0, b'*', b'w', b'x', b'c', b'v',
+    b'b', b'n', b',', b';', b':', b'!', 0, b'*', 0, b' ',
+];
+
+static FR_AZERTY_SHIFTED: [u8; KEYMAP_LEN] = [
+    0, 27, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', 0, b'+', 8, b'\t',
+    b'A', b'Z', b'E', b'R', b'T', b'Y', b'U', b'I', b'O', b'P', 0, 0, b'\n', 0, b'Q', b'S',
+    b'D', b'F', b'G', b'H', b'J', b'K', b'L', b'M', b'%', 0, 0, 0, b'W', b'X', b'C', b'V',
+    b'B', b'N', b'?', b'.', b'/', 0, 0, b'*', 0, b' ',
+];
+
+static US_DVORAK: [u8; KEYMAP_LEN] = [
+    0, 27, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', b'[', b']', 8, b'\t',
+    b'\'', b',', b'.', b'p', b'y', b'f', b'g', b'c', b'r', b'l', b'/', b'=', b'\n', 0, b'a', b'o',
+    b'e', b'u', b'i', b'd', b'h', b't', b'n', b's', b'-', b'`', 0, b'\\', b';', b'q', b'j', b'k',
+    b'x', b'b', b'm', b'w', b'v', b'z', 0, b'*', 0, b' ',
+];
+
+static US_DVORAK_SHIFTED: [u8; KEYMAP_LEN] = [
+    0, 27, b'!', b'@', b'#', b'$', b'%', b'^', b'&', b'*', b'(', b')', b'{', b'}', 8, b'\t',
+    b'"', b'<', b'>', b'P', b'Y', b'F', b'G', b'C', b'R', b'L', b'?', b'+', b'\n', 0, b'A', b'O',
+    b'E', b'U', b'I', b'D', b'H', b'T', b'N', b'S', b'_', b'~', 0, b'|', b':', b'Q', b'J', b'K',
+    b'X', b'B', b'M', b'W', b'V', b'Z', 0, b'*', 0, b' ',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Qwerty,
+    Azerty,
+    Dvorak,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Qwerty, Layout::Azerty, Layout::Dvorak];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Qwerty => "qwerty",
+            Layout::Azerty => "azerty",
+            Layout::Dvorak => "dvorak",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.iter().copied().find(|layout| layout.name() == name)
+    }
+
+    // The unshifted and shifted tables.
+    fn keymaps(self) -> (&'static [u8; KEYMAP_LEN], &'static [u8; KEYMAP_LEN]) {
+        match self {
+            Layout::Qwerty => (&US_QWERTY, &US_QWERTY_SHIFTED),
+            Layout::Azerty => (&FR_AZERTY, &FR_AZERTY_SHIFTED),
+            Layout::Dvorak => (&US_DVORAK, &US_DVORAK_SHIFTED),
+        }
+    }
+}
+
+const LEFT_SHIFT: u8 = 0x2A;
+const LEFT_SHIFT_RELEASE: u8 = 0xAA;
+const RIGHT_SHIFT: u8 = 0x36;
+const RIGHT_SHIFT_RELEASE: u8 = 0xB6;
+const CTRL: u8 = 0x1D;
+const CTRL_RELEASE: u8 = 0x9D;
+const CAPS_LOCK: u8 = 0x3A;
+const CAPS_LOCK_RELEASE: u8 = 0xBA;
+const BREAK: u8 = 0x80;
+const EXTENDED_PREFIX: u8 = 0xE0;
+
+// Make codes that follow the 0xE0 prefix.
+const EXTENDED_HOME: u8 = 0x47;
+const EXTENDED_UP: u8 = 0x48;
+const EXTENDED_PAGE_UP: u8 = 0x49;
+const EXTENDED_LEFT: u8 = 0x4B;
+const EXTENDED_RIGHT: u8 = 0x4D;
+const EXTENDED_END: u8 = 0x4F;
+const EXTENDED_DOWN: u8 = 0x50;
+const EXTENDED_PAGE_DOWN: u8 = 0x51;
+const EXTENDED_DELETE: u8 = 0x53;
+
+// Codes for keys without an ASCII equivalent, chosen above the ASCII range.
+pub const KEY_PAGE_UP: u8 = 0x80;
+pub const KEY_PAGE_DOWN: u8 = 0x81;
+pub const KEY_UP: u8 = 0x82;
+pub const KEY_DOWN: u8 = 0x83;
+pub const KEY_LEFT: u8 = 0x84;
+pub const KEY_RIGHT: u8 = 0x85;
+pub const KEY_HOME: u8 = 0x86;
+pub const KEY_END: u8 = 0x87;
+pub const KEY_DELETE: u8 = 0x88;
+
+// Ctrl with a letter gives the matching ASCII control code, e.g. Ctrl-C is
+// 0x03. These are the ones the shell and the editor act on.
+pub const KEY_CTRL_C: u8 = 0x03;
+pub const KEY_CTRL_L: u8 = 0x0C;
+pub const KEY_CTRL_Q: u8 = 0x11;
+pub const KEY_CTRL_S: u8 = 0x13;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModifierState {
+    pub shift: bool,
+    pub caps_lock: bool,
+    pub ctrl: bool,
+}
+
+impl ModifierState {
+    pub const fn new() -> Self {
+        ModifierState {
+            shift: false,
+            caps_lock: false,
+            ctrl: false,
+        }
+    }
+
+    // Returns true if the scancode was a modifier and has been consumed.
+    fn update(&mut self, scancode: u8) -> bool {
+        match scancode {
+            LEFT_SHIFT | RIGHT_SHIFT => self.shift = true,
+            LEFT_SHIFT_RELEASE | RIGHT_SHIFT_RELEASE => self.shift = false,
+            CTRL => self.ctrl = true,
+            CTRL_RELEASE => self.ctrl = false,
+            CAPS_LOCK => self.caps_lock = !self.caps_lock,
+            CAPS_LOCK_RELEASE => {}
+            _ => return false,
+        }
+        true
+    }
+}
+
+pub struct Keyboard {
+    modifiers: ModifierState,
+    // Set after a 0xE0 prefix byte; applies to the next scancode only.
+    extended: bool,
+    layout: Layout,
+}
+
+impl Keyboard {
+    pub const fn new() -> Self {
+        Keyboard {
+            modifiers: ModifierState::new(),
+            extended: false,
+            layout: Layout::Qwerty,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn modifiers(&self) -> ModifierState {
+        self.modifiers
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    pub fn process(&mut self, scancode: u8) -> Option<u8> {
+        if scancode == EXTENDED_PREFIX {
+            self.extended = true;
+            return None;
+        }
+        if core::mem::take(&mut self.extended) {
+            return self.process_extended(scancode);
+        }
+
+        if self.modifiers.update(scancode) {
+            return None;
+        }
+        // Break codes have bit 7 set; only key presses produce characters.
+        if scancode & BREAK != 0 {
+            return None;
+        }
+
+        let (keymap, shifted_keymap) = self.layout.keymaps();
+        let base = *keymap.get(scancode as usize)?;
+
+        // Caps Lock only affects letters, and cancels out with Shift.
+        let shifted = if base.is_ascii_alphabetic() {
+            self.modifiers.shift != self.modifiers.caps_lock
+        } else {
+            self.modifiers.shift
+        };
+
+        if self.modifiers.ctrl && base.is_ascii_alphabetic() {
+            return Some(base.to_ascii_lowercase() & 0x1F);
+        }
+
+        let ascii = if shifted { shifted_keymap[scancode as usize] } else { base };
+        if ascii == 0 {
+            None
+        } else {
+            Some(ascii)
+        }
+    }
+
+    fn process_extended(&mut self, scancode: u8) -> Option<u8> {
+        // Right Ctrl shares its code with left Ctrl. The fake shifts some
+        // keys emit (E0 2A / E0 AA) must not touch the shift state.
+        if scancode == CTRL || scancode == CTRL_RELEASE {
+            self.modifiers.update(scancode);
+            return None;
+        }
+        match scancode {
+            EXTENDED_UP => Some(KEY_UP),
+            EXTENDED_DOWN => Some(KEY_DOWN),
+            EXTENDED_PAGE_UP => Some(KEY_PAGE_UP),
+            EXTENDED_PAGE_DOWN => Some(KEY_PAGE_DOWN),
+            EXTENDED_LEFT => Some(KEY_LEFT),
+            EXTENDED_RIGHT => Some(KEY_RIGHT),
+            EXTENDED_HOME => Some(KEY_HOME),
+            EXTENDED_END => Some(KEY_END),
+            EXTENDED_DELETE => Some(KEY_DELETE),
+            _ => None,
+        }
+    }
+}
+
+pub struct ScancodeQueue {
+    buffer: Mutex<[u8; QUEUE_SIZE]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl ScancodeQueue {
+    pub const fn new() -> Self {
+        ScancodeQueue {
+            buffer: Mutex::new([0; QUEUE_SIZE]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // One slot is kept empty so that head == tail always means "empty".
+    pub fn push(&self, scancode: u8) -> bool {
+        let mut buffer = self.buffer.lock();
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % QUEUE_SIZE;
+        if next == self.head.load(Ordering::Acquire) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        buffer[tail] = scancode;
+        self.tail.store(next, Ordering::Release);
+        true
+    }
+
+    pub fn pop(&self) -> Option<u8> {
+        let buffer = self.buffer.lock();
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let scancode = buffer[head];
+        self.head.store((head + 1) % QUEUE_SIZE, Ordering::Release);
+        Some(scancode)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    // Scancodes lost to a full queue. Not shown anywhere yet.
+    #[allow(dead_code)]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+pub static SCANCODE_QUEUE: ScancodeQueue = ScancodeQueue::new();
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new());
+
+// Runs the controller's interface test on the keyboard port. Must be called
+// before interrupts are enabled, or the keyboard ISR would eat the reply.
+pub fn init() -> bool {
+    let mut controller = PS2.lock();
+    controller.write_command(TEST_FIRST_PORT)
+        && controller.read_data() == Some(PORT_TEST_PASSED)
+}
+
+// Called by the PS/2 controller for bytes from the keyboard port. Decoding
+// happens on the consumer side so the ISR stays short.
+pub fn handle_scancode(scancode: u8) {
+    SCANCODE_QUEUE.push(scancode);
+}
+
+pub fn layout() -> Layout {
+    KEYBOARD.lock().layout()
+}
+
+pub fn set_layout(layout: Layout) {
+    KEYBOARD.lock().set_layout(layout);
+}
+
+// Returns a key if one has been pressed, without waiting.
+pub fn try_read_char() -> Option<u8> {
+    loop {
+        let scancode = interrupts::without_interrupts(|| SCANCODE_QUEUE.pop())?;
+        if let Some(ascii) = KEYBOARD.lock().process(scancode) {
+            return Some(ascii);
+        }
+    }
+}
+
+pub fn read_char() -> u8 {
+    loop {
+        // The ISR takes the queue lock too, so pop with interrupts off and
+        // re-enable them atomically with `hlt` if there is nothing to read.
+        interrupts::disable();
+        match SCANCODE_QUEUE.pop() {
+            Some(scancode) => {
+                interrupts::enable();
+                if let Some(ascii) = KEYBOARD.lock().process(scancode) {
+                    return ascii;
+                }
+            }
+            None => {
+                // Waiting for a key is the shell idling, not hanging.
+                watchdog::pet();
+                interrupts::enable_and_hlt();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn a_full_queue_drops_and_counts_new_scancodes() {
+        let queue = ScancodeQueue::new();
+        for i in 0..QUEUE_SIZE - 1 {
+            assert!(queue.push(i as u8));
+        }
+        assert!(!queue.push(0xFF));
+        assert!(!queue.push(0xFF));
+        assert_eq!(queue.dropped(), 2);
+        // What was queued before it filled up comes out intact and in order.
+        for i in 0..QUEUE_SIZE - 1 {
+            assert_eq!(queue.pop(), Some(i as u8));
+        }
+        assert!(queue.is_empty());
+        assert!(queue.push(0x1E));
+        assert_eq!(queue.pop(), Some(0x1E));
+    }
+
+    // Presses and releases the key, returning what the press produced.
+    fn press(keyboard: &mut Keyboard, scancode: u8) -> Option<u8> {
+        let ascii = keyboard.process(scancode);
+        assert_eq!(keyboard.process(scancode | BREAK), None);
+        ascii
+    }
+
+    #[test_case]
+    fn the_layout_decides_what_a_key_types() {
+        // The key right of Tab, and the one right of Caps Lock.
+        const Q_KEY: u8 = 0x10;
+        const A_KEY: u8 = 0x1E;
+        let mut keyboard = Keyboard::new();
+        assert_eq!(press(&mut keyboard, Q_KEY), Some(b'q'));
+        keyboard.set_layout(Layout::Azerty);
+        assert_eq!(press(&mut keyboard, Q_KEY), Some(b'a'));
+        assert_eq!(press(&mut keyboard, A_KEY), Some(b'q'));
+        keyboard.set_layout(Layout::Dvorak);
+        assert_eq!(press(&mut keyboard, Q_KEY), Some(b'\''));
+        assert_eq!(press(&mut keyboard, A_KEY), Some(b'a'));
+        keyboard.process(LEFT_SHIFT);
+        assert_eq!(press(&mut keyboard, Q_KEY), Some(b'"'));
+    }
+}
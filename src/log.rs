@@ -0,0 +1,282 @@
+// The kernel log. Every line logged at or above the minimum level is printed
+// to the console and COM1 and kept in a fixed ring of the most recent lines,
+// which dmesg shows. Lines below it are kept in the ring unless that is
+// turned off. Storing a line takes no allocation, so interrupt handlers can
+// log too.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::vga_buffer::{self, Color};
+use crate::{serial, time};
+
+const CAPACITY: usize = 128;
+// Longer lines are cut short in the ring, but printed in full.
+const LINE_LEN: usize = 120;
+
+// Ordered from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    const ALL: [Level; 3] = [Level::Info, Level::Warn, Level::Error];
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL.iter().copied().find(|level| level.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    // None for lines shown in the console's own color.
+    pub fn color(self) -> Option<Color> {
+        match self {
+            Level::Info => None,
+            Level::Warn => Some(Color::Brown),
+            Level::Error => Some(Color::Red),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    level: Level,
+    timestamp_ms: usize,
+    len: usize,
+    text: [u8; LINE_LEN],
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        level: Level::Info,
+        timestamp_ms: 0,
+        len: 0,
+        text: [0; LINE_LEN],
+    };
+}
+
+struct Ring {
+    entries: [Entry; CAPACITY],
+    // Where the next line goes, overwriting the oldest once the ring is full.
+    next: usize,
+    count: usize,
+}
+
+impl Ring {
+    fn push(&mut self, entry: Entry) {
+        self.entries[self.next] = entry;
+        self.next = (self.next + 1) % CAPACITY;
+        self.count = (self.count + 1).min(CAPACITY);
+    }
+}
+
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static KEEP_FILTERED: AtomicBool = AtomicBool::new(true);
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+    entries: [Entry::EMPTY; CAPACITY],
+    next: 0,
+    count: 0,
+});
+
+// A logged line, as handed out by records().
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub level: Level,
+    pub timestamp_ms: usize,
+    pub text: String,
+}
+
+// Fills entries from formatted text, starting a new one at each newline so
+// multi-line messages keep one line per entry.
+struct EntryWriter<'a> {
+    ring: &'a mut Ring,
+    entry: Entry,
+}
+
+impl EntryWriter<'_> {
+    fn finish_line(&mut self) {
+        self.ring.push(self.entry);
+        self.entry.len = 0;
+    }
+}
+
+impl fmt::Write for EntryWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for ch in s.chars() {
+            if ch == '\n' {
+                self.finish_line();
+                continue;
+            }
+            // Cut on a character boundary so the text stays valid UTF-8.
+            let len = ch.len_utf8();
+            if self.entry.len + len <= LINE_LEN {
+                ch.encode_utf8(&mut self.entry.text[self.entry.len..]);
+                self.entry.len += len;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn store(ring: &mut Ring, level: Level, args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mut writer = EntryWriter {
+        ring,
+        entry: Entry {
+            level,
+            timestamp_ms: time::uptime_ms(),
+            ..Entry::EMPTY
+        },
+    };
+    let _ = writer.write_fmt(args);
+    writer.finish_line();
+}
+
+pub fn min_level() -> Level {
+    let value = MIN_LEVEL.load(Ordering::Relaxed);
+    Level::ALL.iter().copied().find(|&level| level as u8 == value).unwrap_or(Level::Info)
+}
+
+pub fn set_min_level(level: Level) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Whether lines below the minimum level still go into the ring, where they
+// show up in dmesg once the level is lowered.
+pub fn set_keep_filtered(keep: bool) {
+    KEEP_FILTERED.store(keep, Ordering::Relaxed);
+}
+
+// Same locking rules as vga_buffer::_print: with interrupts disabled the
+// line is left out of the ring rather than waiting for whoever holds it.
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    let shown = level >= min_level();
+    if !shown && !KEEP_FILTERED.load(Ordering::Relaxed) {
+        return;
+    }
+    if interrupts::are_enabled() {
+        interrupts::without_interrupts(|| store(&mut RING.lock(), level, args));
+    } else if let Some(mut ring) = RING.try_lock() {
+        store(&mut ring, level, args);
+    }
+    if !shown {
+        return;
+    }
+    match level.color() {
+        Some(color) => vga_buffer::_print_colored(color, format_args!("{}\n", args)),
+        None => vga_buffer::_print(format_args!("{}\n", args)),
+    }
+    serial::_print(format_args!("{}\n", args));
+}
+
+// The lines in the ring, oldest first.
+pub fn records() -> Vec<Record> {
+    interrupts::without_interrupts(|| {
+        let ring = RING.lock();
+        let first = (ring.next + CAPACITY - ring.count) % CAPACITY;
+        (0..ring.count)
+            .map(|i| &ring.entries[(first + i) % CAPACITY])
+            .map(|entry| Record {
+                level: entry.level,
+                timestamp_ms: entry.timestamp_ms,
+                text: String::from_utf8_lossy(&entry.text[..entry.len]).into_owned(),
+            })
+            .collect()
+    })
+}
+
+// For the panic handler, like console::force_unlock().
+#[cfg_attr(test, allow(dead_code))]
+pub unsafe fn force_unlock() {
+    RING.force_unlock();
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => ($crate::log::_log($crate::log::Level::Info, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => ($crate::log::_log($crate::log::Level::Warn, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => ($crate::log::_log($crate::log::Level::Error, format_args!($($arg)*)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_records(count: usize) -> Vec<(Level, String)> {
+        let records = records();
+        records[records.len() - count..]
+            .iter()
+            .map(|record| (record.level, record.text.clone()))
+            .collect()
+    }
+
+    #[test_case]
+    fn records_keep_lines_in_order_with_their_levels() {
+        crate::log_info!("order test {}", 1);
+        crate::log_warn!("order test 2");
+        crate::log_error!("order test 3\nsecond line");
+        let expected = [
+            (Level::Info, "order test 1"),
+            (Level::Warn, "order test 2"),
+            (Level::Error, "order test 3"),
+            (Level::Error, "second line"),
+        ];
+        assert!(last_records(4).iter().map(|(level, text)| (*level, text.as_str())).eq(expected));
+        let records = records();
+        assert!(records.windows(2).all(|pair| pair[0].timestamp_ms <= pair[1].timestamp_ms));
+    }
+
+    #[test_case]
+    fn long_lines_are_cut_in_the_ring() {
+        crate::log_info!("{:x<1$}", "", LINE_LEN + 10);
+        assert_eq!(last_records(1)[0].1.len(), LINE_LEN);
+    }
+
+    #[test_case]
+    fn the_error_level_filters_info_and_warn() {
+        set_min_level(Level::Error);
+        crate::log_info!("filter test info");
+        crate::log_warn!("filter test warn");
+        crate::log_error!("filter test error");
+        // Filtered lines are kept for dmesg unless that is turned off.
+        let levels: Vec<Level> = last_records(3).iter().map(|(level, _)| *level).collect();
+        assert_eq!(levels, [Level::Info, Level::Warn, Level::Error]);
+        set_keep_filtered(false);
+        crate::log_warn!("filter test dropped");
+        let kept = last_records(1);
+        set_keep_filtered(true);
+        set_min_level(Level::Info);
+        assert_eq!(kept[0], (Level::Error, String::from("filter test error")));
+
+        let mut shell = crate::shell::tests::new_shell();
+        set_min_level(Level::Error);
+        let output = crate::shell::tests::run_captured(&mut shell, "dmesg");
+        set_min_level(Level::Info);
+        assert!(output.lines().any(|line| line.ends_with("filter test error")));
+        assert!(!output.contains("filter test info") && !output.contains("filter test warn"));
+    }
+}
@@ -1,47 +1,476 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
 use x86_64::{
-    structures::paging::{PageTable, PageTableFlags, PhysFrame, Size4KiB},
+    instructions::interrupts,
+    registers::control::Cr3,
+    registers::model_specific::{Efer, EferFlags},
+    structures::paging::{
+        mapper::{MapToError, TranslateResult}, FrameAllocator, FrameDeallocator, Mapper,
+        OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate,
+    },
     PhysAddr, VirtAddr,
 };
-use x86_64::structures::paging::mapper::MapToError;
-use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
 
-pub struct MemoryManager {
-    next_free_frame: PhysAddr,
+use crate::{allocator, cpu};
+
+// Updated by BootInfoFrameAllocator so stats() can be read from anywhere.
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemStats {
+    pub total_frames: usize,
+    pub allocated_frames: usize,
+    pub heap_used: usize,
+    pub heap_free: usize,
+}
+
+impl MemStats {
+    pub fn free_frames(&self) -> usize {
+        self.total_frames - self.allocated_frames
+    }
 }
 
-impl MemoryManager {
-    pub fn new() -> Self {
-        MemoryManager {
-            next_free_frame: PhysAddr::new(0x100000), // Start at 1 MB
+pub fn stats() -> MemStats {
+    let (heap_used, heap_free) = allocator::usage();
+    MemStats {
+        total_frames: TOTAL_FRAMES.load(Ordering::Relaxed),
+        allocated_frames: ALLOCATED_FRAMES.load(Ordering::Relaxed),
+        heap_used,
+        heap_free,
+    }
+}
+
+// Hands out frames from the regions the bootloader reported as usable, so
+// reserved, ACPI and MMIO ranges are never returned. Frames given back are
+// handed out again first; only do that once the heap is up.
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    region: usize,
+    next_addr: u64,
+    freed: Vec<PhysFrame>,
+}
+
+impl BootInfoFrameAllocator {
+    /// The caller must guarantee that every `Usable` region in `memory_map`
+    /// really is unused.
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let allocator = BootInfoFrameAllocator {
+            memory_map,
+            region: 0,
+            next_addr: 0,
+            freed: Vec::new(),
+        };
+        TOTAL_FRAMES.store(allocator.usable_frames().count(), Ordering::Relaxed);
+        ALLOCATED_FRAMES.store(0, Ordering::Relaxed);
+        allocator
+    }
+
+    pub fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| r.range.start_addr()..r.range.end_addr())
+            .flat_map(|r| r.step_by(Size4KiB::SIZE as usize))
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.freed.pop() {
+            ALLOCATED_FRAMES.fetch_add(1, Ordering::Relaxed);
+            return Some(frame);
         }
+        while let Some(region) = self.memory_map.get(self.region) {
+            if region.region_type == MemoryRegionType::Usable {
+                let start = region.range.start_addr().max(self.next_addr);
+                if start + Size4KiB::SIZE <= region.range.end_addr() {
+                    self.next_addr = start + Size4KiB::SIZE;
+                    ALLOCATED_FRAMES.fetch_add(1, Ordering::Relaxed);
+                    return Some(PhysFrame::containing_address(PhysAddr::new(start)));
+                }
+            }
+            self.region += 1;
+            self.next_addr = 0;
+        }
+        None
     }
+}
 
-    pub fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = PhysFrame::containing_address(self.next_free_frame);
-        self.next_free_frame += Size4KiB::SIZE;
-        Some(frame)
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.freed.push(frame);
+        ALLOCATED_FRAMES.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
-pub fn init() {
-    let mut memory_manager = MemoryManager::new();
-    let mut mapper = unsafe { memory::init(PhysAddr::new(0xb8000)) };
+/// Builds a mapper over the page tables the bootloader left active.
+///
+/// The bootloader must map all of physical memory at `physical_memory_offset`,
+/// and this must only be called once to avoid aliasing `&mut` page tables.
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+///     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+///     let mut mapper = unsafe { memory::init(phys_mem_offset) };
+///     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+///     // mapper.map_to(page, frame, flags, &mut frame_allocator) ...
+/// }
+/// ```
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    OffsetPageTable::new(level_4_table, physical_memory_offset)
+}
+
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
 
-    for i in 0..10 {
-        let page = Page::containing_address(VirtAddr::new(0x400000 + i * 0x1000));
-        let frame = memory_manager.allocate_frame().expect("no more frames");
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    &mut *page_table_ptr
+}
+
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Turns on EFER.NXE so NO_EXECUTE can be set in page tables. Without it the
+// bit is reserved and any page using it faults, so this must run before the
+// first map_region call.
+pub fn enable_nx() -> bool {
+    if !cpu::CPU_INFO.features.nx {
+        return false;
+    }
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    }
+    NX_ENABLED.store(true, Ordering::Relaxed);
+    true
+}
+
+// What a mapping is for. No variant is both writable and executable, so
+// mappings made through map_region are W^X. The kernel's own code and data
+// are mapped by the bootloader from its ELF segment flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    // Nothing maps read-only data yet.
+    #[allow(dead_code)]
+    Read,
+    ReadWrite,
+    ReadExecute,
+    // Device registers: writable, never cached, never executed.
+    Mmio,
+    // A user-mode program's code, and its data and stack.
+    UserReadExecute,
+    UserReadWrite,
+}
+
+impl Access {
+    pub fn flags(self) -> PageTableFlags {
+        let flags = match self {
+            Access::Read => PageTableFlags::PRESENT,
+            Access::ReadWrite => PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            Access::ReadExecute => return PageTableFlags::PRESENT,
+            Access::Mmio => {
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE
+            }
+            Access::UserReadExecute => {
+                return PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE
+            }
+            Access::UserReadWrite => {
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
+            }
+        };
+        if NX_ENABLED.load(Ordering::Relaxed) {
+            flags | PageTableFlags::NO_EXECUTE
+        } else {
+            flags
+        }
+    }
+}
+
+// Backs each page in `pages` with a fresh frame.
+pub fn map_region(
+    pages: impl Iterator<Item = Page<Size4KiB>>,
+    access: Access,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    for page in pages {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
         unsafe {
-            mapper.map_to(page, frame, flags, &mut memory_manager)
-                .expect("map_to failed")
-                .flush();
+            mapper.map_to(page, frame, access.flags(), frame_allocator)?.flush();
         }
     }
+    Ok(())
 }
 
-// Implement FrameAllocator for MemoryManager
-unsafe impl FrameAllocator<Size4KiB> for MemoryManager {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        self.allocate_frame()
+// Maps `len` bytes of physical memory at `phys`, such as a device's memory
+// window, to `virt`. Both addresses must be page-aligned. Pages that already
+// map the same frames are left as they are, so a window can be remapped
+// larger.
+pub fn map_physical(
+    phys: PhysAddr,
+    virt: VirtAddr,
+    len: u64,
+    access: Access,
+) -> Result<(), MapToError<Size4KiB>> {
+    interrupts::without_interrupts(|| {
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+        for offset in (0..len).step_by(Size4KiB::SIZE as usize) {
+            let page = Page::containing_address(virt + offset);
+            let frame = PhysFrame::containing_address(phys + offset);
+            let result = unsafe {
+                memory
+                    .mapper
+                    .map_to(page, frame, access.flags(), &mut memory.frame_allocator)
+            };
+            match result {
+                Ok(flush) => flush.flush(),
+                Err(MapToError::PageAlreadyMapped(existing)) if existing == frame => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    })
+}
+
+struct MemoryContext {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator,
+}
+
+// The page tables and frame allocator, once early boot is done with them.
+static MEMORY: Mutex<Option<MemoryContext>> = Mutex::new(None);
+
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    *MEMORY.lock() = Some(MemoryContext {
+        mapper,
+        frame_allocator,
+    });
+}
+
+// Returns None if the page tables are busy or not installed yet, which
+// callers on fault paths must treat as "don't know" rather than blocking.
+pub fn is_mapped(addr: VirtAddr) -> Option<bool> {
+    let memory = MEMORY.try_lock()?;
+    let memory = memory.as_ref()?;
+    Some(memory.mapper.translate_addr(addr).is_some())
+}
+
+// Whether every page of `len` bytes from `start` is mapped with at least the
+// `required` flags, for checking a buffer handed over by a caller that can't
+// be trusted to pass a valid one.
+pub fn is_range_mapped(start: VirtAddr, len: u64, required: PageTableFlags) -> bool {
+    if len == 0 {
+        return true;
+    }
+    let last = match start.as_u64().checked_add(len - 1).map(VirtAddr::try_new) {
+        Some(Ok(last)) => last,
+        _ => return false,
+    };
+    interrupts::without_interrupts(|| {
+        let memory = MEMORY.lock();
+        let memory = match memory.as_ref() {
+            Some(memory) => memory,
+            None => return false,
+        };
+        let first: Page<Size4KiB> = Page::containing_address(start);
+        Page::range_inclusive(first, Page::containing_address(last)).all(|page| {
+            match memory.mapper.translate(page.start_address()) {
+                TranslateResult::Mapped { flags, .. } => flags.contains(required),
+                _ => false,
+            }
+        })
+    })
+}
+
+// Maps `len` bytes at `start`, which must be page-aligned, for a user-mode
+// program, filled from `contents` and zeroed past its end. Pages left mapped
+// by an earlier program keep their frames and flags and are refilled, since
+// frames can't be freed.
+pub fn map_user(start: VirtAddr, len: u64, contents: &[u8], access: Access) -> Result<(), MapToError<Size4KiB>> {
+    interrupts::without_interrupts(|| {
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+        let phys_offset = memory.mapper.phys_offset();
+        for offset in (0..len).step_by(Size4KiB::SIZE as usize) {
+            let page = Page::containing_address(start + offset);
+            let frame = match memory.mapper.translate_page(page) {
+                Ok(frame) => frame,
+                Err(_) => {
+                    let frame = memory
+                        .frame_allocator
+                        .allocate_frame()
+                        .ok_or(MapToError::FrameAllocationFailed)?;
+                    unsafe {
+                        memory
+                            .mapper
+                            .map_to(page, frame, access.flags(), &mut memory.frame_allocator)?
+                            .flush();
+                    }
+                    frame
+                }
+            };
+            // Filled through the physical memory mapping, since the page
+            // itself may be read-only.
+            let page_bytes: *mut u8 = (phys_offset + frame.start_address().as_u64()).as_mut_ptr();
+            let start = (offset as usize).min(contents.len());
+            let end = (offset as usize + Size4KiB::SIZE as usize).min(contents.len());
+            unsafe {
+                core::ptr::write_bytes(page_bytes, 0, Size4KiB::SIZE as usize);
+                core::ptr::copy_nonoverlapping(contents[start..end].as_ptr(), page_bytes, end - start);
+            }
+        }
+        Ok(())
+    })
+}
+
+// Task stacks are carved out of their own region, one slot each: an unmapped
+// guard page followed by STACK_PAGES mapped pages. Running off the bottom of a
+// stack faults on the guard page instead of corrupting whatever lies below.
+const STACK_REGION_START: u64 = 0x_6666_6666_0000;
+const STACK_REGION_SLOTS: u64 = 1024;
+pub const STACK_PAGES: u64 = 4;
+const STACK_SLOT_SIZE: u64 = (STACK_PAGES + 1) * Size4KiB::SIZE;
+
+static NEXT_STACK_SLOT: AtomicU64 = AtomicU64::new(0);
+
+// Stacks of dead tasks stay mapped and are handed out again.
+static FREE_STACKS: Mutex<Vec<Stack>> = Mutex::new(Vec::new());
+
+#[derive(Debug)]
+pub struct Stack {
+    bottom: VirtAddr,
+    top: VirtAddr,
+}
+
+impl Stack {
+    #[allow(dead_code)]
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+}
+
+// Returns None if the stack region or physical memory is exhausted, or
+// before install().
+pub fn alloc_stack() -> Option<Stack> {
+    interrupts::without_interrupts(|| {
+        if let Some(stack) = FREE_STACKS.lock().pop() {
+            return Some(stack);
+        }
+
+        // The slot is only taken once its pages are mapped; MEMORY keeps
+        // other CPUs from claiming it meanwhile.
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut()?;
+        let slot = NEXT_STACK_SLOT.load(Ordering::Relaxed);
+        if slot >= STACK_REGION_SLOTS {
+            return None;
+        }
+        let guard = VirtAddr::new(STACK_REGION_START + slot * STACK_SLOT_SIZE);
+        let bottom = guard + Size4KiB::SIZE;
+        let top = bottom + STACK_PAGES * Size4KiB::SIZE;
+
+        let pages = Page::range(Page::containing_address(bottom), Page::containing_address(top));
+        if map_region(pages, Access::ReadWrite, &mut memory.mapper, &mut memory.frame_allocator).is_err() {
+            // Give back the pages mapped before the failure.
+            for page in pages {
+                if let Ok((frame, flush)) = memory.mapper.unmap(page) {
+                    flush.flush();
+                    unsafe {
+                        memory.frame_allocator.deallocate_frame(frame);
+                    }
+                }
+            }
+            return None;
+        }
+        NEXT_STACK_SLOT.store(slot + 1, Ordering::Relaxed);
+        Some(Stack { bottom, top })
+    })
+}
+
+pub fn free_stack(stack: Stack) {
+    interrupts::without_interrupts(|| FREE_STACKS.lock().push(stack));
+}
+
+pub fn is_stack_guard(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64();
+    let region_end = STACK_REGION_START + STACK_REGION_SLOTS * STACK_SLOT_SIZE;
+    (STACK_REGION_START..region_end).contains(&addr)
+        && (addr - STACK_REGION_START) % STACK_SLOT_SIZE < Size4KiB::SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use bootloader::bootinfo::{FrameRange, MemoryRegion};
+
+    fn region(start: u64, end: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion {
+            range: FrameRange::new(start, end),
+            region_type,
+        }
+    }
+
+    #[test_case]
+    fn frames_come_only_from_usable_regions() {
+        let mut map = MemoryMap::new();
+        map.add_region(region(0x0000, 0x1000, MemoryRegionType::Reserved));
+        map.add_region(region(0x1000, 0x3000, MemoryRegionType::Usable));
+        map.add_region(region(0x3000, 0x5000, MemoryRegionType::AcpiReclaimable));
+        map.add_region(region(0x5000, 0x6000, MemoryRegionType::Usable));
+        let map: &'static MemoryMap = Box::leak(Box::new(map));
+        // Built directly rather than through init(), which would reset the
+        // counters stats() reports for the real allocator.
+        let mut allocator = BootInfoFrameAllocator {
+            memory_map: map,
+            region: 0,
+            next_addr: 0,
+            freed: Vec::new(),
+        };
+        assert_eq!(allocator.usable_frames().count(), 3);
+
+        let mut frames = Vec::new();
+        while let Some(frame) = allocator.allocate_frame() {
+            frames.push(frame);
+        }
+        let addresses: Vec<u64> = frames.iter().map(|frame| frame.start_address().as_u64()).collect();
+        assert_eq!(addresses, [0x1000, 0x2000, 0x5000]);
+        for frame in frames {
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+    }
+
+    #[test_case]
+    fn stats_count_frames_and_heap_in_use() {
+        let before = stats();
+        assert!(before.allocated_frames > 0 && before.allocated_frames <= before.total_frames);
+        let frame = interrupts::without_interrupts(|| {
+            let mut memory = MEMORY.lock();
+            memory.as_mut().unwrap().frame_allocator.allocate_frame().unwrap()
+        });
+        assert_eq!(stats().allocated_frames, before.allocated_frames + 1);
+        interrupts::without_interrupts(|| unsafe {
+            MEMORY.lock().as_mut().unwrap().frame_allocator.deallocate_frame(frame);
+        });
+        assert_eq!(stats().allocated_frames, before.allocated_frames);
+
+        let block = Box::new([0u8; 4096]);
+        assert!(stats().heap_used >= before.heap_used + block.len());
+        drop(block);
+        assert_eq!(stats().heap_used, before.heap_used);
     }
 }
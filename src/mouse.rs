@@ -0,0 +1,161 @@
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::ps2::{Ps2Controller, PS2};
+
+const ENABLE_AUX_DEVICE: u8 = 0xA8;
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const WRITE_TO_AUX: u8 = 0xD4;
+
+const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+
+const MOUSE_SET_DEFAULTS: u8 = 0xF6;
+const MOUSE_ENABLE_REPORTING: u8 = 0xF4;
+const MOUSE_ACK: u8 = 0xFA;
+
+// Packet byte 0 flags.
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseState {
+    pub x_delta: i16,
+    pub y_delta: i16,
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+// Movement packets are three bytes long and arrive one byte per IRQ12.
+pub struct MouseDecoder {
+    packet: [u8; 3],
+    phase: usize,
+}
+
+impl MouseDecoder {
+    pub const fn new() -> Self {
+        MouseDecoder {
+            packet: [0; 3],
+            phase: 0,
+        }
+    }
+
+    pub fn process(&mut self, byte: u8) -> Option<MouseState> {
+        // Bit 3 of the first byte is always set; anything else means we lost
+        // sync with the packet boundaries.
+        if self.phase == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+
+        self.packet[self.phase] = byte;
+        self.phase += 1;
+        if self.phase < 3 {
+            return None;
+        }
+        self.phase = 0;
+
+        let flags = self.packet[0];
+        if flags & (X_OVERFLOW | Y_OVERFLOW) != 0 {
+            return None;
+        }
+        // The deltas are 9-bit two's complement with the sign bit in byte 0.
+        let x_delta = self.packet[1] as i16 - if flags & X_SIGN != 0 { 0x100 } else { 0 };
+        let y_delta = self.packet[2] as i16 - if flags & Y_SIGN != 0 { 0x100 } else { 0 };
+
+        Some(MouseState {
+            x_delta,
+            y_delta,
+            left: flags & LEFT_BUTTON != 0,
+            right: flags & RIGHT_BUTTON != 0,
+            middle: flags & MIDDLE_BUTTON != 0,
+        })
+    }
+}
+
+static DECODER: Mutex<MouseDecoder> = Mutex::new(MouseDecoder::new());
+static LATEST_STATE: Mutex<MouseState> = Mutex::new(MouseState {
+    x_delta: 0,
+    y_delta: 0,
+    left: false,
+    right: false,
+    middle: false,
+});
+
+fn send_to_mouse(controller: &mut Ps2Controller, byte: u8) -> bool {
+    controller.write_command(WRITE_TO_AUX)
+        && controller.write_data(byte)
+        && controller.read_data() == Some(MOUSE_ACK)
+}
+
+// Returns false if the mouse didn't acknowledge its setup commands.
+pub fn init() -> bool {
+    let mut controller = PS2.lock();
+    controller.write_command(ENABLE_AUX_DEVICE);
+
+    controller.write_command(READ_CONFIG);
+    let config = controller.read_data().unwrap_or(0);
+    let config = (config | CONFIG_AUX_INTERRUPT) & !CONFIG_AUX_CLOCK_DISABLED;
+    controller.write_command(WRITE_CONFIG);
+    controller.write_data(config);
+
+    send_to_mouse(&mut controller, MOUSE_SET_DEFAULTS)
+        && send_to_mouse(&mut controller, MOUSE_ENABLE_REPORTING)
+}
+
+// Called by the PS/2 controller for bytes from the auxiliary port.
+pub fn handle_byte(byte: u8) {
+    if let Some(state) = DECODER.lock().process(byte) {
+        *LATEST_STATE.lock() = state;
+    }
+}
+
+pub fn latest_state() -> MouseState {
+    interrupts::without_interrupts(|| *LATEST_STATE.lock())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(decoder: &mut MouseDecoder, bytes: &[u8]) -> Option<MouseState> {
+        bytes.iter().fold(None, |_, &byte| decoder.process(byte))
+    }
+
+    #[test_case]
+    fn packets_decode_to_movement_and_buttons() {
+        let mut decoder = MouseDecoder::new();
+        // Left button down, 5 right and 3 up.
+        let state = feed(&mut decoder, &[ALWAYS_ONE | LEFT_BUTTON, 5, 3]).unwrap();
+        assert_eq!(
+            state,
+            MouseState {
+                x_delta: 5,
+                y_delta: 3,
+                left: true,
+                right: false,
+                middle: false,
+            }
+        );
+        // Negative deltas: 0x100 - 0xFE = 2 left and 0x100 - 0x80 = 128 down.
+        let state = feed(&mut decoder, &[ALWAYS_ONE | X_SIGN | Y_SIGN | RIGHT_BUTTON, 0xFE, 0x80]).unwrap();
+        assert_eq!((state.x_delta, state.y_delta), (-2, -128));
+        assert!(state.right && !state.left);
+    }
+
+    #[test_case]
+    fn bad_packets_are_skipped() {
+        let mut decoder = MouseDecoder::new();
+        // A stray byte without the always-one bit can't start a packet.
+        assert_eq!(decoder.process(0x00), None);
+        assert_eq!(feed(&mut decoder, &[ALWAYS_ONE | X_OVERFLOW, 0xFF, 0]), None);
+        assert_eq!(feed(&mut decoder, &[ALWAYS_ONE | MIDDLE_BUTTON, 1, 1]).map(|s| s.middle), Some(true));
+    }
+}
@@ -0,0 +1,71 @@
+// PCI configuration space access through the legacy 0xCF8/0xCFC ports.
+
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+// Register offsets in the standard configuration header.
+const REG_ID: u8 = 0x00;
+const REG_HEADER_TYPE: u8 = 0x0C;
+const REG_BAR0: u8 = 0x10;
+
+const HEADER_MULTIFUNCTION: u32 = 0x80 << 16;
+const NO_DEVICE: u16 = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    // Reads the aligned dword at `offset` in this function's header.
+    pub fn read(&self, offset: u8) -> u32 {
+        let address = CONFIG_ENABLE
+            | (u32::from(self.bus) << 16)
+            | (u32::from(self.device) << 11)
+            | (u32::from(self.function) << 8)
+            | u32::from(offset & 0xFC);
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(address);
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(REG_ID) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(REG_ID) >> 16) as u16
+    }
+
+    // The raw value of base address register `index` (0-5).
+    pub fn bar(&self, index: u8) -> u32 {
+        self.read(REG_BAR0 + index * 4)
+    }
+}
+
+// Finds the first function with the given vendor and device IDs by
+// scanning every bus.
+pub fn find_device(vendor_id: u16, device_id: u16) -> Option<PciAddress> {
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let first = PciAddress { bus, device, function: 0 };
+            if first.vendor_id() == NO_DEVICE {
+                continue;
+            }
+            let functions = if first.read(REG_HEADER_TYPE) & HEADER_MULTIFUNCTION != 0 { 8 } else { 1 };
+            for function in 0..functions {
+                let address = PciAddress { bus, device, function };
+                if address.vendor_id() == vendor_id && address.device_id() == device_id {
+                    return Some(address);
+                }
+            }
+        }
+    }
+    None
+}
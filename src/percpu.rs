@@ -0,0 +1,126 @@
+// Per-CPU data. Each processor gets a control block of its own, whose
+// address is kept in its IA32_GS_BASE so this_cpu() can find it with a
+// single GS-relative load and no locking.
+//
+// Code in ring 3 can change the GS base by loading GS, so the address is
+// also kept in IA32_KERNEL_GS_BASE, which only ring 0 can reach, and every
+// entry from ring 3 copies it back with restore_gs_base() before touching
+// per-CPU data. The user's own GS base is not preserved.
+
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::model_specific::Msr;
+
+const IA32_GS_BASE: u32 = 0xC000_0101;
+const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+// last_switch_tick before this CPU has switched tasks.
+const NEVER_SWITCHED: usize = usize::MAX;
+
+#[repr(C)]
+pub struct PerCpu {
+    // Points back at the block itself; must stay the first field, where
+    // this_cpu() reads it.
+    self_ptr: *const PerCpu,
+    // 0 for the boot processor, then 1, 2, ... in the order the APs started.
+    id: usize,
+    // The id of the task running on this CPU.
+    current_task: AtomicUsize,
+    // The tick this CPU last switched tasks at, from which the running
+    // task's CPU time is counted.
+    last_switch_tick: AtomicUsize,
+}
+
+// A block is only written through its atomics.
+unsafe impl Sync for PerCpu {}
+
+impl PerCpu {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn current_task(&self) -> usize {
+        self.current_task.load(Ordering::Relaxed)
+    }
+
+    pub fn set_current_task(&self, id: usize) {
+        self.current_task.store(id, Ordering::Relaxed);
+    }
+
+    pub fn last_switch_tick(&self) -> Option<usize> {
+        Some(self.last_switch_tick.load(Ordering::Relaxed)).filter(|&tick| tick != NEVER_SWITCHED)
+    }
+
+    pub fn set_last_switch_tick(&self, tick: usize) {
+        self.last_switch_tick.store(tick, Ordering::Relaxed);
+    }
+}
+
+// Allocates this CPU's block and points GS at it. Runs once on each CPU,
+// after the heap is up and before anything calls this_cpu(). Blocks are
+// never freed.
+pub fn init(id: usize) {
+    let block = Box::leak(Box::new(PerCpu {
+        self_ptr: core::ptr::null(),
+        id,
+        // Task 0 is the boot processor's bootstrap task. The APs don't take
+        // tasks from the run queue yet.
+        current_task: AtomicUsize::new(0),
+        last_switch_tick: AtomicUsize::new(NEVER_SWITCHED),
+    }));
+    block.self_ptr = &*block;
+    let address = block as *const PerCpu as u64;
+    unsafe {
+        Msr::new(IA32_GS_BASE).write(address);
+        Msr::new(IA32_KERNEL_GS_BASE).write(address);
+    }
+}
+
+// Called first thing by interrupt and exception handlers, with the
+// interrupted code's selector. Entries from the kernel leave GS alone.
+pub fn restore_gs_base(code_segment: u64) {
+    if code_segment & 3 == 0 {
+        return;
+    }
+    unsafe {
+        let address = Msr::new(IA32_KERNEL_GS_BASE).read();
+        Msr::new(IA32_GS_BASE).write(address);
+    }
+}
+
+pub fn this_cpu() -> &'static PerCpu {
+    let block: *const PerCpu;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) block, options(nostack, preserves_flags, readonly));
+        &*block
+    }
+}
+
+pub fn current_cpu_id() -> usize {
+    this_cpu().id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    // The tests run on the boot processor.
+    #[test_case]
+    fn gs_points_at_the_boot_cpus_block() {
+        assert_eq!(current_cpu_id(), 0);
+        let block = this_cpu() as *const PerCpu as u64;
+        assert_eq!(unsafe { Msr::new(IA32_GS_BASE).read() }, block);
+        assert_eq!(unsafe { Msr::new(IA32_KERNEL_GS_BASE).read() }, block);
+    }
+
+    #[test_case]
+    fn each_task_sees_itself_as_current() {
+        static SEEN: AtomicUsize = AtomicUsize::new(usize::MAX);
+        let id = task::tests::spawn(|| SEEN.store(this_cpu().current_task(), Ordering::SeqCst));
+        task::tests::run_until(|| SEEN.load(Ordering::SeqCst) != usize::MAX);
+        assert_eq!(SEEN.load(Ordering::SeqCst), id);
+        assert_ne!(this_cpu().current_task(), id);
+    }
+}
@@ -0,0 +1,59 @@
+use pic8259::ChainedPics;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::InterruptIndex;
+
+// IRQs 0-15 are moved past the 32 vectors reserved for CPU exceptions.
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+// Runs the ICW1-ICW4 sequence on both controllers. Must be called before
+// interrupts are enabled.
+pub fn init() {
+    unsafe {
+        PICS.lock().initialize();
+    }
+}
+
+pub fn notify_end_of_interrupt(irq: InterruptIndex) {
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(irq.as_u8());
+    }
+}
+
+// Masks a single IRQ line through the controller's OCW1 register.
+pub fn disable_irq(irq: InterruptIndex) {
+    let line = irq.as_u8() - PIC_1_OFFSET;
+    let (port, bit) = if line < 8 { (0x21, line) } else { (0xA1, line - 8) };
+    let _pics = PICS.lock();
+    let mut data = Port::<u8>::new(port);
+    unsafe {
+        let mask = data.read();
+        data.write(mask | (1 << bit));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time;
+
+    #[test_case]
+    fn timer_interrupts_arrive_after_init() {
+        let start = time::uptime_ticks();
+        // Each hlt returns on the next interrupt, whatever it is.
+        for _ in 0..1000 {
+            if time::uptime_ticks() > start {
+                break;
+            }
+            x86_64::instructions::hlt();
+        }
+        assert!(time::uptime_ticks() > start);
+        // The timer lands past the vectors reserved for exceptions.
+        assert!(InterruptIndex::Timer.as_u8() >= 32);
+    }
+}
@@ -0,0 +1,277 @@
+use core::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+use x86_64::instructions::{interrupts, port::Port};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use crate::{log_info, ps2};
+
+// A way of resetting or powering off the machine. `attempt` returns if the
+// method had no effect, so the next one can be tried.
+pub trait PowerMethod {
+    fn name(&self) -> &'static str;
+    fn available(&self) -> bool;
+    fn attempt(&self);
+}
+
+struct KeyboardController;
+
+impl PowerMethod for KeyboardController {
+    fn name(&self) -> &'static str {
+        "8042 reset pulse"
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    // reboot() runs with interrupts disabled, possibly from the watchdog in
+    // the timer interrupt, so a controller locked by the interrupted code is
+    // skipped rather than waited for; the next method will reset instead.
+    fn attempt(&self) {
+        // Pulse the CPU reset line through the keyboard controller.
+        if let Some(mut ps2) = ps2::PS2.try_lock() {
+            ps2.write_command(0xFE);
+        }
+    }
+}
+
+// Port 0 means the FADT didn't provide an I/O-space reset register.
+static ACPI_RESET_PORT: AtomicU16 = AtomicU16::new(0);
+static ACPI_RESET_VALUE: AtomicU8 = AtomicU8::new(0);
+
+// Records the FADT reset register for reboot() to use. acpi::init() calls
+// this if the FADT has an I/O-space one; otherwise the method is skipped.
+pub fn set_acpi_reset(port: u16, value: u8) {
+    ACPI_RESET_VALUE.store(value, Ordering::Relaxed);
+    ACPI_RESET_PORT.store(port, Ordering::Relaxed);
+}
+
+struct AcpiReset;
+
+impl PowerMethod for AcpiReset {
+    fn name(&self) -> &'static str {
+        "ACPI reset register"
+    }
+
+    fn available(&self) -> bool {
+        ACPI_RESET_PORT.load(Ordering::Relaxed) != 0
+    }
+
+    fn attempt(&self) {
+        let mut port = Port::<u8>::new(ACPI_RESET_PORT.load(Ordering::Relaxed));
+        unsafe {
+            port.write(ACPI_RESET_VALUE.load(Ordering::Relaxed));
+        }
+    }
+}
+
+struct TripleFault;
+
+impl PowerMethod for TripleFault {
+    fn name(&self) -> &'static str {
+        "triple fault"
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    // With an empty IDT the breakpoint can't be delivered, nor can the
+    // resulting double fault, so the CPU shuts down and the machine resets.
+    fn attempt(&self) {
+        let empty = DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        };
+        unsafe {
+            x86_64::instructions::tables::lidt(&empty);
+            core::arch::asm!("int3");
+        }
+    }
+}
+
+static RESET_METHODS: [&(dyn PowerMethod + Sync); 3] = [&KeyboardController, &AcpiReset, &TripleFault];
+
+// Gives a reset that is in flight time to take effect before the next method
+// is tried. Each write to the POST port takes about a microsecond.
+fn settle() {
+    let mut post = Port::<u8>::new(0x80);
+    for _ in 0..100_000 {
+        unsafe {
+            post.write(0);
+        }
+    }
+}
+
+// Tries each available method in order. Only returns if none of them took
+// the machine down.
+pub fn try_methods(verb: &str, methods: &[&(dyn PowerMethod + Sync)]) {
+    for method in methods.iter().filter(|method| method.available()) {
+        log_info!("{} via {}", verb, method.name());
+        method.attempt();
+        settle();
+    }
+}
+
+pub fn reboot() -> ! {
+    interrupts::disable();
+    try_methods("Rebooting", &RESET_METHODS);
+    panic!("all reset methods failed");
+}
+
+// Port 0 means no ACPI sleep information has been recorded.
+static ACPI_PM1A_PORT: AtomicU16 = AtomicU16::new(0);
+static ACPI_SLP_TYP: AtomicU16 = AtomicU16::new(0);
+const SLP_EN: u16 = 1 << 13;
+
+// Records the FADT PM1a control port and the SLP_TYPa value of the \_S5
+// sleep state for shutdown() to use. Finding SLP_TYPa needs an AML
+// interpreter, which the kernel lacks, so nothing calls this yet.
+#[allow(dead_code)]
+pub fn set_acpi_poweroff(pm1a_control_port: u16, slp_typ: u16) {
+    ACPI_SLP_TYP.store(slp_typ, Ordering::Relaxed);
+    ACPI_PM1A_PORT.store(pm1a_control_port, Ordering::Relaxed);
+}
+
+struct AcpiPowerOff;
+
+impl PowerMethod for AcpiPowerOff {
+    fn name(&self) -> &'static str {
+        "ACPI S5"
+    }
+
+    fn available(&self) -> bool {
+        ACPI_PM1A_PORT.load(Ordering::Relaxed) != 0
+    }
+
+    fn attempt(&self) {
+        let mut port = Port::<u16>::new(ACPI_PM1A_PORT.load(Ordering::Relaxed));
+        let slp_typ = ACPI_SLP_TYP.load(Ordering::Relaxed);
+        unsafe {
+            port.write((slp_typ << 10) | SLP_EN);
+        }
+    }
+}
+
+// Emulators that power off on a fixed write, for when ACPI isn't parsed.
+struct PortPowerOff {
+    name: &'static str,
+    port: u16,
+    value: u16,
+}
+
+impl PowerMethod for PortPowerOff {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    fn attempt(&self) {
+        unsafe {
+            Port::<u16>::new(self.port).write(self.value);
+        }
+    }
+}
+
+// Kernel tests run under QEMU with the isa-debug-exit device, and shutting
+// down there should count as a pass.
+#[cfg(test)]
+struct QemuDebugExit;
+
+#[cfg(test)]
+impl PowerMethod for QemuDebugExit {
+    fn name(&self) -> &'static str {
+        "QEMU isa-debug-exit"
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    fn attempt(&self) {
+        crate::exit_qemu(crate::QemuExitCode::Success);
+    }
+}
+
+static QEMU_POWER_OFF: PortPowerOff = PortPowerOff {
+    name: "QEMU power-off port",
+    port: 0x604,
+    value: 0x2000,
+};
+static BOCHS_POWER_OFF: PortPowerOff = PortPowerOff {
+    name: "Bochs/old QEMU power-off port",
+    port: 0xB004,
+    value: 0x2000,
+};
+
+#[cfg(not(test))]
+static SHUTDOWN_METHODS: [&(dyn PowerMethod + Sync); 3] =
+    [&AcpiPowerOff, &QEMU_POWER_OFF, &BOCHS_POWER_OFF];
+#[cfg(test)]
+static SHUTDOWN_METHODS: [&(dyn PowerMethod + Sync); 4] =
+    [&QemuDebugExit, &AcpiPowerOff, &QEMU_POWER_OFF, &BOCHS_POWER_OFF];
+
+// Returns only if nothing turned the machine off, leaving the caller to
+// decide what to do next.
+pub fn shutdown() {
+    interrupts::without_interrupts(|| try_methods("Powering off", &SHUTDOWN_METHODS));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use spin::Mutex;
+
+    static ATTEMPTS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+    // Records its attempts instead of touching any hardware.
+    struct Stub {
+        name: &'static str,
+        available: bool,
+    }
+
+    impl PowerMethod for Stub {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn available(&self) -> bool {
+            self.available
+        }
+
+        fn attempt(&self) {
+            ATTEMPTS.lock().push(self.name);
+        }
+    }
+
+    #[test_case]
+    fn available_methods_are_tried_in_order() {
+        static FIRST: Stub = Stub { name: "first", available: true };
+        static MISSING: Stub = Stub { name: "missing", available: false };
+        static LAST: Stub = Stub { name: "last", available: true };
+        ATTEMPTS.lock().clear();
+        try_methods("Testing", &[&FIRST, &MISSING, &LAST]);
+        assert_eq!(*ATTEMPTS.lock(), ["first", "last"]);
+    }
+
+    #[test_case]
+    fn reboot_falls_back_from_the_keyboard_controller_to_a_triple_fault() {
+        let names: Vec<_> = RESET_METHODS.iter().map(|method| method.name()).collect();
+        assert_eq!(names, ["8042 reset pulse", "ACPI reset register", "triple fault"]);
+        assert!(RESET_METHODS[0].available() && RESET_METHODS[2].available());
+    }
+
+    // Actually shutting down would end the test run early, so this only
+    // checks that the debug exit device, which reports the run as passed, is
+    // the first thing shutdown() tries.
+    #[test_case]
+    fn shutdown_under_test_uses_the_qemu_exit_device() {
+        let first = SHUTDOWN_METHODS[0];
+        assert_eq!(first.name(), QemuDebugExit.name());
+        assert!(first.available());
+    }
+}
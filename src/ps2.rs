@@ -0,0 +1,132 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::{keyboard, mouse};
+
+const DATA_PORT: u16 = 0x60;
+// Reads return the status register, writes go to the controller.
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const TIMEOUT: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Keyboard,
+    Mouse,
+}
+
+// Bit 5 of the status byte tells whether the pending byte came from the
+// auxiliary (mouse) port.
+pub fn route(status: u8) -> Device {
+    if status & STATUS_AUX_DATA != 0 {
+        Device::Mouse
+    } else {
+        Device::Keyboard
+    }
+}
+
+pub struct Ps2Controller {
+    data: Port<u8>,
+    command: Port<u8>,
+}
+
+impl Ps2Controller {
+    pub const fn new() -> Self {
+        Ps2Controller {
+            data: Port::new(DATA_PORT),
+            command: Port::new(COMMAND_PORT),
+        }
+    }
+
+    pub fn status(&mut self) -> u8 {
+        unsafe { self.command.read() }
+    }
+
+    pub fn wait_input_clear(&mut self) -> bool {
+        (0..TIMEOUT).any(|_| self.status() & STATUS_INPUT_FULL == 0)
+    }
+
+    pub fn wait_output_full(&mut self) -> bool {
+        (0..TIMEOUT).any(|_| self.status() & STATUS_OUTPUT_FULL != 0)
+    }
+
+    pub fn read_data(&mut self) -> Option<u8> {
+        if self.wait_output_full() {
+            Some(unsafe { self.data.read() })
+        } else {
+            None
+        }
+    }
+
+    pub fn write_command(&mut self, command: u8) -> bool {
+        if !self.wait_input_clear() {
+            return false;
+        }
+        unsafe { self.command.write(command) }
+        true
+    }
+
+    pub fn write_data(&mut self, byte: u8) -> bool {
+        if !self.wait_input_clear() {
+            return false;
+        }
+        unsafe { self.data.write(byte) }
+        true
+    }
+}
+
+pub static PS2: Mutex<Ps2Controller> = Mutex::new(Ps2Controller::new());
+
+// Shared by the keyboard and mouse interrupt handlers: whichever IRQ fired,
+// the status byte decides which driver gets the data.
+pub fn handle_interrupt() {
+    let mut controller = PS2.lock();
+    let status = controller.status();
+    let pending = take_pending(status, || unsafe { controller.data.read() });
+    drop(controller);
+
+    match pending {
+        Some((Device::Keyboard, byte)) => keyboard::handle_scancode(byte),
+        Some((Device::Mouse, byte)) => mouse::handle_byte(byte),
+        None => {}
+    }
+}
+
+// The byte waiting in the output buffer, read with `read_data`, and which
+// device sent it. None, without reading, if the status byte says the buffer
+// is empty.
+fn take_pending(status: u8, read_data: impl FnOnce() -> u8) -> Option<(Device, u8)> {
+    if status & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+    Some((route(status), read_data()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test_case]
+    fn the_status_byte_picks_the_device() {
+        // Stands in for the data port, counting reads.
+        let reads = Cell::new(0);
+        let data_port = || {
+            reads.set(reads.get() + 1);
+            0x1E
+        };
+        assert_eq!(take_pending(0, data_port), None);
+        assert_eq!(take_pending(STATUS_AUX_DATA, data_port), None);
+        assert_eq!(reads.get(), 0);
+        assert_eq!(take_pending(STATUS_OUTPUT_FULL, data_port), Some((Device::Keyboard, 0x1E)));
+        assert_eq!(
+            take_pending(STATUS_OUTPUT_FULL | STATUS_AUX_DATA, data_port),
+            Some((Device::Mouse, 0x1E))
+        );
+        assert_eq!(reads.get(), 2);
+    }
+}
@@ -0,0 +1,80 @@
+// xorshift64* pseudo-random numbers. Fast and statistically decent, but
+// predictable from a few outputs, so never use it for anything that needs
+// to be secret.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    // xorshift gets stuck at zero, so a zero seed is replaced.
+    pub const fn new(seed: u64) -> Self {
+        Rng {
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Returns a value in lo..hi, which must not be empty. Scaling by a
+    // 128-bit multiply avoids the bias of taking a remainder.
+    pub fn next_range(&mut self, lo: u64, hi: u64) -> u64 {
+        assert!(lo < hi, "empty range");
+        let span = hi - lo;
+        lo + ((u128::from(self.next_u64()) * u128::from(span)) >> 64) as u64
+    }
+}
+
+static RNG: Mutex<Rng> = Mutex::new(Rng::new(0));
+
+pub fn init(seed: u64) {
+    *RNG.lock() = Rng::new(seed);
+}
+
+pub fn next_u64() -> u64 {
+    interrupts::without_interrupts(|| RNG.lock().next_u64())
+}
+
+#[allow(dead_code)]
+pub fn next_range(lo: u64, hi: u64) -> u64 {
+    interrupts::without_interrupts(|| RNG.lock().next_range(lo, hi))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn a_fixed_seed_gives_a_fixed_sequence() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(42).next_u64(), Rng::new(43).next_u64());
+        // Zero is replaced rather than getting stuck.
+        assert_ne!(Rng::new(0).next_u64(), 0);
+    }
+
+    #[test_case]
+    fn next_range_stays_in_bounds() {
+        let mut rng = Rng::new(7);
+        let mut seen = [false; 10];
+        for _ in 0..10_000 {
+            let value = rng.next_range(5, 15);
+            assert!((5..15).contains(&value));
+            seen[(value - 5) as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+        assert_eq!(rng.next_range(3, 4), 3);
+        let value = rng.next_range(0, u64::MAX);
+        assert!(value < u64::MAX);
+    }
+}
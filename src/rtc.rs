@@ -0,0 +1,173 @@
+use core::fmt;
+use x86_64::instructions::{interrupts, port::Port};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+// Setting bit 7 of the address keeps NMIs disabled while we poke CMOS.
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl DateTime {
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+}
+
+pub fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn read_register(register: u8) -> u8 {
+    let mut address = Port::<u8>::new(CMOS_ADDRESS);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    unsafe {
+        address.write(NMI_DISABLE | register);
+        data.read()
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+// Raw register values in the order seconds, minutes, hours, day, month, year.
+fn read_raw() -> [u8; 6] {
+    while update_in_progress() {}
+    [
+        read_register(REG_SECONDS),
+        read_register(REG_MINUTES),
+        read_register(REG_HOURS),
+        read_register(REG_DAY),
+        read_register(REG_MONTH),
+        read_register(REG_YEAR),
+    ]
+}
+
+// Converts raw register values according to the status B format bits.
+pub fn decode(raw: [u8; 6], status_b: u8) -> DateTime {
+    let [mut second, mut minute, hours, mut day, mut month, mut year] = raw;
+    let pm = hours & HOUR_PM != 0;
+    let mut hour = hours & !HOUR_PM;
+
+    if status_b & STATUS_B_BINARY == 0 {
+        second = bcd_to_binary(second);
+        minute = bcd_to_binary(minute);
+        hour = bcd_to_binary(hour);
+        day = bcd_to_binary(day);
+        month = bcd_to_binary(month);
+        year = bcd_to_binary(year);
+    }
+
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12-hour clock: 12 AM is 0h, 12 PM is 12h.
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    DateTime {
+        year: 2000 + year as u16,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+// Checks that a CMOS clock is there and holds a sane time, returning it.
+// Without one the bus floats to 0xFF, which would leave read_raw waiting on
+// an update that never finishes.
+pub fn init() -> Option<DateTime> {
+    if read_register(REG_STATUS_A) == 0xFF && read_register(REG_STATUS_B) == 0xFF {
+        return None;
+    }
+    Some(read_datetime()).filter(DateTime::is_valid)
+}
+
+pub fn read_datetime() -> DateTime {
+    interrupts::without_interrupts(|| {
+        // An update can start between the UIP check and the reads, so repeat
+        // until two consecutive reads agree.
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        decode(raw, read_register(REG_STATUS_B))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn bcd_digits_convert_to_binary() {
+        assert_eq!(bcd_to_binary(0x00), 0);
+        assert_eq!(bcd_to_binary(0x09), 9);
+        assert_eq!(bcd_to_binary(0x10), 10);
+        assert_eq!(bcd_to_binary(0x59), 59);
+        assert_eq!(bcd_to_binary(0x99), 99);
+    }
+
+    #[test_case]
+    fn registers_decode_in_either_format() {
+        let expected = DateTime { year: 2024, month: 12, day: 31, hour: 23, minute: 59, second: 58 };
+        let bcd_24_hour = [0x58, 0x59, 0x23, 0x31, 0x12, 0x24];
+        assert_eq!(decode(bcd_24_hour, STATUS_B_24_HOUR), expected);
+        let binary_24_hour = [58, 59, 23, 31, 12, 24];
+        assert_eq!(decode(binary_24_hour, STATUS_B_24_HOUR | STATUS_B_BINARY), expected);
+        // 11 PM on a 12-hour clock.
+        let bcd_12_hour = [0x58, 0x59, HOUR_PM | 0x11, 0x31, 0x12, 0x24];
+        assert_eq!(decode(bcd_12_hour, 0), expected);
+        // 12 AM is midnight.
+        assert_eq!(decode([0, 0, 0x12, 0x01, 0x01, 0x25], 0).hour, 0);
+    }
+
+    #[test_case]
+    fn the_clock_reads_a_valid_time() {
+        assert!(read_datetime().is_valid());
+    }
+}
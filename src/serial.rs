@@ -0,0 +1,156 @@
+// 16550 UART on COM1. Under QEMU, pass `-serial stdio` to see this output
+// on the host terminal.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+const COM1: u16 = 0x3F8;
+
+// 115200 / 3 = 38400 baud
+const BAUD_DIVISOR: u16 = 3;
+
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+// Sent while the UART is in loopback mode; it should come straight back.
+const LOOPBACK_TEST_BYTE: u8 = 0xAE;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+pub struct SerialPort {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: Port<u8>,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> Self {
+        SerialPort {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: Port::new(base + 5),
+        }
+    }
+
+    // Programs the UART and checks it with a loopback round trip. Returns
+    // false if nothing answering like a 16550 sits at the port.
+    pub fn init(&mut self) -> bool {
+        unsafe {
+            self.interrupt_enable.write(0x00);
+
+            // With DLAB set, the data and interrupt-enable registers hold
+            // the low and high bytes of the baud rate divisor.
+            self.line_control.write(0x80);
+            self.data.write((BAUD_DIVISOR & 0xFF) as u8);
+            self.interrupt_enable.write((BAUD_DIVISOR >> 8) as u8);
+
+            // 8 data bits, no parity, one stop bit, DLAB cleared.
+            self.line_control.write(0x03);
+            // Enable and clear the FIFOs with a 14-byte threshold.
+            self.fifo_control.write(0xC7);
+            // Loopback with OUT1 and OUT2, so the test byte never goes out
+            // on the line.
+            self.modem_control.write(0x1E);
+            self.data.write(LOOPBACK_TEST_BYTE);
+            if self.data.read() != LOOPBACK_TEST_BYTE {
+                return false;
+            }
+            // DTR, RTS and OUT2.
+            self.modem_control.write(0x0B);
+        }
+        true
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        unsafe {
+            while self.line_status.read() & LINE_STATUS_TRANSMIT_EMPTY == 0 {}
+            self.data.write(byte);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = SerialPort::new(COM1);
+        PRESENT.store(serial_port.init(), Ordering::Relaxed);
+        Mutex::new(serial_port)
+    };
+}
+
+// Whether COM1 passed its loopback check. Output to a missing port is simply
+// lost, so printing works either way.
+pub fn init() -> bool {
+    lazy_static::initialize(&SERIAL1);
+    PRESENT.load(Ordering::Relaxed)
+}
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+// Same locking rules as vga_buffer::_print.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    if interrupts::are_enabled() {
+        interrupts::without_interrupts(|| SERIAL1.lock().write_fmt(args).unwrap());
+    } else if let Some(mut serial) = SERIAL1.try_lock() {
+        serial.write_fmt(args).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+
+    // In loopback mode everything sent is received straight back.
+    #[test_case]
+    fn sent_bytes_reach_the_wire() {
+        let sent = b"RustOS serial";
+        // Checked once the port is unlocked, so a failure can be reported.
+        let received: Vec<u8> = interrupts::without_interrupts(|| {
+            let mut serial = SERIAL1.lock();
+            unsafe { serial.modem_control.write(0x1E) };
+            let received = sent
+                .iter()
+                .map(|&byte| {
+                    serial.send(byte);
+                    unsafe {
+                        while serial.line_status.read() & LINE_STATUS_DATA_READY == 0 {}
+                        serial.data.read()
+                    }
+                })
+                .collect();
+            unsafe { serial.modem_control.write(0x0B) };
+            received
+        });
+        assert_eq!(received, sent);
+    }
+}
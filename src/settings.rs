@@ -0,0 +1,98 @@
+// Named string settings, changed with the shell's `set` command. A few keys
+// also configure the kernel as they are set; see apply().
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::vga_buffer::{self, Color};
+use crate::log::{self, Level};
+use crate::{task, time, watchdog};
+
+// The shell's prompt, which may include {cwd} and {status}; see
+// shell::render_prompt.
+pub const PROMPT: &str = "prompt";
+// The default text colors, as "<fg> <bg>".
+pub const COLOR: &str = "color";
+// How long a task runs before the scheduler may switch, in milliseconds.
+pub const TIMESLICE: &str = "timeslice";
+// "on" to reboot if the shell hangs; see the watchdog module.
+pub const WATCHDOG: &str = "watchdog";
+// The least severe log level shown: info, warn or error.
+pub const LOGLEVEL: &str = "loglevel";
+// "off" to drop log lines below the log level instead of keeping them for
+// dmesg.
+pub const LOGKEEP: &str = "logkeep";
+
+const DEFAULT_PROMPT: &str = "{cwd}> ";
+
+static SETTINGS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+pub fn get(key: &str) -> Option<String> {
+    interrupts::without_interrupts(|| SETTINGS.lock().get(key).cloned())
+}
+
+// Every setting, in key order.
+pub fn all() -> Vec<(String, String)> {
+    interrupts::without_interrupts(|| {
+        SETTINGS
+            .lock()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    })
+}
+
+// Stores `value` under `key`. A value the kernel can't use for its key is
+// refused with the reason, keeping the previous value.
+pub fn set(key: &str, value: &str) -> Result<(), String> {
+    apply(key, value)?;
+    interrupts::without_interrupts(|| {
+        SETTINGS.lock().insert(key.to_string(), value.to_string());
+    });
+    Ok(())
+}
+
+fn apply(key: &str, value: &str) -> Result<(), String> {
+    match key {
+        COLOR => {
+            let names: Vec<&str> = value.split_whitespace().collect();
+            match names.as_slice() {
+                [fg, bg] => match (Color::from_name(fg), Color::from_name(bg)) {
+                    (Some(fg), Some(bg)) => vga_buffer::set_color(fg, bg),
+                    (None, _) => return Err(format!("unknown color: {}", fg)),
+                    (_, None) => return Err(format!("unknown color: {}", bg)),
+                },
+                _ => return Err(String::from("expected <fg> <bg>")),
+            }
+        }
+        TIMESLICE => match value.parse::<usize>() {
+            Ok(ms) if ms > 0 => task::set_time_slice(time::ms_to_ticks(ms).max(1)),
+            _ => return Err(String::from("expected a number of milliseconds")),
+        },
+        WATCHDOG => match value {
+            "on" => watchdog::set_enabled(true),
+            "off" => watchdog::set_enabled(false),
+            _ => return Err(String::from("expected on or off")),
+        },
+        LOGLEVEL => match Level::from_name(value) {
+            Some(level) => log::set_min_level(level),
+            None => return Err(String::from("expected info, warn or error")),
+        },
+        LOGKEEP => match value {
+            "on" => log::set_keep_filtered(true),
+            "off" => log::set_keep_filtered(false),
+            _ => return Err(String::from("expected on or off")),
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+// The prompt template.
+pub fn prompt() -> String {
+    get(PROMPT).unwrap_or_else(|| String::from(DEFAULT_PROMPT))
+}
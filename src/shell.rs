@@ -0,0 +1,978 @@
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::filesystem::{self, FileSystem, FsError};
+use crate::{keyboard, settings};
+use crate::vga_buffer::{self, Color};
+use crate::{print, println, println_colored};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenizeError {
+    UnterminatedQuote,
+    TrailingBackslash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    // The unquoted whitespace typed before the word is kept so commands like
+    // echo can space their arguments as they were typed.
+    Word { text: String, space_before: String },
+    // An unquoted '>'.
+    Redirect,
+    // An unquoted '|'.
+    Pipe,
+}
+
+// Splits a command line on whitespace. Double quotes group text (including
+// spaces) into one token and are stripped; a backslash makes the next
+// character literal, inside or outside quotes. An unquoted '>' or '|' is an
+// operator token even without surrounding spaces.
+pub fn tokenize(line: &str) -> Result<Vec<Token>, TokenizeError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    // Whitespace since the last token, and before the current one.
+    let mut space = String::new();
+    let mut space_before = String::new();
+    // Distinguishes an empty quoted token ("") from no token at all.
+    let mut in_token = false;
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        // Outside a token there are no open quotes, so this starts one.
+        if !in_token && !c.is_whitespace() && !matches!(c, '>' | '|') {
+            space_before = core::mem::take(&mut space);
+        }
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) => {
+                    current.push(escaped);
+                    in_token = true;
+                }
+                None => return Err(TokenizeError::TrailingBackslash),
+            },
+            '"' => {
+                in_quotes = !in_quotes;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_token {
+                    tokens.push(take_word(&mut current, &mut space_before));
+                    in_token = false;
+                }
+                space.push(c);
+            }
+            '>' | '|' if !in_quotes => {
+                if in_token {
+                    tokens.push(take_word(&mut current, &mut space_before));
+                    in_token = false;
+                }
+                space.clear();
+                tokens.push(if c == '>' { Token::Redirect } else { Token::Pipe });
+            }
+            c => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err(TokenizeError::UnterminatedQuote);
+    }
+    if in_token {
+        tokens.push(take_word(&mut current, &mut space_before));
+    }
+    Ok(tokens)
+}
+
+fn take_word(text: &mut String, space_before: &mut String) -> Token {
+    Token::Word {
+        text: core::mem::take(text),
+        space_before: core::mem::take(space_before),
+    }
+}
+
+// One word of a pipeline stage and the whitespace typed before it.
+type Word<'a> = (&'a str, &'a str);
+
+// Splits tokens into pipeline stages and an optional redirect target. Only
+// the last stage may redirect, as `stage > file`. An empty line gives no
+// stages.
+fn parse_pipeline(tokens: &[Token]) -> Result<(Vec<Vec<Word<'_>>>, Option<&str>), &'static str> {
+    let mut stages = Vec::new();
+    let mut current = Vec::new();
+    let mut redirect = None;
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word { text, space_before } => current.push((text.as_str(), space_before.as_str())),
+            Token::Pipe => {
+                if current.is_empty() {
+                    return Err("empty command in pipeline");
+                }
+                stages.push(core::mem::take(&mut current));
+            }
+            Token::Redirect => match (tokens.next(), tokens.next()) {
+                (Some(Token::Word { text, .. }), None) => redirect = Some(text.as_str()),
+                _ => return Err("expected one file name after '>'"),
+            },
+        }
+    }
+    if current.is_empty() && !stages.is_empty() {
+        return Err("empty command in pipeline");
+    }
+    if !current.is_empty() {
+        stages.push(current);
+    }
+    Ok((stages, redirect))
+}
+
+// Where commands send their output: the screen, or a buffer while the output
+// is being redirected. Usage and error messages are printed directly so they
+// still reach the screen. Being inherent, `write_fmt` lets commands use
+// `writeln!(ctx.out, ...)` without a Result to discard.
+pub struct Output {
+    capture: Option<String>,
+}
+
+impl Output {
+    fn console() -> Self {
+        Output { capture: None }
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_fmt(format_args!("{}", s));
+    }
+
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        match &mut self.capture {
+            Some(buffer) => {
+                let _ = fmt::Write::write_fmt(buffer, args);
+            }
+            None => vga_buffer::_print(args),
+        }
+    }
+
+    // Whether output is going to a buffer rather than the screen.
+    pub fn is_captured(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    // Colored on the console; captured output gets the plain text.
+    pub fn write_colored(&mut self, color: Color, args: fmt::Arguments) {
+        match &mut self.capture {
+            Some(buffer) => {
+                let _ = fmt::Write::write_fmt(buffer, args);
+            }
+            None => vga_buffer::_print_colored(color, args),
+        }
+    }
+
+    // Captures nest, as when a redirected `run` runs a script line that is
+    // itself piped: the capture already in progress is returned, to be given
+    // back to finish_capture(), which resumes it.
+    fn start_capture(&mut self) -> Option<String> {
+        self.capture.replace(String::new())
+    }
+
+    fn finish_capture(&mut self, outer: Option<String>) -> String {
+        core::mem::replace(&mut self.capture, outer).unwrap_or_default()
+    }
+}
+
+// Exit statuses, following Unix shells.
+pub const STATUS_SUCCESS: i32 = 0;
+pub const STATUS_FAILURE: i32 = 1;
+// Bad arguments, or a line that doesn't parse.
+pub const STATUS_USAGE: i32 = 2;
+pub const STATUS_NOT_FOUND: i32 = 127;
+
+// Expands `{cwd}` and `{status}` in a prompt template. Without a current
+// directory `{cwd}` expands to nothing, so a prompt can be shown before the
+// shell is set up. Other text, including unknown braces, is kept as it is.
+pub fn render_prompt(template: &str, cwd: Option<&str>, status: i32) -> String {
+    template
+        .replace("{cwd}", cwd.unwrap_or(""))
+        .replace("{status}", &format!("{}", status))
+}
+
+pub trait Command: Sync {
+    fn name(&self) -> &'static str;
+    // One-line usage and description shown by `help`.
+    fn help(&self) -> &'static str;
+    // `args` excludes the command name itself. Returns the exit status.
+    fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32;
+}
+
+pub struct CommandRegistry {
+    commands: Vec<&'static dyn Command>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        CommandRegistry {
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, command: &'static dyn Command) {
+        self.commands.push(command);
+    }
+
+    pub fn find(&self, name: &str) -> Option<&'static dyn Command> {
+        self.commands.iter().copied().find(|command| command.name() == name)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &'static dyn Command> + '_ {
+        self.commands.iter().copied()
+    }
+}
+
+pub struct ShellContext {
+    pub fs: FileSystem,
+    pub cwd: String,
+    pub registry: CommandRegistry,
+    pub out: Output,
+    // The previous pipeline stage's output while running the next stage.
+    pub input: Option<String>,
+    pub history: History,
+    // The exit status of the last command run.
+    pub status: i32,
+    // How many scripts are running, each from a line of the one before.
+    script_depth: usize,
+    // The whitespace typed before each argument of the running command.
+    arg_spaces: Vec<String>,
+}
+
+// Deep enough for scripts that run other scripts, low enough that a script
+// running itself stops well before the stack runs out.
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptError {
+    Read(FsError),
+    TooDeep,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Read(err) => write!(f, "{}", err),
+            ScriptError::TooDeep => write!(f, "scripts nested more than {} deep", MAX_SCRIPT_DEPTH),
+        }
+    }
+}
+
+impl ShellContext {
+    // Picks up the history saved by the last shutdown, if any.
+    pub fn new(fs: FileSystem, registry: CommandRegistry) -> Self {
+        let history = History::load(&fs);
+        ShellContext {
+            fs,
+            cwd: String::from("/"),
+            registry,
+            out: Output::console(),
+            input: None,
+            history,
+            status: 0,
+            script_depth: 0,
+            arg_spaces: Vec::new(),
+        }
+    }
+
+    pub fn prompt(&self) -> String {
+        render_prompt(&settings::prompt(), Some(&self.cwd), self.status)
+    }
+
+    // The running command's arguments from `first` on as one string, spaced
+    // as they were typed rather than collapsed to single spaces the way
+    // args.join(" ") would. `args` must be the slice the command was given.
+    pub fn joined_args(&self, args: &[&str], first: usize) -> String {
+        let mut text = String::new();
+        for (index, arg) in args.iter().enumerate().skip(first) {
+            if index > first {
+                text.push_str(self.arg_spaces.get(index).map_or(" ", String::as_str));
+            }
+            text.push_str(arg);
+        }
+        text
+    }
+
+    // Resolves a path argument against the current directory.
+    pub fn resolve(&self, path: &str) -> String {
+        filesystem::resolve(&self.cwd, path)
+    }
+
+    pub fn execute(&mut self, line: &str) {
+        let tokens = match tokenize(line) {
+            Ok(tokens) => tokens,
+            Err(TokenizeError::UnterminatedQuote) => {
+                println_colored!(Color::Red, "Syntax error: unterminated quote");
+                self.status = STATUS_USAGE;
+                return;
+            }
+            Err(TokenizeError::TrailingBackslash) => {
+                println_colored!(Color::Red, "Syntax error: trailing backslash");
+                self.status = STATUS_USAGE;
+                return;
+            }
+        };
+        let (stages, redirect) = match parse_pipeline(&tokens) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                println_colored!(Color::Red, "Syntax error: {}", message);
+                self.status = STATUS_USAGE;
+                return;
+            }
+        };
+
+        // Each stage but the last is captured and becomes the next one's input.
+        let (last, earlier) = match stages.split_last() {
+            Some(split) => split,
+            None => return,
+        };
+        let mut input = None;
+        for stage in earlier {
+            self.input = input.take();
+            let outer = self.out.start_capture();
+            self.run(stage);
+            input = Some(self.out.finish_capture(outer));
+        }
+        self.input = input;
+        match redirect {
+            None => self.run(last),
+            Some(file) => {
+                let outer = self.out.start_capture();
+                self.run(last);
+                let output = self.out.finish_capture(outer);
+                let path = self.resolve(file);
+                if let Err(err) = self.fs.create_file(&path, output.as_bytes()) {
+                    println_colored!(Color::Red, "Cannot write {}: {}", file, err);
+                    self.status = STATUS_FAILURE;
+                }
+            }
+        }
+        self.input = None;
+    }
+
+    // Runs each line of the file at `path` as if it had been typed, skipping
+    // blank lines and lines starting with #. A line that fails gets a
+    // warning, and with `stop_on_error` also ends the script. Returns the
+    // status of the last line run.
+    pub fn run_script(&mut self, path: &str, stop_on_error: bool) -> Result<i32, ScriptError> {
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            return Err(ScriptError::TooDeep);
+        }
+        let data = self.fs.read_file(path).map_err(ScriptError::Read)?;
+        let text = String::from_utf8_lossy(&data);
+        self.script_depth += 1;
+        self.status = STATUS_SUCCESS;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.execute(line);
+            if self.status != STATUS_SUCCESS {
+                println_colored!(Color::Yellow, "{}:{}: exit status {}", path, index + 1, self.status);
+                if stop_on_error {
+                    break;
+                }
+            }
+        }
+        self.script_depth -= 1;
+        Ok(self.status)
+    }
+
+    fn run(&mut self, stage: &[Word]) {
+        let words: Vec<&str> = stage.iter().map(|&(text, _)| text).collect();
+        let (name, args) = match words.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        match self.registry.find(name) {
+            Some(command) => {
+                self.arg_spaces = stage[1..].iter().map(|&(_, space)| String::from(space)).collect();
+                self.status = command.run(args, self);
+            }
+            None => {
+                println_colored!(Color::Red, "Unknown command. Type 'help' for available commands.");
+                self.status = STATUS_NOT_FOUND;
+            }
+        }
+    }
+}
+
+const HISTORY_CAPACITY: usize = 50;
+// Where the history is kept across reboots, one entry per line.
+const HISTORY_PATH: &str = "/.history";
+const SCROLL_PAGE_LINES: usize = 20;
+
+pub struct History {
+    entries: VecDeque<String>,
+    // Index into `entries` while recalling with Up/Down, None when editing
+    // a fresh line.
+    cursor: Option<usize>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            entries: VecDeque::with_capacity(HISTORY_CAPACITY),
+            cursor: None,
+        }
+    }
+
+    // Empty lines are not recorded. The oldest entry is dropped when full.
+    pub fn push(&mut self, line: &str) {
+        self.end_recall();
+        if line.trim().is_empty() {
+            return;
+        }
+        if self.entries.len() == HISTORY_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(String::from(line));
+    }
+
+    // Goes back to editing a fresh line, so the next previous() starts from
+    // the newest entry again.
+    pub fn end_recall(&mut self) {
+        self.cursor = None;
+    }
+
+    // Steps to the next older entry, staying on the oldest one.
+    pub fn previous(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let index = match self.cursor {
+            None => self.entries.len() - 1,
+            Some(index) => index.saturating_sub(1),
+        };
+        self.cursor = Some(index);
+        Some(&self.entries[index])
+    }
+
+    // Steps to the next newer entry. Moving past the newest one returns
+    // None, meaning the line should be cleared.
+    pub fn next(&mut self) -> Option<&str> {
+        let index = self.cursor? + 1;
+        if index >= self.entries.len() {
+            self.cursor = None;
+            return None;
+        }
+        self.cursor = Some(index);
+        Some(&self.entries[index])
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(String::as_str)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor = None;
+    }
+
+    pub fn save(&self, fs: &mut FileSystem) -> Result<(), FsError> {
+        let mut text = String::new();
+        for entry in &self.entries {
+            text.push_str(entry);
+            text.push('\n');
+        }
+        fs.create_file(HISTORY_PATH, text.as_bytes())
+    }
+
+    // Reads the history written by save(). A missing file, or one holding
+    // anything a typed line couldn't, gives an empty history rather than an
+    // error, so a damaged file can't keep the shell from starting.
+    pub fn load(fs: &FileSystem) -> History {
+        let mut history = History::new();
+        let data = match fs.read_file(HISTORY_PATH) {
+            Ok(data) => data,
+            Err(_) => return history,
+        };
+        if !data.iter().all(|&b| b == b'\n' || (0x20..=0x7E).contains(&b)) {
+            return history;
+        }
+        // Only ASCII remains, so this can't fail.
+        for line in core::str::from_utf8(&data).unwrap_or("").lines() {
+            history.push(line);
+        }
+        history
+    }
+}
+
+// The line being typed plus the cursor position within it. Every edit
+// echoes its effect, so the screen always mirrors `line` with the hardware
+// cursor at `cursor`. Only printable ASCII is stored, so byte and character
+// indices coincide.
+struct LineEditor {
+    line: String,
+    cursor: usize,
+}
+
+fn move_back(cells: usize) {
+    for _ in 0..cells {
+        print!("\x08");
+    }
+}
+
+impl LineEditor {
+    fn new() -> Self {
+        LineEditor {
+            line: String::new(),
+            cursor: 0,
+        }
+    }
+
+    fn insert(&mut self, c: char) {
+        self.line.insert(self.cursor, c);
+        print!("{}", &self.line[self.cursor..]);
+        self.cursor += 1;
+        move_back(self.line.len() - self.cursor);
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor == 0 {
+            return;
+        }
+        self.cursor -= 1;
+        self.line.remove(self.cursor);
+        print!("\x08{} ", &self.line[self.cursor..]);
+        move_back(self.line.len() - self.cursor + 1);
+    }
+
+    fn delete(&mut self) {
+        if self.cursor == self.line.len() {
+            return;
+        }
+        self.line.remove(self.cursor);
+        print!("{} ", &self.line[self.cursor..]);
+        move_back(self.line.len() - self.cursor + 1);
+    }
+
+    fn left(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            move_back(1);
+        }
+    }
+
+    fn right(&mut self) {
+        if self.cursor < self.line.len() {
+            print!("{}", &self.line[self.cursor..self.cursor + 1]);
+            self.cursor += 1;
+        }
+    }
+
+    fn home(&mut self) {
+        move_back(self.cursor);
+        self.cursor = 0;
+    }
+
+    fn end(&mut self) {
+        print!("{}", &self.line[self.cursor..]);
+        self.cursor = self.line.len();
+    }
+
+    // Echoes the prompt and line again on a cleared screen.
+    fn redraw(&self, prompt: &str) {
+        print!("{}{}", prompt, self.line);
+        move_back(self.line.len() - self.cursor);
+    }
+
+    // Erases the echoed line and echoes `line` in its place.
+    fn replace(&mut self, line: &str) {
+        self.end();
+        for _ in 0..self.line.len() {
+            print!("\x08 \x08");
+        }
+        self.line.clear();
+        self.line.push_str(line);
+        self.cursor = line.len();
+        print!("{}", line);
+    }
+}
+
+// Byte length of the common prefix; fine for names, which are ASCII.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count()
+}
+
+// Returns the candidates starting with `partial`, and what `partial` should
+// become: a unique match in full, followed by a space unless it's a directory
+// (ending in '/') that the user will want to continue into, or else the
+// longest prefix the matches share.
+pub fn complete<'a>(partial: &str, candidates: &'a [String]) -> (Vec<&'a str>, String) {
+    let matches: Vec<&str> = candidates
+        .iter()
+        .map(String::as_str)
+        .filter(|candidate| candidate.starts_with(partial))
+        .collect();
+    let completed = match matches.as_slice() {
+        [] => String::from(partial),
+        [only] if only.ends_with('/') => String::from(*only),
+        [only] => format!("{} ", only),
+        [first, rest @ ..] => {
+            let len = rest.iter().fold(first.len(), |len, m| len.min(common_prefix_len(first, m)));
+            String::from(&first[..len])
+        }
+    };
+    (matches, completed)
+}
+
+// Completes the word before the cursor: a command name if it's the first one,
+// otherwise a file or directory name, possibly after a directory path.
+fn complete_word(editor: &mut LineEditor, prompt: &str, ctx: &ShellContext) {
+    let before = &editor.line[..editor.cursor];
+    let start = before.rfind(' ').map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let (candidates, partial): (Vec<String>, &str) = if before[..start].trim().is_empty() {
+        let names = ctx.registry.commands().map(|command| String::from(command.name()));
+        (names.collect(), word)
+    } else {
+        let (dir, name) = word.split_at(word.rfind('/').map_or(0, |i| i + 1));
+        (ctx.fs.list_files(Some(&ctx.resolve(dir))).unwrap_or_default(), name)
+    };
+    let (matches, completed) = complete(partial, &candidates);
+    let suffix = String::from(&completed[partial.len()..]);
+    for c in suffix.chars() {
+        editor.insert(c);
+    }
+    if matches.len() > 1 {
+        println!();
+        println!("{}", matches.join("  "));
+        editor.redraw(prompt);
+    }
+}
+
+// Prints `prompt` and reads a line, completing words on Tab. Returns None if
+// the line was cancelled with Ctrl-C.
+pub fn read_line(prompt: &str, ctx: &mut ShellContext) -> Option<String> {
+    print!("{}", prompt);
+    let mut editor = LineEditor::new();
+    loop {
+        let key = keyboard::read_char();
+        match key {
+            b'\n' => {
+                editor.end();
+                println!();
+                ctx.history.push(&editor.line);
+                return Some(editor.line);
+            }
+            keyboard::KEY_CTRL_C => {
+                editor.end();
+                println!("^C");
+                ctx.history.end_recall();
+                return None;
+            }
+            keyboard::KEY_CTRL_L => {
+                vga_buffer::clear_screen();
+                editor.redraw(prompt);
+            }
+            8 => editor.backspace(),
+            b'\t' => complete_word(&mut editor, prompt, ctx),
+            keyboard::KEY_DELETE => editor.delete(),
+            keyboard::KEY_LEFT => editor.left(),
+            keyboard::KEY_RIGHT => editor.right(),
+            keyboard::KEY_HOME => editor.home(),
+            keyboard::KEY_END => editor.end(),
+            keyboard::KEY_UP => {
+                if let Some(entry) = ctx.history.previous() {
+                    let entry = String::from(entry);
+                    editor.replace(&entry);
+                }
+            }
+            keyboard::KEY_DOWN => {
+                let entry = ctx.history.next().map(String::from).unwrap_or_default();
+                editor.replace(&entry);
+            }
+            keyboard::KEY_PAGE_UP => {
+                vga_buffer::with_writer(|writer| writer.scroll_up(SCROLL_PAGE_LINES))
+            }
+            keyboard::KEY_PAGE_DOWN => {
+                vga_buffer::with_writer(|writer| writer.scroll_down(SCROLL_PAGE_LINES))
+            }
+            32..=126 => editor.insert(key as char),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::block::RamDisk;
+    use crate::commands;
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    // A shell with the real commands over an empty RAM disk.
+    pub fn new_shell() -> ShellContext {
+        let fs = FileSystem::format(Box::new(RamDisk::new(256))).unwrap();
+        ShellContext::new(fs, commands::registry())
+    }
+
+    // Runs `line` and returns what it printed through `ctx.out`.
+    pub fn run_captured(shell: &mut ShellContext, line: &str) -> String {
+        let outer = shell.out.start_capture();
+        shell.execute(line);
+        shell.out.finish_capture(outer)
+    }
+
+    #[test_case]
+    fn history_recalls_entries_newest_first() {
+        let mut history = History::new();
+        history.push("ls");
+        history.push("   ");
+        history.push("cat a");
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.previous(), Some("cat a"));
+        assert_eq!(history.previous(), Some("ls"));
+        // Stays on the oldest entry.
+        assert_eq!(history.previous(), Some("ls"));
+        assert_eq!(history.next(), Some("cat a"));
+        assert_eq!(history.next(), None);
+        assert_eq!(history.next(), None);
+    }
+
+    #[test_case]
+    fn ending_a_recall_starts_over_from_the_newest_entry() {
+        let mut history = History::new();
+        history.push("ls");
+        history.push("cat a");
+        assert_eq!(history.previous(), Some("cat a"));
+        assert_eq!(history.previous(), Some("ls"));
+        history.end_recall();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.next(), None);
+        assert_eq!(history.previous(), Some("cat a"));
+    }
+
+    #[test_case]
+    fn full_history_drops_the_oldest_entry() {
+        let mut history = History::new();
+        for n in 0..HISTORY_CAPACITY + 1 {
+            history.push(&format!("echo {}", n));
+        }
+        assert_eq!(history.len(), HISTORY_CAPACITY);
+        assert_eq!(history.iter().next(), Some("echo 1"));
+        assert_eq!(history.previous(), Some(format!("echo {}", HISTORY_CAPACITY).as_str()));
+    }
+
+    #[test_case]
+    fn history_survives_save_and_load() {
+        let mut fs = FileSystem::format(Box::new(RamDisk::new(256))).unwrap();
+        assert_eq!(History::load(&fs).len(), 0);
+        let mut history = History::new();
+        history.push("ls /");
+        history.push("echo \"hi there\"");
+        history.save(&mut fs).unwrap();
+        let loaded = History::load(&fs);
+        assert!(loaded.iter().eq(["ls /", "echo \"hi there\""]));
+
+        // A damaged file gives an empty history instead of garbage entries.
+        fs.create_file(HISTORY_PATH, b"ls\n\xFF\x00junk\n").unwrap();
+        assert_eq!(History::load(&fs).len(), 0);
+    }
+
+    // The editor echoes to the screen as it goes; only the line is checked.
+    #[test_case]
+    fn editing_in_the_middle_of_a_line() {
+        let mut editor = LineEditor::new();
+        for c in "helo".chars() {
+            editor.insert(c);
+        }
+        editor.left();
+        editor.insert('l');
+        assert_eq!((editor.line.as_str(), editor.cursor), ("hello", 4));
+        editor.left();
+        editor.left();
+        editor.delete();
+        assert_eq!((editor.line.as_str(), editor.cursor), ("helo", 2));
+        editor.backspace();
+        assert_eq!((editor.line.as_str(), editor.cursor), ("hlo", 1));
+        editor.home();
+        editor.backspace();
+        editor.end();
+        editor.delete();
+        assert_eq!((editor.line.as_str(), editor.cursor), ("hlo", 3));
+    }
+
+    #[test_case]
+    fn echo_output_can_be_captured() {
+        let mut shell = new_shell();
+        assert_eq!(run_captured(&mut shell, "echo hello   world"), "hello   world\n");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        assert_eq!(run_captured(&mut shell, "echo \"a  b\" c"), "a  b c\n");
+    }
+
+    // Writes its arguments back in reverse order.
+    struct Reverse;
+
+    impl Command for Reverse {
+        fn name(&self) -> &'static str {
+            "reverse"
+        }
+
+        fn help(&self) -> &'static str {
+            "reverse <args> - Print the arguments last to first"
+        }
+
+        fn run(&self, args: &[&str], ctx: &mut ShellContext) -> i32 {
+            let reversed: Vec<&str> = args.iter().rev().copied().collect();
+            writeln!(ctx.out, "{}", reversed.join(" "));
+            if args.is_empty() {
+                STATUS_USAGE
+            } else {
+                STATUS_SUCCESS
+            }
+        }
+    }
+
+    #[test_case]
+    fn registered_commands_are_found_and_run() {
+        let mut registry = CommandRegistry::new();
+        registry.register(&Reverse);
+        assert!(registry.find("reverse").is_some());
+        assert!(registry.find("rev").is_none());
+        let fs = FileSystem::format(Box::new(RamDisk::new(256))).unwrap();
+        let mut shell = ShellContext::new(fs, registry);
+        assert_eq!(run_captured(&mut shell, "reverse a b c"), "c b a\n");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        run_captured(&mut shell, "reverse");
+        assert_eq!(shell.status, STATUS_USAGE);
+        run_captured(&mut shell, "echo a");
+        assert_eq!(shell.status, STATUS_NOT_FOUND);
+    }
+
+    // The text of each token, which must all be words.
+    fn words(line: &str) -> Vec<String> {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|token| match token {
+                Token::Word { text, .. } => text,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test_case]
+    fn tokenize_handles_quotes_and_escapes() {
+        assert_eq!(words("  ls   -l\t/ "), ["ls", "-l", "/"]);
+        assert_eq!(words("echo \"a  b\"c"), ["echo", "a  bc"]);
+        assert_eq!(words("echo \"\""), ["echo", ""]);
+        assert_eq!(words("a\\ b \\\"c\\\\"), ["a b", "\"c\\"]);
+        assert_eq!(words("\"x > y\" a\\|b"), ["x > y", "a|b"]);
+        assert!(words("").is_empty());
+    }
+
+    #[test_case]
+    fn tokenize_splits_operators_without_spaces() {
+        let tokens = tokenize("a|b>c").unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[1], Token::Pipe);
+        assert_eq!(tokens[3], Token::Redirect);
+    }
+
+    #[test_case]
+    fn tokenize_rejects_malformed_lines() {
+        assert_eq!(tokenize("echo \"open"), Err(TokenizeError::UnterminatedQuote));
+        assert_eq!(tokenize("echo end\\"), Err(TokenizeError::TrailingBackslash));
+        assert_eq!(tokenize("\"a\\\""), Err(TokenizeError::UnterminatedQuote));
+    }
+
+    #[test_case]
+    fn redirected_output_goes_to_the_file() {
+        let mut shell = new_shell();
+        assert_eq!(run_captured(&mut shell, "echo hi > f.txt"), "");
+        assert_eq!(shell.fs.read_file("/f.txt").unwrap(), b"hi\n");
+        run_captured(&mut shell, "echo again>f.txt");
+        assert_eq!(shell.fs.read_file("/f.txt").unwrap(), b"again\n");
+        run_captured(&mut shell, "echo hi >");
+        assert_eq!(shell.status, STATUS_USAGE);
+    }
+
+    #[test_case]
+    fn pipes_feed_one_stage_into_the_next() {
+        let mut shell = new_shell();
+        assert_eq!(run_captured(&mut shell, "echo hello | grep ell"), "hello\n");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        // The middle stage prints nothing, so the last one gets empty input.
+        assert_eq!(run_captured(&mut shell, "echo hello | grep xyz | grep -n h"), "");
+        assert_eq!(shell.status, STATUS_FAILURE);
+        assert_eq!(shell.input, None);
+        run_captured(&mut shell, "echo hello |");
+        assert_eq!(shell.status, STATUS_USAGE);
+    }
+
+    #[test_case]
+    fn scripts_run_each_nonblank_line() {
+        let mut shell = new_shell();
+        shell.fs.create_file("/s", b"# two echoes\n\necho one\nnosuchcommand\n  echo two\n").unwrap();
+        assert_eq!(run_captured(&mut shell, "run s"), "one\ntwo\n");
+        assert_eq!(shell.status, STATUS_SUCCESS);
+        assert_eq!(run_captured(&mut shell, "run -e s"), "one\n");
+        assert_eq!(shell.status, STATUS_NOT_FOUND);
+
+        // A script that runs itself stops at the depth limit.
+        shell.fs.create_file("/again", b"run again\n").unwrap();
+        assert_eq!(shell.run_script("/again", false), Ok(STATUS_FAILURE));
+        assert_eq!(shell.run_script("/missing", false), Err(ScriptError::Read(FsError::NotFound)));
+    }
+
+    const CTRL_DOWN: u8 = 0x1D;
+    const CTRL_UP: u8 = 0x9D;
+
+    // Queues scancodes as if the keys had been pressed, each released at once
+    // unless it is Ctrl, which stays down until CTRL_UP.
+    fn type_scancodes(scancodes: &[u8]) {
+        for &scancode in scancodes {
+            keyboard::handle_scancode(scancode);
+            if scancode != CTRL_DOWN && scancode != CTRL_UP {
+                keyboard::handle_scancode(scancode | 0x80);
+            }
+        }
+    }
+
+    #[test_case]
+    fn ctrl_c_cancels_the_line_being_typed() {
+        const A: u8 = 0x1E;
+        const B: u8 = 0x30;
+        const C: u8 = 0x2E;
+        const ENTER: u8 = 0x1C;
+        let mut shell = new_shell();
+        type_scancodes(&[A, B, CTRL_DOWN, C, CTRL_UP, B, ENTER]);
+        assert_eq!(read_line("> ", &mut shell), None);
+        assert_eq!(shell.history.len(), 0);
+        // Typing carries on normally on the next line.
+        assert_eq!(read_line("> ", &mut shell).as_deref(), Some("b"));
+        assert!(keyboard::try_read_char().is_none());
+    }
+
+    #[test_case]
+    fn completion_extends_to_what_the_candidates_share() {
+        let candidates = [String::from("cat"), String::from("cd"), String::from("clear"), String::from("docs/")];
+        assert_eq!(complete("cl", &candidates), (vec!["clear"], String::from("clear ")));
+        assert_eq!(complete("c", &candidates), (vec!["cat", "cd", "clear"], String::from("c")));
+        assert_eq!(complete("ca", &candidates), (vec!["cat"], String::from("cat ")));
+        // A directory is left open for the next path component.
+        assert_eq!(complete("d", &candidates), (vec!["docs/"], String::from("docs/")));
+        assert_eq!(complete("x", &candidates), (Vec::new(), String::from("x")));
+        let shared = [String::from("report1"), String::from("report2")];
+        assert_eq!(complete("r", &shared), (vec!["report1", "report2"], String::from("report")));
+    }
+}
@@ -0,0 +1,286 @@
+// Starting the application processors (APs). Each AP listed in the MADT is
+// sent INIT and then two startup IPIs pointing at a trampoline in low memory,
+// which takes it from real mode straight to long mode on the kernel's page
+// tables and calls ap_main on a stack of its own. APs start one at a time,
+// since they share the trampoline's stack slot.
+
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::registers::control::{Cr0, Cr3, Cr4};
+use x86_64::registers::model_specific::Efer;
+use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::{self, Access};
+use crate::{acpi, apic, gdt, log_info, log_warn, percpu};
+
+// SIPI vectors are page numbers below 1 MiB.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+const PAGE_SIZE: u64 = 4096;
+// EFER.LMA is set by the processor and can't be written.
+const EFER_LMA: u64 = 1 << 10;
+// The AP gets 10 ms between INIT and the first SIPI and 1 ms between SIPIs,
+// more than the 200 us the MP specification asks for, then this long to call
+// in before it is given up on.
+const INIT_DELAY_MS: u32 = 10;
+const SIPI_DELAY_MS: u32 = 1;
+const STARTUP_TIMEOUT_MS: u32 = 100;
+
+// The boot processor counts as online from the start.
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+// The physical address of the trampoline page, 0 if none was reserved.
+static TRAMPOLINE: AtomicU64 = AtomicU64::new(0);
+
+// Loaded at the start of a page below 1 MiB, where an AP starts with CS set
+// to the page's segment and IP to 0. The fields at the end are filled in by
+// start_ap before each AP is started. The 16-bit code only uses offsets from
+// the start and the 64-bit code only RIP-relative addresses, so the code runs
+// wherever it is copied.
+global_asm!(
+    r#"
+.pushsection .text.ap_trampoline, "ax"
+.global ap_trampoline_start
+.global ap_trampoline_end
+.global ap_trampoline_long_mode
+.global ap_trampoline_gdt
+.global ap_trampoline_gdtr
+.global ap_trampoline_far_jump
+.global ap_trampoline_cr0
+.global ap_trampoline_cr3
+.global ap_trampoline_cr4
+.global ap_trampoline_efer
+.global ap_trampoline_stack
+.global ap_trampoline_entry
+
+.code16
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+    lgdtl (ap_trampoline_gdtr - ap_trampoline_start)
+    mov (ap_trampoline_cr4 - ap_trampoline_start), %eax
+    mov %eax, %cr4
+    mov (ap_trampoline_cr3 - ap_trampoline_start), %eax
+    mov %eax, %cr3
+    mov $0xC0000080, %ecx
+    mov (ap_trampoline_efer - ap_trampoline_start), %eax
+    xor %edx, %edx
+    wrmsr
+    // Turning on protection and paging together goes straight to long mode.
+    mov (ap_trampoline_cr0 - ap_trampoline_start), %eax
+    mov %eax, %cr0
+    ljmpl *(ap_trampoline_far_jump - ap_trampoline_start)
+
+.code64
+ap_trampoline_long_mode:
+    xor %eax, %eax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    mov ap_trampoline_stack(%rip), %rsp
+    mov ap_trampoline_entry(%rip), %rax
+    call *%rax
+2:
+    hlt
+    jmp 2b
+
+.balign 8
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00AF9B000000FFFF
+ap_trampoline_gdtr:
+    .word 15
+    .long 0
+ap_trampoline_far_jump:
+    .long 0
+    .word 8
+ap_trampoline_cr0:
+    .long 0
+ap_trampoline_cr3:
+    .long 0
+ap_trampoline_cr4:
+    .long 0
+ap_trampoline_efer:
+    .long 0
+.balign 8
+ap_trampoline_stack:
+    .quad 0
+ap_trampoline_entry:
+    .quad 0
+ap_trampoline_end:
+.popsection
+"#,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_gdtr: u8;
+    static ap_trampoline_far_jump: u8;
+    static ap_trampoline_cr0: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_cr4: u8;
+    static ap_trampoline_efer: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+}
+
+// The offset of a trampoline symbol from the trampoline's start.
+fn offset_of(symbol: &u8) -> u64 {
+    symbol as *const u8 as u64 - unsafe { &ap_trampoline_start as *const u8 as u64 }
+}
+
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::SeqCst)
+}
+
+// Takes a page below 1 MiB for the trampoline. Must run before anything else
+// allocates frames, while the low frames are still free. Returns false if the
+// first free frame is too high.
+pub fn reserve_trampoline(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> bool {
+    match frame_allocator.allocate_frame() {
+        Some(frame) if frame.start_address().as_u64() + PAGE_SIZE <= LOW_MEMORY_END => {
+            TRAMPOLINE.store(frame.start_address().as_u64(), Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+// Copies the trampoline to its page, which is also mapped at its physical
+// address so the AP can keep running there once paging is on.
+fn install_trampoline(physical_memory_offset: VirtAddr, phys: u64) -> Option<*mut u8> {
+    let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(phys));
+    memory::map_physical(frame.start_address(), VirtAddr::new(phys), PAGE_SIZE, Access::ReadExecute).ok()?;
+    let page: *mut u8 = (physical_memory_offset + phys).as_mut_ptr();
+    unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let len = offset_of(&ap_trampoline_end) as usize;
+        core::ptr::copy_nonoverlapping(start, page, len);
+    }
+    Some(page)
+}
+
+// Writes `value` `offset` bytes into the copy of the trampoline at `page`.
+unsafe fn write_field<T>(page: *mut u8, offset: u64, value: T) {
+    core::ptr::write_unaligned(page.add(offset as usize).cast::<T>(), value);
+}
+
+// Starts the AP with local APIC `apic_id` and waits for it to come online.
+fn start_ap(page: *mut u8, vector: u8, apic_id: u8) -> bool {
+    // The stack is never freed: the AP runs on it until reboot.
+    let stack = match memory::alloc_stack() {
+        Some(stack) => stack,
+        None => return false,
+    };
+    unsafe {
+        write_field(page, offset_of(&ap_trampoline_stack), stack.top().as_u64());
+        write_field(page, offset_of(&ap_trampoline_entry), ap_main as *const () as usize as u64);
+    }
+
+    let online = online_cpus();
+    apic::send_init(apic_id);
+    apic::pit_wait_ms(INIT_DELAY_MS);
+    for _ in 0..2 {
+        apic::send_startup(apic_id, vector);
+        apic::pit_wait_ms(SIPI_DELAY_MS);
+    }
+    for _ in 0..STARTUP_TIMEOUT_MS {
+        if online_cpus() > online {
+            return true;
+        }
+        apic::pit_wait_ms(1);
+    }
+    false
+}
+
+// Starts every enabled AP in the MADT, returning how many came online. Must
+// run after memory::install(), acpi::init() and apic::init(), with
+// interrupts disabled since it times the startup with the PIT.
+pub fn init(physical_memory_offset: VirtAddr) -> usize {
+    let phys = TRAMPOLINE.load(Ordering::Relaxed);
+    let madt = match acpi::madt() {
+        Some(madt) if phys != 0 && apic::is_enabled() => madt,
+        _ => return 0,
+    };
+    // The trampoline loads CR3 with a 32-bit move, before it is in long mode,
+    // so the APs can only share page tables whose PML4 is below 4 GiB.
+    let (cr3, _) = Cr3::read();
+    let pml4 = cr3.start_address().as_u64();
+    if pml4 > u64::from(u32::MAX) {
+        log_warn!("PML4 at {:#x} is above 4 GiB, not starting APs", pml4);
+        return 0;
+    }
+    let page = match install_trampoline(physical_memory_offset, phys) {
+        Some(page) => page,
+        None => return 0,
+    };
+
+    // The APs take the boot processor's control registers, apart from
+    // EFER.LMA, and so its page tables, NX and caching settings.
+    unsafe {
+        // The GDT base follows the 16-bit limit.
+        let gdt = phys + offset_of(&ap_trampoline_gdt);
+        write_field(page, offset_of(&ap_trampoline_gdtr) + 2, gdt as u32);
+        let long_mode = phys + offset_of(&ap_trampoline_long_mode);
+        write_field(page, offset_of(&ap_trampoline_far_jump), long_mode as u32);
+        write_field(page, offset_of(&ap_trampoline_cr0), Cr0::read_raw() as u32);
+        write_field(page, offset_of(&ap_trampoline_cr3), pml4 as u32);
+        write_field(page, offset_of(&ap_trampoline_cr4), Cr4::read_raw() as u32);
+        write_field(page, offset_of(&ap_trampoline_efer), (Efer::read_raw() & !EFER_LMA) as u32);
+    }
+
+    let boot_apic_id = apic::id();
+    let mut started = 0;
+    for ap in madt.local_apics.iter().filter(|apic| apic.enabled && apic.apic_id != boot_apic_id) {
+        // Startup IPIs in xAPIC mode can only name 8-bit APIC IDs.
+        if ap.apic_id > u32::from(u8::MAX) {
+            continue;
+        }
+        let apic_id = ap.apic_id as u8;
+        if start_ap(page, (phys / PAGE_SIZE) as u8, apic_id) {
+            started += 1;
+        } else {
+            log_warn!("CPU with APIC ID {} did not start", apic_id);
+        }
+    }
+    started
+}
+
+// Where each AP lands from the trampoline, on its own stack but still with
+// the trampoline's GDT and no IDT.
+extern "C" fn ap_main() -> ! {
+    gdt::init_ap();
+    crate::IDT.load();
+    apic::init_ap();
+    // APs start one at a time, so the count so far is this one's id.
+    percpu::init(online_cpus());
+    ONLINE_CPUS.fetch_add(1, Ordering::SeqCst);
+    interrupts::enable();
+    log_info!("CPU {} online.", percpu::current_cpu_id());
+    loop {
+        hlt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time;
+
+    // Under QEMU's -smp 2 the one AP is started at boot, before the tests.
+    #[test_case]
+    fn the_second_cpu_comes_online() {
+        let deadline = time::uptime_ticks() + time::ms_to_ticks(STARTUP_TIMEOUT_MS as usize);
+        while online_cpus() < 2 && time::uptime_ticks() < deadline {
+            hlt();
+        }
+        assert_eq!(online_cpus(), 2);
+        assert_eq!(online_cpus(), acpi::cpu_count());
+    }
+}
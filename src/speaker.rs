@@ -0,0 +1,72 @@
+// The PC speaker, driven by a square wave from PIT channel 2.
+
+use x86_64::instructions::port::Port;
+
+use crate::time;
+
+const PIT_CHANNEL2_PORT: u16 = 0x42;
+const PIT_COMMAND_PORT: u16 = 0x43;
+// Channel 2, lobyte/hibyte, mode 3 (square wave).
+const PIT_CHANNEL2_SQUARE_WAVE: u8 = 0xB6;
+
+// Bit 0 gates channel 2's clock and bit 1 connects its output to the speaker.
+const SPEAKER_CONTROL_PORT: u16 = 0x61;
+const SPEAKER_BITS: u8 = 0x03;
+
+// The PIT divisor for `freq_hz`, or None if the frequency can't be made with
+// a 16-bit divisor.
+fn divisor(freq_hz: u32) -> Option<u16> {
+    if freq_hz == 0 {
+        return None;
+    }
+    let divisor = time::PIT_BASE_FREQUENCY_HZ as u32 / freq_hz;
+    if divisor == 0 || divisor > u32::from(u16::MAX) {
+        return None;
+    }
+    Some(divisor as u16)
+}
+
+// Sounds a `freq_hz` tone for `ms` milliseconds, sleeping meanwhile. Returns
+// false, without a sound, if the frequency is out of range.
+pub fn beep(freq_hz: u32, ms: usize) -> bool {
+    let divisor = match divisor(freq_hz) {
+        Some(divisor) => divisor,
+        None => return false,
+    };
+    let mut control = Port::<u8>::new(SPEAKER_CONTROL_PORT);
+    unsafe {
+        Port::<u8>::new(PIT_COMMAND_PORT).write(PIT_CHANNEL2_SQUARE_WAVE);
+        let mut channel2 = Port::<u8>::new(PIT_CHANNEL2_PORT);
+        channel2.write((divisor & 0xFF) as u8);
+        channel2.write((divisor >> 8) as u8);
+    }
+    let original = unsafe { control.read() };
+    unsafe {
+        control.write(original | SPEAKER_BITS);
+    }
+    time::sleep(time::ms_to_ticks(ms));
+    // Put the speaker bits back as they were, leaving the others alone.
+    unsafe {
+        let current = control.read();
+        control.write((current & !SPEAKER_BITS) | (original & SPEAKER_BITS));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn divisor_divides_the_pit_clock() {
+        assert_eq!(time::PIT_BASE_FREQUENCY_HZ, 1_193_182);
+        assert_eq!(divisor(440), Some((1_193_182 / 440) as u16));
+        assert_eq!(divisor(1000), Some(1193));
+        // Above the PIT clock and below 19 Hz the divisor doesn't fit.
+        assert_eq!(divisor(1_193_182), Some(1));
+        assert_eq!(divisor(1_193_183), None);
+        assert_eq!(divisor(19), Some(62799));
+        assert_eq!(divisor(18), None);
+        assert_eq!(divisor(0), None);
+    }
+}
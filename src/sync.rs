@@ -0,0 +1,286 @@
+// Locks for data shared between CPUs and interrupt handlers, and blocking
+// primitives for tasks.
+
+use alloc::collections::VecDeque;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::task;
+
+// A spinlock that is handed out in the order it was asked for, so a waiter
+// can't be starved by others that keep winning the race for a plain spinlock.
+// Each locker takes the next ticket and spins until it is being served.
+pub struct TicketLock<T> {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+}
+
+impl<T> TicketLock<T> {
+    pub const fn new(data: T) -> Self {
+        TicketLock {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+        }
+        TicketLockGuard { lock: self }
+    }
+
+    // Takes the lock only if nobody holds it or is waiting for it.
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.next_ticket
+            .compare_exchange(serving, serving + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| TicketLockGuard { lock: self })
+    }
+
+    // Releases the lock on behalf of whoever holds it, such as code that
+    // panicked while holding it, and does nothing if it isn't held. The caller
+    // must make sure the holder never touches the data again.
+    #[cfg_attr(test, allow(dead_code))]
+    pub unsafe fn force_unlock(&self) {
+        if self.now_serving.load(Ordering::Relaxed) != self.next_ticket.load(Ordering::Relaxed) {
+            self.now_serving.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+struct TaskMutexState {
+    locked: bool,
+    // Ids of tasks waiting for the lock, longest waiting first.
+    waiters: VecDeque<usize>,
+}
+
+// A mutex for tasks. A task that finds it locked waits off the CPU instead
+// of spinning, and unlocking hands the lock straight to the longest waiter.
+// Not for interrupt handlers, which can't wait. Nothing in the kernel uses
+// this or Semaphore yet, only the tests.
+pub struct TaskMutex<T> {
+    state: Mutex<TaskMutexState>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for TaskMutex<T> {}
+
+pub struct TaskMutexGuard<'a, T> {
+    mutex: &'a TaskMutex<T>,
+}
+
+#[allow(dead_code)]
+impl<T> TaskMutex<T> {
+    pub const fn new(data: T) -> Self {
+        TaskMutex {
+            state: Mutex::new(TaskMutexState {
+                locked: false,
+                waiters: VecDeque::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> TaskMutexGuard<'_, T> {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            if !state.locked {
+                state.locked = true;
+                return;
+            }
+            state.waiters.push_back(task::current_task_id());
+            task::prepare_to_wait();
+            drop(state);
+            // unlock() hands over the lock before waking us.
+            task::wait();
+        });
+        TaskMutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<TaskMutexGuard<'_, T>> {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.locked {
+                return None;
+            }
+            state.locked = true;
+            Some(TaskMutexGuard { mutex: self })
+        })
+    }
+
+    fn unlock(&self) {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            // Waiters that were killed are skipped.
+            while let Some(id) = state.waiters.pop_front() {
+                if task::wake(id) {
+                    return;
+                }
+            }
+            state.locked = false;
+        })
+    }
+}
+
+impl<T> Deref for TaskMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for TaskMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for TaskMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+struct SemaphoreState {
+    permits: usize,
+    waiters: VecDeque<usize>,
+}
+
+// A counting semaphore for tasks. acquire() takes a permit, waiting off the
+// CPU while there are none; release() gives one back, straight to the
+// longest waiter if there is one. Like TaskMutex, not for interrupt handlers.
+pub struct Semaphore {
+    state: Mutex<SemaphoreState>,
+}
+
+#[allow(dead_code)]
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: Mutex::new(SemaphoreState {
+                permits,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn acquire(&self) {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.permits > 0 {
+                state.permits -= 1;
+                return;
+            }
+            state.waiters.push_back(task::current_task_id());
+            task::prepare_to_wait();
+            drop(state);
+            // release() hands over its permit before waking us.
+            task::wait();
+        })
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.permits == 0 {
+                return false;
+            }
+            state.permits -= 1;
+            true
+        })
+    }
+
+    pub fn release(&self) {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            while let Some(id) = state.waiters.pop_front() {
+                if task::wake(id) {
+                    return;
+                }
+            }
+            state.permits += 1;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use crate::task::tests::{run_until, spawn};
+
+    // Each contender queues up while the test holds the lock, and is only
+    // started once the one before it has taken its ticket.
+    #[test_case]
+    fn ticket_lock_serves_waiters_in_order() {
+        static LOCK: TicketLock<Vec<usize>> = TicketLock::new(Vec::new());
+        let guard = LOCK.lock();
+        for n in 0..3 {
+            let queued = LOCK.next_ticket.load(Ordering::SeqCst);
+            spawn(move || LOCK.lock().push(n));
+            run_until(|| LOCK.next_ticket.load(Ordering::SeqCst) > queued);
+        }
+        assert!(LOCK.try_lock().is_none());
+        drop(guard);
+        run_until(|| LOCK.try_lock().is_some_and(|order| order.len() == 3));
+        assert_eq!(*LOCK.lock(), [0, 1, 2]);
+    }
+
+    // Each task yields while holding the mutex, so the others find it
+    // locked and have to wait for it.
+    #[test_case]
+    fn task_mutex_loses_no_updates() {
+        const TASKS: usize = 3;
+        const ROUNDS: usize = 50;
+        static COUNT: TaskMutex<usize> = TaskMutex::new(0);
+        static FINISHED: AtomicUsize = AtomicUsize::new(0);
+        for _ in 0..TASKS {
+            spawn(|| {
+                for _ in 0..ROUNDS {
+                    let mut count = COUNT.lock();
+                    let seen = *count;
+                    task::yield_now();
+                    *count = seen + 1;
+                }
+                FINISHED.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        run_until(|| FINISHED.load(Ordering::SeqCst) == TASKS);
+        assert_eq!(*COUNT.lock(), TASKS * ROUNDS);
+    }
+}
@@ -0,0 +1,251 @@
+// System calls through `int 0x80`, the one way into the kernel for user-mode
+// tasks (see the usermode module). Kernel tasks may use the gate as well.
+//
+// The ABI: the call number goes in rax and up to three arguments in rdi, rsi
+// and rdx. The result comes back in rax, negative for an error; every other
+// register is preserved. The calls are:
+//
+//   0  read(fd, buf, len)   waits for a key on fd 0 and then reads what has
+//                           been typed, up to `len` bytes; returns the count
+//   1  write(fd, buf, len)  writes `len` bytes to fd 1 or 2, the console;
+//                           returns `len`
+//   2  exit(status)         ends the calling task; doesn't return, and the
+//                           status isn't kept anywhere yet
+//   3  getpid()             returns the calling task's id
+//   4  sleep(ms)            waits at least `ms` milliseconds, which must
+//                           fit in 32 bits; returns 0
+
+use alloc::string::String;
+use core::arch::{asm, global_asm};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use crate::{keyboard, memory, percpu, print, task, time};
+
+pub const VECTOR: u8 = 0x80;
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_EXIT: u64 = 2;
+pub const SYS_GETPID: u64 = 3;
+pub const SYS_SLEEP: u64 = 4;
+
+// Error results.
+pub const ERR_NO_SYSCALL: i64 = -1;
+pub const ERR_BAD_FD: i64 = -2;
+pub const ERR_BAD_ADDRESS: i64 = -3;
+pub const ERR_BAD_ARGUMENT: i64 = -4;
+
+// About 49 days.
+const MAX_SLEEP_MS: u64 = u32::MAX as u64;
+
+const STDIN: u64 = 0;
+const STDOUT: u64 = 1;
+const STDERR: u64 = 2;
+
+// RFLAGS.IF in the interrupted code's saved flags.
+const INTERRUPT_FLAG: u64 = 1 << 9;
+// The privilege level in the low bits of the caller's code selector.
+const RPL_MASK: u64 = 3;
+
+// The registers as syscall_entry leaves them on the stack, lowest address
+// first, followed by what the processor pushed on the interrupt. Only the
+// argument registers and rflags are read; the rest give the layout.
+#[allow(dead_code)]
+#[repr(C)]
+struct SyscallFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rdi: u64,
+    rsi: u64,
+    rbp: u64,
+    rbx: u64,
+    rdx: u64,
+    rcx: u64,
+    rax: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+// The processor's five words and the fifteen pushed here keep the stack
+// 16-byte aligned for the call.
+global_asm!(
+    r#"
+.global syscall_entry
+syscall_entry:
+    push rax
+    push rcx
+    push rdx
+    push rbx
+    push rbp
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rdi, rsp
+    cld
+    call {dispatch}
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rbp
+    pop rbx
+    pop rdx
+    pop rcx
+    pop rax
+    iretq
+"#,
+    dispatch = sym dispatch,
+);
+
+extern "C" {
+    fn syscall_entry();
+}
+
+// For the IDT entry.
+pub fn entry_address() -> VirtAddr {
+    VirtAddr::new(syscall_entry as *const () as usize as u64)
+}
+
+extern "C" fn dispatch(frame: &mut SyscallFrame) {
+    // Before interrupts are back on, as the timer handler will only see a
+    // kernel selector from here on.
+    percpu::restore_gs_base(frame.cs);
+    // The gate turns interrupts off; a call runs with the caller's setting,
+    // as an ordinary function call would, so it can wait.
+    if frame.rflags & INTERRUPT_FLAG != 0 {
+        interrupts::enable();
+    }
+    // User mode may only pass buffers in its own pages.
+    let user = frame.cs & RPL_MASK != 0;
+    let result = match frame.rax {
+        SYS_READ => read(frame.rdi, frame.rsi, frame.rdx, user),
+        SYS_WRITE => write(frame.rdi, frame.rsi, frame.rdx, user),
+        SYS_EXIT => task::exit(),
+        SYS_GETPID => task::current_task_id() as i64,
+        SYS_SLEEP if frame.rdi > MAX_SLEEP_MS => ERR_BAD_ARGUMENT,
+        SYS_SLEEP => {
+            time::sleep(time::ms_to_ticks(frame.rdi as usize));
+            0
+        }
+        _ => ERR_NO_SYSCALL,
+    };
+    interrupts::disable();
+    frame.rax = result as u64;
+}
+
+// The caller's buffer, if all of it is mapped with `flags`, and also
+// accessible from user mode if the call came from there.
+fn user_buffer(address: u64, len: u64, mut flags: PageTableFlags, user: bool) -> Option<&'static mut [u8]> {
+    let start = VirtAddr::try_new(address).ok()?;
+    if user {
+        flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    if address == 0 || !memory::is_range_mapped(start, len, flags) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts_mut(start.as_mut_ptr(), len as usize) })
+}
+
+fn write(fd: u64, address: u64, len: u64, user: bool) -> i64 {
+    if fd != STDOUT && fd != STDERR {
+        return ERR_BAD_FD;
+    }
+    match user_buffer(address, len, PageTableFlags::PRESENT, user) {
+        Some(bytes) => {
+            print!("{}", String::from_utf8_lossy(bytes));
+            len as i64
+        }
+        None => ERR_BAD_ADDRESS,
+    }
+}
+
+fn read(fd: u64, address: u64, len: u64, user: bool) -> i64 {
+    if fd != STDIN {
+        return ERR_BAD_FD;
+    }
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let buffer = match user_buffer(address, len, flags, user) {
+        Some(buffer) => buffer,
+        None => return ERR_BAD_ADDRESS,
+    };
+    if buffer.is_empty() {
+        return 0;
+    }
+    buffer[0] = keyboard::read_char();
+    let mut count = 1;
+    while count < buffer.len() {
+        match keyboard::try_read_char() {
+            Some(key) => buffer[count] = key,
+            None => break,
+        }
+        count += 1;
+    }
+    count as i64
+}
+
+// Makes system call `number` with `args` through the gate, for tasks.
+#[allow(dead_code)]
+pub fn syscall(number: u64, args: [u64; 3]) -> i64 {
+    let result: u64;
+    unsafe {
+        asm!(
+            "int 0x80",
+            inout("rax") number => result,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+        );
+    }
+    result as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicI64, Ordering};
+    use crate::task::tests::{run_until, spawn};
+
+    #[test_case]
+    fn getpid_returns_the_calling_task() {
+        assert_eq!(syscall(SYS_GETPID, [0; 3]), task::current_task_id() as i64);
+        static PID: AtomicI64 = AtomicI64::new(-1);
+        let id = spawn(|| PID.store(syscall(SYS_GETPID, [0; 3]), Ordering::SeqCst));
+        run_until(|| PID.load(Ordering::SeqCst) != -1);
+        assert_eq!(PID.load(Ordering::SeqCst), id as i64);
+    }
+
+    #[test_case]
+    fn bad_calls_return_errors() {
+        assert_eq!(syscall(99, [0; 3]), ERR_NO_SYSCALL);
+        let text = b"x";
+        assert_eq!(syscall(SYS_WRITE, [7, text.as_ptr() as u64, 1]), ERR_BAD_FD);
+        assert_eq!(syscall(SYS_WRITE, [STDOUT, 0, 1]), ERR_BAD_ADDRESS);
+        assert_eq!(syscall(SYS_WRITE, [STDOUT, 0x8000_0000_0000, 1]), ERR_BAD_ADDRESS);
+        assert_eq!(syscall(SYS_SLEEP, [1, 0, 0]), 0);
+        assert_eq!(syscall(SYS_SLEEP, [u64::MAX, 0, 0]), ERR_BAD_ARGUMENT);
+    }
+}
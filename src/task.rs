@@ -1,66 +1,864 @@
+use alloc::boxed::Box;
+use alloc::vec;
 use alloc::vec::Vec;
-use spin::Mutex;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+
+use crate::memory::{self, Stack};
+use crate::percpu::this_cpu;
+use crate::sync::TicketLock;
+use crate::time;
+
+// Saves the callee-saved registers of the running task on its own stack,
+// stores the resulting rsp through `old_rsp`, then loads `new_rsp` and
+// restores the registers the target task saved the same way.
+global_asm!(
+    ".global switch_context",
+    "switch_context:",
+    "push rbx",
+    "push rbp",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbp",
+    "pop rbx",
+    "ret",
+);
+
+extern "C" {
+    fn switch_context(old_rsp: *mut usize, new_rsp: usize);
+}
+
+// What a task runs, taken out of the task and called once when it first
+// runs.
+type Entry = Box<dyn FnOnce() + Send>;
+
+// First code a new task runs, returned to by switch_context. Every switch
+// happens with interrupts disabled and the scheduler lock released, so the
+// entry can be fetched before interrupts are turned back on for the task's
+// own code.
+extern "C" fn task_start() -> ! {
+    let entry = SCHEDULER.lock().take_current_entry();
+    interrupts::enable();
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit();
+}
+
+// task_start's return address, should it ever return.
+extern "C" fn task_exit() -> ! {
+    exit();
+}
+
+// rbx, rbp, r12-r15, in the order switch_context pops them.
+const SAVED_REGISTERS: usize = 6;
+
+// A new task's stack (see memory::alloc_stack), from the page-aligned top
+// down:
+//
+//   top - 8    task_exit, a return address for task_start, which never returns
+//   top - 16   task_start, popped by switch_context's `ret`
+//   top - 64   the six saved registers, r15 lowest, all 0
+//
+// The initial stack pointer (top - 64) is 16-byte aligned, and after the
+// `ret` rsp is top - 8, which is what the System V ABI expects on entry to
+// task_start.
+const INITIAL_FRAME_WORDS: usize = SAVED_REGISTERS + 2;
+
+pub const DEFAULT_PRIORITY: u8 = 8;
+
+// Each time a task is picked its pass advances by STRIDE_BASE / (priority + 1),
+// and the task with the lowest pass runs next, so CPU share is proportional to
+// priority + 1 and even priority 0 tasks are never starved.
+const STRIDE_BASE: usize = 1 << 20;
+
+// About 10 ms at time::PIT_FREQUENCY_HZ; changed with the timeslice setting.
+const DEFAULT_TIME_SLICE_TICKS: usize = 10;
+
+static TIME_SLICE_TICKS: AtomicUsize = AtomicUsize::new(DEFAULT_TIME_SLICE_TICKS);
+
+// Timer ticks between preemptions.
+pub fn time_slice() -> usize {
+    TIME_SLICE_TICKS.load(Ordering::Relaxed)
+}
+
+// `ticks` must be at least 1.
+pub fn set_time_slice(ticks: usize) {
+    TIME_SLICE_TICKS.store(ticks, Ordering::Relaxed);
+}
+
+// Id 0 is reserved for the bootstrap (kernel) task.
+static NEXT_TASK_ID: AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Running,
+    Ready,
+    // Not scheduled until TIMER_TICKS reaches `wake_tick`.
+    Blocked { wake_tick: usize },
+    // Not scheduled until wake() is called for it.
+    Waiting,
+    // Returned from its entry, called exit() or was killed. Removed from the
+    // run queue, and its stack freed, by reap() once it has been switched
+    // away from.
+    Exited,
+}
+
+impl TaskState {
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskState::Running => "running",
+            TaskState::Ready => "ready",
+            TaskState::Blocked { .. } => "blocked",
+            TaskState::Waiting => "waiting",
+            TaskState::Exited => "exited",
+        }
+    }
+}
 
 pub struct Task {
     id: usize,
-    stack: Vec<u8>,
+    state: TaskState,
+    priority: u8,
+    pass: usize,
+    // None for the bootstrap task, which runs on the boot stack.
+    stack: Option<Stack>,
     stack_pointer: usize,
+    // None once the task has started, and for the bootstrap task. A task
+    // killed before it starts drops its entry unrun.
+    entry: Option<Entry>,
+    // Timer ticks spent running, up to the last switch away from it.
+    cpu_ticks: usize,
 }
 
 impl Task {
     pub fn new(entry_point: fn()) -> Self {
-        let mut stack = Vec::with_capacity(4096);
-        let stack_pointer = unsafe {
-            let sp = stack.as_mut_ptr().add(4096);
-            (sp as *mut usize).write(entry_point as usize);
-            sp as usize
-        };
+        Task::with_priority(entry_point, DEFAULT_PRIORITY)
+    }
+
+    // Higher priorities get a larger share of the CPU.
+    pub fn with_priority(entry_point: fn(), priority: u8) -> Self {
+        Task::with_entry(Box::new(entry_point), priority)
+    }
+
+    fn with_entry(entry: Entry, priority: u8) -> Self {
+        let stack = memory::alloc_stack().expect("out of memory for task stack");
+        let stack_pointer = stack.top().as_u64() as usize - INITIAL_FRAME_WORDS * 8;
+        let frame = stack_pointer as *mut usize;
+        unsafe {
+            for i in 0..INITIAL_FRAME_WORDS {
+                frame.add(i).write(0);
+            }
+            frame.add(SAVED_REGISTERS).write(task_start as *const () as usize);
+            frame.add(SAVED_REGISTERS + 1).write(task_exit as *const () as usize);
+        }
 
         Task {
-            id: 0,
-            stack,
+            id: NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed),
+            state: TaskState::Ready,
+            priority,
+            pass: 0,
+            stack: Some(stack),
             stack_pointer,
+            entry: Some(entry),
+            cpu_ticks: 0,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn state(&self) -> TaskState {
+        self.state
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    // Timer ticks spent running, not counting the current stretch if the
+    // task is running now.
+    pub fn cpu_ticks(&self) -> usize {
+        self.cpu_ticks
+    }
+
+    fn stride(&self) -> usize {
+        STRIDE_BASE / (usize::from(self.priority) + 1)
+    }
+
+    // The context that is already running when the scheduler starts, i.e.
+    // the kernel on its boot stack. Its stack pointer is saved on the first
+    // switch away from it.
+    fn bootstrap() -> Self {
+        Task {
+            id: 0,
+            state: TaskState::Running,
+            priority: DEFAULT_PRIORITY,
+            pass: 0,
+            stack: None,
+            stack_pointer: 0,
+            entry: None,
+            cpu_ticks: 0,
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some(stack) = self.stack.take() {
+            memory::free_stack(stack);
         }
     }
 }
 
+// A snapshot of one task, for listing.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo {
+    pub id: usize,
+    pub state: TaskState,
+    pub priority: u8,
+    // Including the current stretch if the task is running.
+    pub cpu_ticks: usize,
+}
+
+// One run queue shared by every CPU. Which task each CPU is running is kept
+// in its per-CPU block, and a task running on one CPU is never picked by
+// another.
 pub struct Scheduler {
     tasks: Vec<Task>,
-    current_task: usize,
+    // When tasks were first switched, which is when CPU time accounting
+    // starts. None before the first switch: until then the bootstrap task is
+    // all there is. Each CPU keeps its own last switch tick.
+    first_switch_tick: Option<usize>,
 }
 
 impl Scheduler {
     pub fn new() -> Self {
         Scheduler {
-            tasks: Vec::new(),
-            current_task: 0,
+            tasks: vec![Task::bootstrap()],
+            first_switch_tick: None,
         }
     }
 
-    pub fn add_task(&mut self, task: Task) {
+    // Ticks spent by this CPU's current task since its last switch.
+    fn running_for(&self, now: usize) -> usize {
+        this_cpu().last_switch_tick().map_or(0, |last| now.saturating_sub(last))
+    }
+
+    // Ticks accounted to tasks since accounting started, including ones that
+    // have since exited, so each task's cpu_ticks over this is its share.
+    pub fn total_ticks(&self) -> usize {
+        self.first_switch_tick.map_or(0, |first| time::uptime_ticks().saturating_sub(first))
+    }
+
+    // The index of the task running on this CPU.
+    fn current(&self) -> usize {
+        let id = this_cpu().current_task();
+        self.tasks
+            .iter()
+            .position(|task| task.id() == id)
+            .expect("current task is not in the run queue")
+    }
+
+    // The new task starts level with the others rather than at pass 0, which
+    // would let it run until it caught up.
+    pub fn add_task(&mut self, mut task: Task) {
+        task.pass = self.tasks.iter().map(|t| t.pass).min().unwrap_or(0);
         self.tasks.push(task);
     }
 
-    pub fn run_next_task(&mut self) {
-        if self.tasks.is_empty() {
-            return;
+    // Starts a task running `f`, returning its id. The closure is freed once
+    // it returns, or with the task if it is killed before it runs.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&mut self, f: F) -> usize {
+        let task = Task::with_entry(Box::new(f), DEFAULT_PRIORITY);
+        let id = task.id();
+        self.add_task(task);
+        id
+    }
+
+    fn take_current_entry(&mut self) -> Option<Entry> {
+        let current = self.current();
+        self.tasks[current].entry.take()
+    }
+
+    pub fn set_priority(&mut self, id: usize, priority: u8) -> bool {
+        match self.tasks.iter_mut().find(|task| task.id() == id) {
+            Some(task) => {
+                task.priority = priority;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Ends the task; reap() releases its stack for reuse. A running task
+    // can't be killed since its stack is in use. The heap lock is only held
+    // with interrupts off, so a killed task can't have been preempted while
+    // holding it.
+    pub fn kill(&mut self, id: usize) -> bool {
+        let current = self.current();
+        match self.tasks.iter().position(|task| task.id() == id) {
+            Some(index)
+                if index != current
+                    && !matches!(self.tasks[index].state, TaskState::Running | TaskState::Exited) =>
+            {
+                self.tasks[index].state = TaskState::Exited;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn current_task_id(&self) -> usize {
+        this_cpu().current_task()
+    }
+
+    // Tasks that have exited are left out, even before they are reaped.
+    fn live_tasks(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.iter().filter(|task| task.state != TaskState::Exited)
+    }
+
+    pub fn task_ids(&self) -> Vec<usize> {
+        self.live_tasks().map(Task::id).collect()
+    }
+
+    pub fn task_states(&self) -> Vec<TaskInfo> {
+        let current = this_cpu().current_task();
+        let running_for = self.running_for(time::uptime_ticks());
+        self.live_tasks()
+            .map(|task| TaskInfo {
+                id: task.id(),
+                state: task.state(),
+                priority: task.priority(),
+                cpu_ticks: task.cpu_ticks() + if task.id() == current { running_for } else { 0 },
+            })
+            .collect()
+    }
+
+    pub fn block_current(&mut self, wake_tick: usize) {
+        let current = self.current();
+        self.tasks[current].state = TaskState::Blocked { wake_tick };
+    }
+
+    fn wait_current(&mut self) {
+        let current = self.current();
+        self.tasks[current].state = TaskState::Waiting;
+    }
+
+    // Makes a waiting task runnable. Returns false if there is no such task,
+    // for instance because it was killed while waiting.
+    fn wake(&mut self, id: usize) -> bool {
+        match self.tasks.iter_mut().find(|task| task.id() == id && task.state != TaskState::Exited) {
+            Some(task) => {
+                if task.state == TaskState::Waiting {
+                    task.state = TaskState::Ready;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn exit_current(&mut self) {
+        let current = self.current();
+        self.tasks[current].state = TaskState::Exited;
+    }
+
+    // Takes out an exited task other than the one this CPU is leaving, which
+    // is still on its stack. The caller drops it.
+    fn take_exited(&mut self) -> Option<Task> {
+        let current = this_cpu().current_task();
+        let index = self
+            .tasks
+            .iter()
+            .position(|task| task.state == TaskState::Exited && task.id() != current)?;
+        Some(self.tasks.remove(index))
+    }
+
+    fn current_is_running(&self) -> bool {
+        self.tasks[self.current()].state == TaskState::Running
+    }
+
+    // Wakes blocked tasks whose time has come, then returns the index of the
+    // runnable task with the lowest pass, or None if every task is blocked.
+    // Tasks are scanned starting after the current one and ties go to the
+    // first seen, which gives round-robin among tasks of equal priority.
+    fn pick_next(&mut self, current: usize, now: usize) -> Option<usize> {
+        let len = self.tasks.len();
+        let mut best: Option<usize> = None;
+        for offset in 1..=len {
+            let index = (current + offset) % len;
+            let task = &mut self.tasks[index];
+            // Waiting, exited, or running on another CPU.
+            let runnable = match task.state {
+                TaskState::Waiting | TaskState::Exited => false,
+                TaskState::Running => index == current,
+                _ => true,
+            };
+            if !runnable {
+                continue;
+            }
+            if let TaskState::Blocked { wake_tick } = task.state {
+                if wake_tick > now {
+                    continue;
+                }
+                task.state = TaskState::Ready;
+            }
+            let pass = task.pass;
+            if best.is_none_or(|best| pass < self.tasks[best].pass) {
+                best = Some(index);
+            }
+        }
+        best
+    }
+
+    // Picks the next task and returns where to save the current rsp and the
+    // rsp to switch to, or None if the current task should keep running or
+    // nothing is runnable. The scheduler lock must be released before
+    // switching.
+    fn next_switch(&mut self, now: usize) -> Option<(*mut usize, usize)> {
+        let current = self.current();
+        let next = self.pick_next(current, now)?;
+        let stride = self.tasks[next].stride();
+        self.tasks[next].pass += stride;
+        self.tasks[next].state = TaskState::Running;
+        if next == current {
+            return None;
+        }
+
+        let next_id = self.tasks[next].id();
+        let new_rsp = self.tasks[next].stack_pointer;
+        let ran_for = self.running_for(now);
+        this_cpu().set_last_switch_tick(now);
+        self.first_switch_tick.get_or_insert(now);
+        let current = &mut self.tasks[current];
+        current.cpu_ticks += ran_for;
+        if current.state == TaskState::Running {
+            current.state = TaskState::Ready;
+        }
+        let old_rsp = &mut current.stack_pointer as *mut usize;
+        this_cpu().set_current_task(next_id);
+        Some((old_rsp, new_rsp))
+    }
+}
+
+// Returns false if there was no other task to switch to.
+pub fn run_next_task() -> bool {
+    // The timer interrupt also switches tasks, so it must not fire while the
+    // scheduler lock is held or halfway through a switch.
+    interrupts::without_interrupts(|| {
+        let switch = SCHEDULER.lock().next_switch(time::uptime_ticks());
+        match switch {
+            Some((old_rsp, new_rsp)) => {
+                unsafe {
+                    switch_context(old_rsp, new_rsp);
+                }
+                true
+            }
+            None => false,
         }
+    })
+}
 
-        self.current_task = (self.current_task + 1) % self.tasks.len();
-        let next_task = &mut self.tasks[self.current_task];
+// Gives up the CPU to the next runnable task, returning once the scheduler
+// picks the caller again. Returns immediately if no other task is runnable.
+pub fn yield_now() {
+    reap();
+    run_next_task();
+}
+
+// Frees the tasks that have exited, returning their stacks for reuse.
+pub fn reap() {
+    while let Some(task) = interrupts::without_interrupts(|| SCHEDULER.lock().take_exited()) {
+        drop(task);
+    }
+}
+
+// Blocks the calling task until TIMER_TICKS reaches `wake_tick`. Other tasks
+// run in the meantime; if none is runnable the CPU halts until the next
+// interrupt.
+pub fn sleep_until(wake_tick: usize) {
+    reap();
+    interrupts::without_interrupts(|| {
+        SCHEDULER.lock().block_current(wake_tick);
+        switch_until_running();
+    })
+}
+
+// Runs other tasks until the scheduler makes the blocked or waiting caller
+// run again. Interrupts must be disabled.
+fn switch_until_running() {
+    loop {
+        let switch = SCHEDULER.lock().next_switch(time::uptime_ticks());
+        if let Some((old_rsp, new_rsp)) = switch {
+            unsafe {
+                switch_context(old_rsp, new_rsp);
+            }
+        }
+        // Either another task switched back to us after waking us, or
+        // next_switch found we could run again and picked us.
+        if SCHEDULER.lock().current_is_running() {
+            break;
+        }
+        interrupts::enable_and_hlt();
+        interrupts::disable();
+    }
+}
 
+pub fn current_task_id() -> usize {
+    this_cpu().current_task()
+}
+
+// Waiting comes in two steps so a wakeup can't be lost. Under the lock that
+// guards the condition, with interrupts disabled, the caller records its id
+// wherever its waker looks and calls prepare_to_wait(). It then releases
+// that lock and calls wait(), which blocks until wake() is called for the
+// task, or returns straight away if that already happened.
+pub fn prepare_to_wait() {
+    SCHEDULER.lock().wait_current();
+}
+
+pub fn wait() {
+    switch_until_running();
+}
+
+// Returns false if there is no task `id`.
+pub fn wake(id: usize) -> bool {
+    interrupts::without_interrupts(|| SCHEDULER.lock().wake(id))
+}
+
+// Ends the calling task, switching to another and never returning. Until
+// another task is runnable the CPU halts between interrupts.
+pub fn exit() -> ! {
+    interrupts::disable();
+    SCHEDULER.lock().exit_current();
+    loop {
+        let switch = SCHEDULER.lock().next_switch(time::uptime_ticks());
+        if let Some((old_rsp, new_rsp)) = switch {
+            unsafe {
+                switch_context(old_rsp, new_rsp);
+            }
+        }
+        interrupts::enable_and_hlt();
+        interrupts::disable();
+    }
+}
+
+// Called from the timer interrupt. If the interrupted code holds the
+// scheduler lock this tick is skipped rather than deadlocking.
+pub fn preempt() {
+    let switch = match SCHEDULER.try_lock() {
+        Some(mut scheduler) => scheduler.next_switch(time::uptime_ticks()),
+        None => return,
+    };
+    if let Some((old_rsp, new_rsp)) = switch {
         unsafe {
-            asm!(
-                "mov rsp, {}",
-                "ret",
-                in(reg) next_task.stack_pointer,
-                options(preserves_flags)
-            );
+            switch_context(old_rsp, new_rsp);
         }
     }
 }
 
 lazy_static! {
-    pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+    pub static ref SCHEDULER: TicketLock<Scheduler> = TicketLock::new(Scheduler::new());
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use spin::Mutex;
+
+    // Five seconds at PIT_FREQUENCY_HZ.
+    const TIMEOUT_TICKS: usize = 5000;
+
+    // Lets other tasks run until `done` holds, failing the test if that takes
+    // more than TIMEOUT_TICKS.
+    pub fn run_until(done: impl Fn() -> bool) {
+        let deadline = time::uptime_ticks() + TIMEOUT_TICKS;
+        while !done() {
+            assert!(time::uptime_ticks() < deadline, "timed out waiting for other tasks");
+            time::sleep(1);
+        }
+    }
+
+    pub fn spawn<F: FnOnce() + Send + 'static>(f: F) -> usize {
+        interrupts::without_interrupts(|| SCHEDULER.lock().spawn(f))
+    }
+
+    #[test_case]
+    fn yielding_tasks_take_turns() {
+        const ROUNDS: usize = 50;
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for task in [1u8, 2] {
+            let log = log.clone();
+            spawn(move || {
+                for _ in 0..ROUNDS {
+                    interrupts::without_interrupts(|| log.lock().push(task));
+                    yield_now();
+                }
+            });
+        }
+        run_until(|| interrupts::without_interrupts(|| log.lock().len()) == 2 * ROUNDS);
+        let log = log.lock();
+        assert_eq!(log.iter().filter(|&&task| task == 1).count(), ROUNDS);
+        // Neither ran all its rounds before the other started.
+        assert!(log.windows(2).filter(|pair| pair[0] != pair[1]).count() > 1);
+    }
+
+    fn idle() {}
+
+    #[test_case]
+    fn task_ids_are_distinct_and_increasing() {
+        let tasks = [Task::new(idle), Task::new(idle), Task::new(idle)];
+        assert!(tasks.iter().all(|task| task.id() != 0));
+        assert!(tasks.windows(2).all(|pair| pair[0].id() < pair[1].id()));
+    }
+
+    // Tasks added to a scheduler of its own, which is never switched to.
+    fn scheduler_with(count: usize) -> (Scheduler, Vec<usize>) {
+        let mut scheduler = Scheduler::new();
+        let mut ids = Vec::new();
+        for _ in 0..count {
+            let task = Task::new(idle);
+            ids.push(task.id());
+            scheduler.add_task(task);
+        }
+        (scheduler, ids)
+    }
+
+    #[test_case]
+    fn task_ids_lists_every_task() {
+        let (scheduler, ids) = scheduler_with(2);
+        assert_eq!(scheduler.task_ids(), [0, ids[0], ids[1]]);
+        let states = scheduler.task_states();
+        assert_eq!(states[0].state, TaskState::Running);
+        assert!(states[1..].iter().all(|info| info.state == TaskState::Ready));
+    }
+
+    #[test_case]
+    fn killing_the_middle_task_leaves_the_others() {
+        let (mut scheduler, ids) = scheduler_with(3);
+        assert!(scheduler.kill(ids[1]));
+        assert_eq!(scheduler.task_ids(), [0, ids[0], ids[2]]);
+        assert!(!scheduler.kill(ids[1]));
+        // The running task can't be killed.
+        assert!(!scheduler.kill(0));
+        assert_eq!(scheduler.take_exited().map(|task| task.id()), Some(ids[1]));
+        assert!(scheduler.take_exited().is_none());
+        assert_eq!(scheduler.tasks.len(), 3);
+    }
+
+    // The ids of the next `picks` tasks the scheduler would run, charging
+    // each pick the way next_switch does, without switching to any of them.
+    // The bootstrap task is set waiting so only the added tasks compete.
+    fn simulate_picks(scheduler: &mut Scheduler, picks: usize) -> Vec<usize> {
+        scheduler.tasks[0].state = TaskState::Waiting;
+        let mut current = 0;
+        let mut picked = Vec::new();
+        for _ in 0..picks {
+            current = scheduler.pick_next(current, time::uptime_ticks()).unwrap();
+            let stride = scheduler.tasks[current].stride();
+            scheduler.tasks[current].pass += stride;
+            picked.push(scheduler.tasks[current].id());
+        }
+        picked
+    }
+
+    #[test_case]
+    fn higher_priority_tasks_run_more_often() {
+        let (mut scheduler, ids) = scheduler_with(2);
+        scheduler.set_priority(ids[0], 15);
+        scheduler.set_priority(ids[1], 3);
+        let picked = simulate_picks(&mut scheduler, 200);
+        let high = picked.iter().filter(|&&id| id == ids[0]).count();
+        // Shares of 16 to 4.
+        assert!((150..=170).contains(&high));
+    }
+
+    #[test_case]
+    fn equal_priority_tasks_take_turns() {
+        let (mut scheduler, ids) = scheduler_with(3);
+        let picked = simulate_picks(&mut scheduler, 9);
+        for round in picked.chunks(3) {
+            let mut round = Vec::from(round);
+            round.sort();
+            assert_eq!(round, ids);
+        }
+    }
+
+    #[test_case]
+    fn blocked_tasks_wait_for_their_wake_tick() {
+        let (mut scheduler, ids) = scheduler_with(2);
+        scheduler.tasks[0].state = TaskState::Waiting;
+        let now = time::uptime_ticks();
+        scheduler.tasks[1].state = TaskState::Blocked { wake_tick: now + 100 };
+        for _ in 0..5 {
+            let next = scheduler.pick_next(0, now + 99).unwrap();
+            assert_eq!(scheduler.tasks[next].id(), ids[1]);
+            let stride = scheduler.tasks[next].stride();
+            scheduler.tasks[next].pass += stride;
+        }
+        assert!(matches!(scheduler.tasks[1].state, TaskState::Blocked { .. }));
+        // Behind on pass, so it runs as soon as it wakes.
+        let next = scheduler.pick_next(0, now + 100).unwrap();
+        assert_eq!(scheduler.tasks[next].id(), ids[0]);
+        assert_eq!(scheduler.tasks[1].state, TaskState::Ready);
+    }
+
+    #[test_case]
+    fn nothing_is_picked_while_every_task_is_blocked() {
+        let (mut scheduler, _) = scheduler_with(1);
+        scheduler.tasks[0].state = TaskState::Waiting;
+        let now = time::uptime_ticks();
+        scheduler.tasks[1].state = TaskState::Blocked { wake_tick: now + 10 };
+        assert_eq!(scheduler.pick_next(0, now), None);
+        assert_eq!(scheduler.pick_next(0, now + 10), Some(1));
+    }
+
+    #[test_case]
+    fn new_stacks_are_aligned_and_in_bounds() {
+        for _ in 0..3 {
+            let task = Task::new(idle);
+            let stack = task.stack.as_ref().unwrap();
+            let (bottom, top) = (stack.bottom().as_u64() as usize, stack.top().as_u64() as usize);
+            assert_eq!(task.stack_pointer % 16, 0);
+            assert!(bottom <= task.stack_pointer && task.stack_pointer + INITIAL_FRAME_WORDS * 8 == top);
+            let frame = task.stack_pointer as *const usize;
+            unsafe {
+                assert_eq!(*frame.add(SAVED_REGISTERS), task_start as *const () as usize);
+                assert_eq!(*frame.add(SAVED_REGISTERS + 1), task_exit as *const () as usize);
+            }
+            // After switch_context's `ret`, as on entry to any function.
+            assert_eq!((task.stack_pointer + (SAVED_REGISTERS + 1) * 8) % 16, 8);
+        }
+    }
+
+    #[test_case]
+    fn yield_now_passes_a_counter_back_and_forth() {
+        const ROUNDS: usize = 20;
+        let counter = Arc::new(AtomicUsize::new(0));
+        for parity in 0..2 {
+            let counter = counter.clone();
+            spawn(move || {
+                for _ in 0..ROUNDS {
+                    // Wait for this task's turn, then hand it to the other.
+                    while counter.load(Ordering::SeqCst) % 2 != parity {
+                        yield_now();
+                    }
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+        run_until(|| counter.load(Ordering::SeqCst) == 2 * ROUNDS);
+    }
+
+    #[test_case]
+    fn spawned_closures_run_once_and_are_freed() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let captured = counter.clone();
+        spawn(move || {
+            captured.fetch_add(1, Ordering::SeqCst);
+        });
+        // The task drops the closure, and its clone of the Arc, once it returns.
+        run_until(|| Arc::strong_count(&counter) == 1);
+        time::sleep(5);
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[test_case]
+    fn returning_tasks_are_reaped() {
+        use core::sync::atomic::AtomicBool;
+        static RETURNED: AtomicBool = AtomicBool::new(false);
+        let id = spawn(|| RETURNED.store(true, Ordering::SeqCst));
+        let present = || interrupts::without_interrupts(|| SCHEDULER.lock().tasks.iter().any(|task| task.id() == id));
+        assert!(present());
+        run_until(|| RETURNED.load(Ordering::SeqCst));
+        // Sleeping reaps, so the exited task is soon dropped altogether.
+        run_until(|| !present());
+        assert!(!interrupts::without_interrupts(|| SCHEDULER.lock().task_ids()).contains(&id));
+    }
+
+    // The task is preempted somewhere in its loop, and killing it must not
+    // leave the heap locked.
+    #[test_case]
+    fn killing_a_task_that_allocates_leaves_the_heap_usable() {
+        static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+        let id = spawn(|| loop {
+            let block = alloc::boxed::Box::new([0u8; 64]);
+            core::hint::black_box(&block);
+            ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        });
+        run_until(|| ALLOCATIONS.load(Ordering::SeqCst) > 1000);
+        run_until(|| interrupts::without_interrupts(|| SCHEDULER.lock().kill(id)));
+        let after = alloc::vec![1u8; 4096];
+        assert_eq!(after.iter().map(|&b| usize::from(b)).sum::<usize>(), 4096);
+    }
+
+    fn cpu_ticks_of(id: usize) -> usize {
+        let states = interrupts::without_interrupts(|| SCHEDULER.lock().task_states());
+        states.iter().find(|info| info.id == id).map_or(0, |info| info.cpu_ticks)
+    }
+
+    #[test_case]
+    fn cpu_time_goes_to_the_task_that_ran() {
+        use core::sync::atomic::AtomicBool;
+        static STOP: AtomicBool = AtomicBool::new(false);
+        let busy = spawn(|| {
+            while !STOP.load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+        });
+        let me = current_task_id();
+        let (busy_before, me_before) = (cpu_ticks_of(busy), cpu_ticks_of(me));
+        // Other tasks may take some of the time, but the sleeper hardly any.
+        time::sleep(50);
+        let (busy_ran, me_ran) = (cpu_ticks_of(busy) - busy_before, cpu_ticks_of(me) - me_before);
+        STOP.store(true, Ordering::SeqCst);
+        assert!(busy_ran >= 25);
+        assert!(me_ran < 5);
+    }
+
+    // Every task's ticks, including tasks that exited but aren't reaped yet,
+    // and the ticks elapsed since accounting started.
+    fn accounted_ticks() -> (usize, usize) {
+        interrupts::without_interrupts(|| {
+            let scheduler = SCHEDULER.lock();
+            let charged: usize = scheduler.tasks.iter().map(Task::cpu_ticks).sum();
+            (charged + scheduler.running_for(time::uptime_ticks()), scheduler.total_ticks())
+        })
+    }
+
+    // Waiting here spins rather than sleeps, since sleeping reaps and would
+    // take exited tasks' ticks out of the sum.
+    #[test_case]
+    fn task_ticks_add_up_to_the_elapsed_ticks() {
+        use core::sync::atomic::AtomicBool;
+        static STOP: AtomicBool = AtomicBool::new(false);
+        reap();
+        let (charged_before, total_before) = accounted_ticks();
+        spawn(|| {
+            while !STOP.load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+        });
+        let end = time::uptime_ticks() + 20;
+        while time::uptime_ticks() < end {
+            core::hint::spin_loop();
+        }
+        let (charged_after, total_after) = accounted_ticks();
+        STOP.store(true, Ordering::SeqCst);
+        assert!(total_after >= total_before + 20);
+        assert_eq!(charged_after - charged_before, total_after - total_before);
+    }
 }
@@ -0,0 +1,172 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
+
+use crate::{apic, pic, task};
+use crate::{InterruptIndex, TIMER_TICKS};
+
+pub const PIT_FREQUENCY_HZ: usize = 1000;
+pub const PIT_BASE_FREQUENCY_HZ: usize = 1_193_182;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerSource {
+    Pit,
+    LocalApic,
+}
+
+static APIC_TIMER_ACTIVE: AtomicBool = AtomicBool::new(false);
+static TIMER_FREQUENCY_HZ: AtomicUsize = AtomicUsize::new(0);
+
+pub fn timer_source() -> TimerSource {
+    if APIC_TIMER_ACTIVE.load(Ordering::Relaxed) {
+        TimerSource::LocalApic
+    } else {
+        TimerSource::Pit
+    }
+}
+
+// The rate the timer source was actually programmed to, which is as close
+// to PIT_FREQUENCY_HZ as its divisor allows. Zero before `init`.
+pub fn timer_frequency_hz() -> usize {
+    TIMER_FREQUENCY_HZ.load(Ordering::Relaxed)
+}
+
+// Drives TIMER_TICKS from the local APIC timer when the CPU has one, and
+// from the PIT otherwise. Either way ticks arrive at about PIT_FREQUENCY_HZ.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    let mut frequency = init_pit();
+    if apic::init(mapper, frame_allocator) {
+        frequency = apic::start_timer(PIT_FREQUENCY_HZ as u32) as usize;
+        pic::disable_irq(InterruptIndex::Timer);
+        APIC_TIMER_ACTIVE.store(true, Ordering::Relaxed);
+    }
+    TIMER_FREQUENCY_HZ.store(frequency, Ordering::Relaxed);
+}
+
+// Program PIT channel 0 as a rate generator at PIT_FREQUENCY_HZ, returning
+// the rate the divisor really gives.
+fn init_pit() -> usize {
+    let divisor = (PIT_BASE_FREQUENCY_HZ / PIT_FREQUENCY_HZ) as u16;
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel0 = Port::<u8>::new(0x40);
+    unsafe {
+        command.write(0x36); // channel 0, lobyte/hibyte, mode 3
+        channel0.write((divisor & 0xFF) as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+    PIT_BASE_FREQUENCY_HZ / divisor as usize
+}
+
+pub fn uptime_ticks() -> usize {
+    TIMER_TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ms() -> usize {
+    uptime_ticks() * 1000 / PIT_FREQUENCY_HZ
+}
+
+// Saturates rather than overflowing, as does sleep(), so absurdly long
+// waits just never end.
+pub fn ms_to_ticks(ms: usize) -> usize {
+    ms.saturating_mul(PIT_FREQUENCY_HZ).div_ceil(1000)
+}
+
+// Blocks the calling task for at least `ticks` timer interrupts, letting
+// other tasks run in the meantime.
+pub fn sleep(ticks: usize) {
+    task::sleep_until(uptime_ticks().saturating_add(ticks));
+}
+
+// Reads the timestamp counter. The lfence keeps earlier instructions from
+// still being in flight when the counter is sampled.
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        core::arch::asm!(
+            "lfence",
+            "rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack),
+        );
+    }
+    (u64::from(high) << 32) | u64::from(low)
+}
+
+// Like rdtsc, but rdtscp itself waits for earlier instructions, and also
+// returns IA32_TSC_AUX (which the OS may set to the CPU number). Not every
+// CPU has it, so nothing relies on it yet.
+#[allow(dead_code)]
+pub fn rdtscp() -> (u64, u32) {
+    let (low, high, aux): (u32, u32, u32);
+    unsafe {
+        core::arch::asm!(
+            "rdtscp",
+            out("eax") low,
+            out("edx") high,
+            out("ecx") aux,
+            options(nomem, nostack),
+        );
+    }
+    ((u64::from(high) << 32) | u64::from(low), aux)
+}
+
+// Runs `f`, returning its result and roughly how many TSC cycles it took.
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, u64) {
+    let start = rdtsc();
+    let result = f();
+    let end = rdtsc();
+    (result, end.wrapping_sub(start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn sleep_waits_at_least_the_requested_ticks() {
+        for ticks in [1, 10, 25] {
+            let start = uptime_ticks();
+            sleep(ticks);
+            assert!(uptime_ticks() - start >= ticks);
+        }
+    }
+
+    #[test_case]
+    fn long_durations_saturate_instead_of_overflowing() {
+        assert_eq!(ms_to_ticks(1000), PIT_FREQUENCY_HZ);
+        assert_eq!(ms_to_ticks(1), 1);
+        assert_eq!(ms_to_ticks(usize::MAX), usize::MAX.div_ceil(1000));
+    }
+
+    #[test_case]
+    fn the_chosen_timer_ticks_at_about_the_nominal_rate() {
+        let frequency = timer_frequency_hz();
+        assert!(frequency > PIT_FREQUENCY_HZ * 9 / 10 && frequency < PIT_FREQUENCY_HZ * 11 / 10);
+        if timer_source() == TimerSource::LocalApic {
+            // The PIT still runs, but its IRQ must be masked so ticks aren't
+            // counted twice.
+            let mask = unsafe { Port::<u8>::new(0x21).read() };
+            assert!(mask & 1 != 0);
+        }
+        let start = uptime_ticks();
+        sleep(5);
+        assert!(uptime_ticks() > start);
+    }
+
+    #[test_case]
+    fn the_timestamp_counter_only_moves_forward() {
+        let (sum, cycles) = measure(|| (0..1000u64).map(core::hint::black_box).sum::<u64>());
+        assert_eq!(sum, 499_500);
+        assert!(cycles > 0);
+        let mut previous = rdtsc();
+        for _ in 0..100 {
+            let now = rdtsc();
+            assert!(now > previous);
+            previous = now;
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Running code in ring 3. A user task is an ordinary task that maps a
+// program and a stack with user access and then drops to ring 3 with iretq,
+// after which it can only reach the kernel through interrupts and system
+// calls. Interrupts from ring 3 arrive on the TSS's single rsp0 stack, so
+// only one user task may exist at a time.
+//
+// User code may load GS; the kernel puts its own GS base back on every entry
+// from ring 3 (see percpu::restore_gs_base).
+
+use core::arch::{asm, global_asm};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::VirtAddr;
+
+use crate::gdt;
+use crate::memory::{self, Access};
+use crate::task::SCHEDULER;
+
+// Well away from the kernel, its heap and its task stacks.
+const USER_CODE_START: u64 = 0x_2222_0000_0000;
+const USER_CODE_LEN: u64 = 4096;
+const USER_STACK_TOP: u64 = 0x_2222_0010_0000;
+const USER_STACK_LEN: u64 = 4096 * 4;
+
+// Interrupts on, and bit 1, which is always set.
+const USER_RFLAGS: u64 = 0x202;
+
+// No user task.
+const NONE: usize = usize::MAX;
+static USER_TASK: AtomicUsize = AtomicUsize::new(NONE);
+
+// Writes a greeting and exits, copied to USER_CODE_START before it runs. It
+// only uses RIP-relative addresses, so it runs wherever it is copied.
+global_asm!(
+    r#"
+.pushsection .text.user_hello, "ax"
+.global user_hello_start
+.global user_hello_end
+user_hello_start:
+    mov $1, %eax
+    mov $1, %edi
+    lea 1f(%rip), %rsi
+    mov $(2f - 1f), %edx
+    int $0x80
+    mov $2, %eax
+    xor %edi, %edi
+    int $0x80
+    ud2
+1:
+    .ascii "Hello from ring 3!\n"
+2:
+user_hello_end:
+.popsection
+"#,
+    options(att_syntax)
+);
+
+extern "C" {
+    static user_hello_start: u8;
+    static user_hello_end: u8;
+}
+
+fn hello_program() -> &'static [u8] {
+    unsafe {
+        let start = &user_hello_start as *const u8;
+        let len = &user_hello_end as *const u8 as usize - start as usize;
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserError {
+    // Another user task still exists.
+    Busy,
+    OutOfMemory,
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserError::Busy => f.write_str("a user task is already running"),
+            UserError::OutOfMemory => f.write_str("cannot map the program"),
+        }
+    }
+}
+
+// Starts a user task running the built-in greeting program, returning its
+// task id.
+pub fn spawn_hello() -> Result<usize, UserError> {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        // A task that has exited or been killed no longer holds the slot.
+        let previous = USER_TASK.load(Ordering::Relaxed);
+        if previous != NONE && scheduler.task_ids().contains(&previous) {
+            return Err(UserError::Busy);
+        }
+        let code = VirtAddr::new(USER_CODE_START);
+        memory::map_user(code, USER_CODE_LEN, hello_program(), Access::UserReadExecute)
+            .map_err(|_| UserError::OutOfMemory)?;
+        let stack_bottom = VirtAddr::new(USER_STACK_TOP - USER_STACK_LEN);
+        memory::map_user(stack_bottom, USER_STACK_LEN, &[], Access::UserReadWrite)
+            .map_err(|_| UserError::OutOfMemory)?;
+        let id = scheduler.spawn(move || enter(code, VirtAddr::new(USER_STACK_TOP)));
+        USER_TASK.store(id, Ordering::Relaxed);
+        Ok(id)
+    })
+}
+
+// Drops to ring 3 at `entry` with the stack at `stack_top`. The task's
+// kernel stack is left behind for good.
+fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    let (code, data) = gdt::user_selectors();
+    unsafe {
+        asm!(
+            "push {ss}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            "iretq",
+            ss = in(reg) u64::from(data.0),
+            rsp = in(reg) stack_top.as_u64(),
+            rflags = in(reg) USER_RFLAGS,
+            cs = in(reg) u64::from(code.0),
+            rip = in(reg) entry.as_u64(),
+            options(noreturn)
+        );
+    }
+}
+
+// Whether an exception with this saved code selector came from ring 3, in
+// which case it ends the user task rather than the kernel.
+pub fn from_user_mode(code_segment: u64) -> bool {
+    code_segment & 3 == 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use x86_64::instructions::segmentation::{Segment, CS};
+    use crate::log;
+    use crate::task::tests::run_until;
+
+    // The program writes through the gate and then exits through it. Had it
+    // not reached ring 3, or faulted there, it would be killed with a log
+    // line instead of exiting, or never end.
+    #[test_case]
+    fn a_ring_3_task_makes_a_syscall_and_exits() {
+        let id = interrupts::without_interrupts(|| {
+            let id = spawn_hello().unwrap();
+            assert_eq!(spawn_hello(), Err(UserError::Busy));
+            id
+        });
+        run_until(|| !interrupts::without_interrupts(|| SCHEDULER.lock().task_ids().contains(&id)));
+        let killed = format!("User task {} killed", id);
+        assert!(!log::records().iter().any(|record| record.text.starts_with(&killed)));
+        assert_eq!(CS::get_reg().rpl() as u8, 0);
+        // The slot is free again.
+        let again = spawn_hello().unwrap();
+        run_until(|| !interrupts::without_interrupts(|| SCHEDULER.lock().task_ids().contains(&again)));
+    }
+}
@@ -1,10 +1,26 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
+use x86_64::instructions::{interrupts, port::Port};
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+use crate::sync::TicketLock;
+use crate::{console, cp437, framebuffer};
+
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+const SCROLLBACK_LINES: usize = 500;
+
+// Moves the write position back one cell without erasing it.
+const BACKSPACE: u8 = 0x08;
+
+const CRTC_ADDRESS_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CURSOR_START_REGISTER: u8 = 0x0A;
+const CURSOR_END_REGISTER: u8 = 0x0B;
+const CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CURSOR_LOCATION_LOW: u8 = 0x0F;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -28,6 +44,31 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    pub fn from_name(name: &str) -> Option<Color> {
+        let color = match name {
+            "black" => Color::Black,
+            "blue" => Color::Blue,
+            "green" => Color::Green,
+            "cyan" => Color::Cyan,
+            "red" => Color::Red,
+            "magenta" => Color::Magenta,
+            "brown" => Color::Brown,
+            "lightgray" => Color::LightGray,
+            "darkgray" => Color::DarkGray,
+            "lightblue" => Color::LightBlue,
+            "lightgreen" => Color::LightGreen,
+            "lightcyan" => Color::LightCyan,
+            "lightred" => Color::LightRed,
+            "pink" => Color::Pink,
+            "yellow" => Color::Yellow,
+            "white" => Color::White,
+            _ => return None,
+        };
+        Some(color)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct ColorCode(u8);
 
@@ -35,6 +76,10 @@ impl ColorCode {
     fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    fn with_foreground(self, foreground: Color) -> ColorCode {
+        ColorCode(self.0 & 0xF0 | (foreground as u8))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -48,35 +93,103 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+type Line = [ScreenChar; BUFFER_WIDTH];
+
 pub struct Writer {
+    row_position: usize,
     column_position: usize,
     color_code: ColorCode,
+    // What clear_screen resets to; changed by set_color.
+    default_color: ColorCode,
     buffer: &'static mut Buffer,
+    // Lines that scrolled off the top. None until the heap is available.
+    scrollback: Option<VecDeque<Line>>,
+    // How many lines back from the live view is currently shown.
+    scroll_offset: usize,
+    // The live screen, saved while a historical window is displayed.
+    saved_screen: Vec<Line>,
 }
 
 impl Writer {
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+        self.default_color = self.color_code;
+    }
+
+    pub fn enable_scrollback(&mut self) {
+        if self.scrollback.is_none() {
+            self.scrollback = Some(VecDeque::with_capacity(SCROLLBACK_LINES));
+        }
+    }
+
     pub fn write_byte(&mut self, byte: u8) {
+        if self.scroll_offset != 0 {
+            self.restore_live_view();
+        }
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
+            BACKSPACE => {
+                if self.column_position > 0 {
+                    self.column_position -= 1;
                 }
+            }
+            byte => self.write_glyph(byte),
+        }
+    }
 
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
+    // Draws CP437 glyph `byte` at the write position, even one that as a
+    // byte would be a control character.
+    fn write_glyph(&mut self, byte: u8) {
+        if self.scroll_offset != 0 {
+            self.restore_live_view();
+        }
+        if self.column_position >= BUFFER_WIDTH {
+            self.new_line();
+        }
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
+        let row = self.row_position;
+        let col = self.column_position;
+
+        let color_code = self.color_code;
+        self.buffer.chars[row][col].write(ScreenChar {
+            ascii_character: byte,
+            color_code,
+        });
+        self.column_position += 1;
+    }
+
+    fn read_row(&self, row: usize) -> Line {
+        let mut line = [ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        }; BUFFER_WIDTH];
+        for (col, character) in line.iter_mut().enumerate() {
+            *character = self.buffer.chars[row][col].read();
+        }
+        line
+    }
+
+    fn write_row(&mut self, row: usize, line: &Line) {
+        for (col, character) in line.iter().enumerate() {
+            self.buffer.chars[row][col].write(*character);
         }
     }
 
+    // Moves to the next row, scrolling once the bottom row is reached.
     fn new_line(&mut self) {
+        self.column_position = 0;
+        if self.row_position < BUFFER_HEIGHT - 1 {
+            self.row_position += 1;
+            return;
+        }
+
+        let top = self.read_row(0);
+        if let Some(scrollback) = self.scrollback.as_mut() {
+            if scrollback.len() == SCROLLBACK_LINES {
+                scrollback.pop_front();
+            }
+            scrollback.push_back(top);
+        }
         for row in 1..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
                 let character = self.buffer.chars[row][col].read();
@@ -84,7 +197,6 @@ impl Writer {
             }
         }
         self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
     }
 
     fn clear_row(&mut self, row: usize) {
@@ -97,20 +209,154 @@ impl Writer {
         }
     }
 
+    // Characters outside ASCII are drawn as their CP437 glyphs.
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe),
+        for c in s.chars() {
+            match c {
+                '\n' => self.write_byte(b'\n'),
+                '\x08' => self.write_byte(BACKSPACE),
+                c => self.write_glyph(cp437::glyph(c)),
             }
         }
+        self.update_cursor();
     }
 
+    // Blanks the screen with the default color and homes the cursor. A
+    // historical scrollback view is dropped in favor of the (now empty) live
+    // screen; the scrollback itself is kept.
     pub fn clear_screen(&mut self) {
+        self.scroll_offset = 0;
+        self.saved_screen.clear();
+        self.color_code = self.default_color;
         for row in 0..BUFFER_HEIGHT {
             self.clear_row(row);
         }
+        self.row_position = 0;
         self.column_position = 0;
+        self.update_cursor();
+    }
+
+    // Fills `row` with `text`, cut off or padded with blanks to the width of
+    // the screen, for programs that draw the whole screen themselves. A
+    // highlighted row is drawn black on gray. The write position is left
+    // alone.
+    pub fn put_row(&mut self, row: usize, text: &str, highlight: bool) {
+        if self.scroll_offset != 0 {
+            self.restore_live_view();
+        }
+        let color_code = if highlight {
+            ColorCode::new(Color::Black, Color::LightGray)
+        } else {
+            self.color_code
+        };
+        let mut chars = text.chars();
+        for col in 0..BUFFER_WIDTH {
+            let ascii_character = chars.next().map_or(b' ', cp437::glyph);
+            self.buffer.chars[row][col].write(ScreenChar {
+                ascii_character,
+                color_code,
+            });
+        }
+    }
+
+    // Moves the write position, and the cursor with it.
+    pub fn move_to(&mut self, row: usize, col: usize) {
+        self.row_position = row.min(BUFFER_HEIGHT - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+        self.update_cursor();
+    }
+
+    // Linear cell index of the next write. A full row parks the cursor on
+    // its last cell.
+    fn cursor_position(&self) -> u16 {
+        let row = self.row_position;
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        (row * BUFFER_WIDTH + col) as u16
+    }
+
+    pub fn update_cursor(&mut self) {
+        let position = self.cursor_position();
+        write_crtc(CURSOR_LOCATION_LOW, (position & 0xFF) as u8);
+        write_crtc(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+    }
+
+    // `start` and `end` are the first and last scanlines of the cursor
+    // within a character cell (0-15). Nothing changes the cursor's shape yet.
+    #[allow(dead_code)]
+    pub fn enable_cursor(&mut self, start: u8, end: u8) {
+        write_crtc(CURSOR_START_REGISTER, (read_crtc(CURSOR_START_REGISTER) & 0xC0) | start);
+        write_crtc(CURSOR_END_REGISTER, (read_crtc(CURSOR_END_REGISTER) & 0xE0) | end);
+    }
+
+    #[allow(dead_code)]
+    pub fn disable_cursor(&mut self) {
+        write_crtc(CURSOR_START_REGISTER, 0x20);
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let history = self.scrollback.as_ref().map_or(0, |s| s.len());
+        if history == 0 {
+            return;
+        }
+        if self.scroll_offset == 0 {
+            self.saved_screen = (0..BUFFER_HEIGHT).map(|row| self.read_row(row)).collect();
+        }
+        self.scroll_offset = (self.scroll_offset + lines).min(history);
+        self.redraw_history();
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        if self.scroll_offset == 0 {
+            return;
+        }
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+        if self.scroll_offset == 0 {
+            self.restore_live_view();
+        } else {
+            self.redraw_history();
+        }
+    }
+
+    // Shows the window that ends `scroll_offset` lines above the live view,
+    // treating the scrollback followed by the saved screen as one history.
+    fn redraw_history(&mut self) {
+        let scrollback = match self.scrollback.take() {
+            Some(scrollback) => scrollback,
+            None => return,
+        };
+        let first = scrollback.len() - self.scroll_offset;
+        for row in 0..BUFFER_HEIGHT {
+            let index = first + row;
+            let line = if index < scrollback.len() {
+                scrollback[index]
+            } else {
+                self.saved_screen[index - scrollback.len()]
+            };
+            self.write_row(row, &line);
+        }
+        self.scrollback = Some(scrollback);
+    }
+
+    fn restore_live_view(&mut self) {
+        let saved = core::mem::take(&mut self.saved_screen);
+        for (row, line) in saved.iter().enumerate() {
+            self.write_row(row, line);
+        }
+        self.scroll_offset = 0;
+    }
+}
+
+fn write_crtc(register: u8, value: u8) {
+    unsafe {
+        Port::new(CRTC_ADDRESS_PORT).write(register);
+        Port::new(CRTC_DATA_PORT).write(value);
+    }
+}
+
+fn read_crtc(register: u8) -> u8 {
+    unsafe {
+        Port::new(CRTC_ADDRESS_PORT).write(register);
+        Port::new(CRTC_DATA_PORT).read()
     }
 }
 
@@ -122,10 +368,17 @@ impl fmt::Write for Writer {
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    pub static ref WRITER: TicketLock<Writer> = TicketLock::new(Writer {
+        // Start on the bottom row so the bootloader's output scrolls up
+        // rather than being overwritten.
+        row_position: BUFFER_HEIGHT - 1,
         column_position: 0,
         color_code: ColorCode::new(Color::Yellow, Color::Black),
+        default_color: ColorCode::new(Color::Yellow, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        scrollback: None,
+        scroll_offset: 0,
+        saved_screen: Vec::new(),
     });
 }
 
@@ -140,8 +393,175 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+#[macro_export]
+macro_rules! print_colored {
+    ($color:expr, $($arg:tt)*) => ($crate::vga_buffer::_print_colored($color, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println_colored {
+    ($color:expr, $($arg:tt)*) => ($crate::print_colored!($color, "{}\n", format_args!($($arg)*)));
+}
+
+// Runs `f` on the writer with interrupts held off, so an interrupt handler
+// that prints can never find WRITER locked by the code it interrupted. Use
+// this rather than WRITER.lock() outside of interrupt handlers.
+pub fn with_writer<R>(f: impl FnOnce(&mut Writer) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut WRITER.lock()))
+}
+
+// Clears whichever of the text screen and the framebuffer console is
+// displayed.
+pub fn clear_screen() {
+    if framebuffer::is_enabled() {
+        console::clear();
+    } else {
+        with_writer(|writer| writer.clear_screen());
+    }
+}
+
+pub fn set_color(foreground: Color, background: Color) {
+    if framebuffer::is_enabled() {
+        console::set_color(foreground, background);
+    } else {
+        with_writer(|writer| writer.set_color(foreground, background));
+    }
+}
+
+// The print path. With interrupts already disabled we may be in a handler,
+// and if WRITER is held the holder can't run again until we return, so the
+// text is dropped instead of spinning forever.
+fn print_with(f: impl FnOnce(&mut Writer)) {
+    if interrupts::are_enabled() {
+        with_writer(f);
+    } else if let Some(mut writer) = WRITER.try_lock() {
+        f(&mut writer);
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    if framebuffer::is_enabled() {
+        console::print(None, args);
+        return;
+    }
+    print_with(|writer| writer.write_fmt(args).unwrap());
+}
+
+// Prints in `color` on the current background, then restores the color.
+#[doc(hidden)]
+pub fn _print_colored(color: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+    if framebuffer::is_enabled() {
+        console::print(Some(color), args);
+        return;
+    }
+    print_with(|writer| {
+        let previous = writer.color_code;
+        writer.color_code = previous.with_foreground(color);
+        writer.write_fmt(args).unwrap();
+        writer.color_code = previous;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::String;
+
+    fn text(line: &Line) -> String {
+        let text: String = line.iter().map(|c| char::from(c.ascii_character)).collect();
+        String::from(text.trim_end())
+    }
+
+    #[test_case]
+    fn lines_scrolled_off_the_top_are_kept_in_order() {
+        with_writer(|writer| {
+            writer.enable_scrollback();
+            writer.clear_screen();
+            for i in 0..100 {
+                writer.write_string(&format!("line {}\n", i));
+            }
+            // Push the last screenful off the top as well.
+            for _ in 0..BUFFER_HEIGHT - 1 {
+                writer.write_byte(b'\n');
+            }
+            let scrollback = writer.scrollback.as_ref().unwrap();
+            let kept: Vec<String> = scrollback.iter().skip(scrollback.len() - 100).map(text).collect();
+            for (i, line) in kept.iter().enumerate() {
+                assert_eq!(*line, format!("line {}", i));
+            }
+            writer.clear_screen();
+        });
+    }
+
+    #[test_case]
+    fn characters_carry_the_requested_colors() {
+        with_writer(|writer| {
+            let (previous, previous_default) = (writer.color_code, writer.default_color);
+            writer.clear_screen();
+            writer.set_color(Color::LightGreen, Color::Blue);
+            writer.write_byte(b'x');
+            let written = writer.buffer.chars[0][0].read();
+            assert_eq!(written.ascii_character, b'x');
+            // Background in the high nibble, foreground in the low one.
+            assert_eq!(written.color_code, ColorCode(0x1A));
+            writer.color_code = previous;
+            writer.default_color = previous_default;
+            writer.clear_screen();
+        });
+    }
+
+    #[test_case]
+    fn the_hardware_cursor_follows_the_write_position() {
+        with_writer(|writer| {
+            writer.move_to(3, 7);
+            writer.write_string("ab");
+            let position = u16::from(read_crtc(CURSOR_LOCATION_HIGH)) << 8
+                | u16::from(read_crtc(CURSOR_LOCATION_LOW));
+            assert_eq!(position as usize, 3 * BUFFER_WIDTH + 9);
+            writer.clear_screen();
+        });
+    }
+
+    #[test_case]
+    fn clear_blanks_every_cell_and_homes_the_cursor() {
+        with_writer(|writer| {
+            for _ in 0..BUFFER_HEIGHT {
+                writer.write_string("some text on every row\n");
+            }
+            // As left by a colored print.
+            writer.color_code = ColorCode::new(Color::Red, Color::White);
+            writer.write_string("in color");
+            writer.clear_screen();
+            assert_eq!(writer.color_code, writer.default_color);
+            for row in writer.buffer.chars.iter() {
+                for cell in row.iter() {
+                    let cell = cell.read();
+                    assert_eq!(cell.ascii_character, b' ');
+                    assert_eq!(cell.color_code, writer.default_color);
+                }
+            }
+            assert_eq!((writer.row_position, writer.column_position), (0, 0));
+            let position = u16::from(read_crtc(CURSOR_LOCATION_HIGH)) << 8
+                | u16::from(read_crtc(CURSOR_LOCATION_LOW));
+            assert_eq!(position, 0);
+        });
+    }
+
+    #[test_case]
+    fn printing_with_interrupts_disabled_never_waits_for_the_lock() {
+        interrupts::without_interrupts(|| {
+            crate::println!("printed with interrupts off");
+            // As if an interrupt handler printed while the code it
+            // interrupted held the writer.
+            let writer = WRITER.lock();
+            crate::print!("dropped");
+            let row: Line = core::array::from_fn(|col| writer.buffer.chars[writer.row_position - 1][col].read());
+            assert_eq!(text(&row), "printed with interrupts off");
+            assert_eq!(writer.column_position, 0);
+        });
+    }
 }
@@ -0,0 +1,80 @@
+// Reboots the machine if the shell stops responding. The shell pets the
+// watchdog each time round its loop and while waiting for input, and the timer
+// interrupt reboots once TIMEOUT_MS pass without a pet. Off until enabled with
+// the watchdog setting.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{log_error, power, time};
+
+const TIMEOUT_MS: usize = 10_000;
+const TIMEOUT_TICKS: usize = TIMEOUT_MS * time::PIT_FREQUENCY_HZ / 1000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LAST_PET: AtomicUsize = AtomicUsize::new(0);
+
+pub fn pet() {
+    LAST_PET.store(time::uptime_ticks(), Ordering::Relaxed);
+}
+
+pub fn set_enabled(enabled: bool) {
+    // Start the timeout afresh rather than from a pet long ago.
+    pet();
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Runs `f` with the watchdog held off, for commands that block on purpose.
+pub fn paused<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = ENABLED.swap(false, Ordering::Relaxed);
+    let result = f();
+    set_enabled(enabled);
+    result
+}
+
+fn expired(now: usize, last_pet: usize) -> bool {
+    now.wrapping_sub(last_pet) > TIMEOUT_TICKS
+}
+
+// Whether the watchdog should go off at tick `now`. Going off disables it,
+// so it fires once.
+fn fire(now: usize) -> bool {
+    if !ENABLED.load(Ordering::Relaxed) || !expired(now, LAST_PET.load(Ordering::Relaxed)) {
+        return false;
+    }
+    ENABLED.store(false, Ordering::Relaxed);
+    true
+}
+
+// Called from the timer interrupt with the current tick.
+pub fn check(now: usize) {
+    if fire(now) {
+        log_error!("Watchdog: the shell has not responded for {} ms, rebooting", TIMEOUT_MS);
+        power::reboot();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x86_64::instructions::interrupts;
+
+    // The real check() would reboot, so this asks fire() about ticks ahead
+    // of now, with the timer held off so it can't act on them first.
+    #[test_case]
+    fn fires_once_on_a_stalled_loop() {
+        interrupts::without_interrupts(|| {
+            let now = time::uptime_ticks();
+            set_enabled(true);
+            assert!(!fire(now + TIMEOUT_TICKS));
+            assert!(fire(now + TIMEOUT_TICKS + 1));
+            assert!(!fire(now + TIMEOUT_TICKS + 2));
+
+            // A later pet pushes the deadline back.
+            set_enabled(true);
+            LAST_PET.store(now + TIMEOUT_TICKS, Ordering::Relaxed);
+            assert!(!fire(now + TIMEOUT_TICKS + 1));
+            set_enabled(false);
+            assert!(!fire(now + 2 * TIMEOUT_TICKS));
+        });
+    }
+}